@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE TokenSubjects;
+DROP TABLE Tokens;
\ No newline at end of file
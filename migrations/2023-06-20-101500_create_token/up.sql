@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE Tokens (
+    key TEXT PRIMARY KEY,
+    token TEXT NOT NULL,
+    expires_at TIMESTAMP
+);
+
+CREATE INDEX idx_tokens_expires_at ON Tokens (expires_at);
+
+CREATE TABLE TokenSubjects (
+    sub VARCHAR(255) NOT NULL,
+    key TEXT NOT NULL,
+    expires_at TIMESTAMP,
+
+    PRIMARY KEY (sub, key)
+);
@@ -10,7 +10,13 @@ use rauth::{
         grpc::{SessionGrpcService, SessionServer},
     },
     smtp::Smtp,
-    token::{application::TokenApplication, repository::RedisTokenRepository},
+    token::{
+        application::{TokenApplication, TokenRepository},
+        event_bus::RabbitMqTokenBus,
+        grpc::{TokenAdminGrpcService, TokenAdminServer, TokenGrpcService, TokenServer},
+        postgres::PostgresTokenRepository,
+        repository::RedisTokenRepository,
+    },
     user::{
         application::UserApplication,
         event_bus::RabbitMqUserBus,
@@ -32,6 +38,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
         warn!(error = err.to_string(), "processing dotenv file");
     }
 
+    if *config::TOKEN_STORE == config::TOKEN_STORE_POSTGRES {
+        let token_repo = Arc::new(PostgresTokenRepository {
+            pool: config::POSTGRES_POOL.get().await,
+        });
+
+        let sweeper = token_repo.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(*config::TOKEN_SWEEP_INTERVAL);
+            sweeper.run_sweeper(period).await
+        });
+
+        return serve(token_repo).await;
+    }
+
+    let token_repo = Arc::new(RedisTokenRepository {
+        pool: &config::REDIS_POOL,
+    });
+
+    serve(token_repo).await
+}
+
+async fn serve<T>(token_repo: Arc<T>) -> Result<(), Box<dyn Error>>
+where
+    T: 'static + TokenRepository + Sync + Send,
+{
     let metadata_repo = Arc::new(PostgresMetadataRepository {
         pool: config::POSTGRES_POOL.get().await,
     });
@@ -52,9 +83,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
         issuer: &config::EVENT_ISSUER,
     });
 
-    let token_repo = Arc::new(RedisTokenRepository {
-        pool: &config::REDIS_POOL,
+    let token_event_bus = Arc::new(RabbitMqTokenBus {
+        pool: config::RABBITMQ_POOL.get().await,
+        exchange: &config::RABBITMQ_TOKENS_EXCHANGE,
+        issuer: &config::EVENT_ISSUER,
     });
+
     let credentials = if config::SMTP_USERNAME.len() > 0 && config::SMTP_PASSWORD.len() > 0 {
         Some((
             config::SMTP_USERNAME.to_string(),
@@ -74,10 +108,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let token_app = Arc::new(TokenApplication {
         token_repo: token_repo.clone(),
-        timeout: Duration::from_secs(*config::TOKEN_TIMEOUT),
+        event_bus: token_event_bus.clone(),
+        ttl_policy: config::TOKEN_TTL_POLICY.clone(),
+        token_format: *config::TOKEN_FORMAT,
         token_issuer: &config::TOKEN_ISSUER,
-        private_key: &config::JWT_SECRET,
-        public_key: &config::JWT_PUBLIC,
+        keyring: &config::JWT_KEYRING,
+        trusted_issuers: &config::TRUSTED_ISSUERS,
+        secret_key: &config::TOKEN_SECRET_KEY,
     });
 
     let user_app = UserApplication {
@@ -88,6 +125,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         event_bus: user_event_bus.clone(),
         totp_secret_len: *config::TOTP_SECRET_LEN,
         totp_secret_name: &config::TOTP_SECRET_NAME,
+        totp_issuer: &config::TOTP_ISSUER,
+        totp_policy: config::TOTP_POLICY.clone(),
+        recovery_code_count: *config::RECOVERY_CODE_COUNT,
+        recovery_code_len: *config::RECOVERY_CODE_LEN,
+        recovery_code_name: &config::RECOVERY_CODE_NAME,
         pwd_sufix: &config::PWD_SUFIX,
     };
 
@@ -95,21 +137,45 @@ async fn main() -> Result<(), Box<dyn Error>> {
         user_app,
         jwt_header: &config::JWT_HEADER,
         totp_header: &config::TOTP_HEADER,
+        dpop_header: &config::DPOP_HEADER,
     };
 
     let session_app = SessionApplication {
         user_repo: user_repo.clone(),
         secret_repo: secret_repo.clone(),
         token_app: token_app.clone(),
+        lockout_policy: config::LOGIN_LOCKOUT_POLICY.clone(),
         totp_secret_name: &config::TOTP_SECRET_NAME,
+        totp_policy: config::TOTP_POLICY.clone(),
+        recovery_code_name: &config::RECOVERY_CODE_NAME,
         pwd_sufix: &config::PWD_SUFIX,
+        introspection_secret: config::INTROSPECTION_SECRET.as_deref(),
     };
 
     let session_grpc_service = SessionGrpcService {
         session_app,
         jwt_header: &config::JWT_HEADER,
+        refresh_header: &config::REFRESH_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+        addr_header: config::ADDR_HEADER.as_deref(),
+        introspection_header: &config::INTROSPECTION_HEADER,
+    };
+
+    let token_grpc_service = TokenGrpcService {
+        keyring: &config::JWT_KEYRING,
+        jwks_max_age: Duration::from_secs(*config::JWKS_MAX_AGE),
     };
 
+    // the admin service is only exposed if an admin secret has been set
+    let token_admin_grpc_service =
+        config::ADMIN_SECRET
+            .as_deref()
+            .map(|admin_secret| TokenAdminGrpcService {
+                token_app: token_app.clone(),
+                admin_header: &config::ADMIN_HEADER,
+                admin_secret,
+            });
+
     let addr: SocketAddr = config::SERVER_ADDR.parse().unwrap();
     info!(
         address = addr.to_string(),
@@ -119,6 +185,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Server::builder()
         .add_service(UserServer::new(user_grpc_service))
         .add_service(SessionServer::new(session_grpc_service))
+        .add_service(TokenServer::new(token_grpc_service))
+        .add_optional_service(token_admin_grpc_service.map(TokenAdminServer::new))
         .serve(addr)
         .await?;
 
@@ -5,8 +5,21 @@ use actix_web::web::Data;
 use actix_web::{middleware, App, HttpServer};
 use rauth::{
     config,
-    session::rest::SessionRestService,
-    token::{application::TokenApplication, repository::RedisTokenRepository},
+    metadata::repository::PostgresMetadataRepository,
+    secret::repository::PostgresSecretRepository,
+    session::{application::SessionApplication, rest::SessionRestService},
+    smtp::Smtp,
+    token::{
+        application::{TokenApplication, TokenRepository},
+        event_bus::RabbitMqTokenBus,
+        postgres::PostgresTokenRepository,
+        repository::RedisTokenRepository,
+        rest::TokenRestService,
+    },
+    user::{
+        application::UserApplication, event_bus::RabbitMqUserBus,
+        repository::PostgresUserRepository, rest::UserRestService,
+    },
 };
 use std::error::Error;
 use std::sync::Arc;
@@ -21,21 +34,128 @@ async fn main() -> Result<(), Box<dyn Error>> {
         warn!(error = err.to_string(), "processing dotenv file",);
     }
 
+    if *config::TOKEN_STORE == config::TOKEN_STORE_POSTGRES {
+        let token_repo = Arc::new(PostgresTokenRepository {
+            pool: config::POSTGRES_POOL.get().await,
+        });
+
+        let sweeper = token_repo.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(*config::TOKEN_SWEEP_INTERVAL);
+            sweeper.run_sweeper(period).await
+        });
+
+        return serve(token_repo).await;
+    }
+
     let token_repo = Arc::new(RedisTokenRepository {
         pool: &config::REDIS_POOL,
     });
 
-    let token_app = TokenApplication {
+    serve(token_repo).await
+}
+
+async fn serve<T>(token_repo: Arc<T>) -> Result<(), Box<dyn Error>>
+where
+    T: 'static + TokenRepository + Sync + Send,
+{
+    let metadata_repo = Arc::new(PostgresMetadataRepository {
+        pool: config::POSTGRES_POOL.get().await,
+    });
+
+    let secret_repo = Arc::new(PostgresSecretRepository {
+        pool: config::POSTGRES_POOL.get().await,
+        metadata_repo: metadata_repo.clone(),
+    });
+
+    let user_repo = Arc::new(PostgresUserRepository {
+        pool: config::POSTGRES_POOL.get().await,
+        metadata_repo: metadata_repo.clone(),
+    });
+
+    let user_event_bus = Arc::new(RabbitMqUserBus {
+        pool: config::RABBITMQ_POOL.get().await,
+        exchange: &config::RABBITMQ_USERS_EXCHANGE,
+        issuer: &config::EVENT_ISSUER,
+    });
+
+    let token_event_bus = Arc::new(RabbitMqTokenBus {
+        pool: config::RABBITMQ_POOL.get().await,
+        exchange: &config::RABBITMQ_TOKENS_EXCHANGE,
+        issuer: &config::EVENT_ISSUER,
+    });
+
+    let credentials = if !config::SMTP_USERNAME.is_empty() && !config::SMTP_PASSWORD.is_empty() {
+        Some((
+            config::SMTP_USERNAME.to_string(),
+            config::SMTP_PASSWORD.to_string(),
+        ))
+    } else {
+        None
+    };
+
+    let mailer = Smtp::new(
+        &config::SMTP_ORIGIN,
+        &config::SMTP_TEMPLATES,
+        &config::SMTP_TRANSPORT,
+        credentials,
+    )?
+    .with_issuer(&config::SMTP_ISSUER);
+
+    let token_app = Arc::new(TokenApplication {
         token_repo: token_repo.clone(),
-        timeout: Duration::from_secs(*config::TOKEN_TIMEOUT),
+        event_bus: token_event_bus.clone(),
+        ttl_policy: config::TOKEN_TTL_POLICY.clone(),
+        token_format: *config::TOKEN_FORMAT,
         token_issuer: &config::TOKEN_ISSUER,
-        private_key: &config::JWT_SECRET,
-        public_key: &config::JWT_PUBLIC,
-    };
+        keyring: &config::JWT_KEYRING,
+        trusted_issuers: &config::TRUSTED_ISSUERS,
+        secret_key: &config::TOKEN_SECRET_KEY,
+    });
+
+    let user_server = Arc::new(UserRestService {
+        user_app: UserApplication {
+            user_repo: user_repo.clone(),
+            secret_repo: secret_repo.clone(),
+            token_app: token_app.clone(),
+            mailer: Arc::new(mailer),
+            event_bus: user_event_bus.clone(),
+            totp_secret_len: *config::TOTP_SECRET_LEN,
+            totp_secret_name: &config::TOTP_SECRET_NAME,
+            totp_issuer: &config::TOTP_ISSUER,
+            totp_policy: config::TOTP_POLICY.clone(),
+            recovery_code_count: *config::RECOVERY_CODE_COUNT,
+            recovery_code_len: *config::RECOVERY_CODE_LEN,
+            recovery_code_name: &config::RECOVERY_CODE_NAME,
+            pwd_sufix: &config::PWD_SUFIX,
+        },
+        jwt_header: &config::JWT_HEADER,
+        totp_header: &config::TOTP_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+    });
 
     let session_server = Arc::new(SessionRestService {
-        token_app,
+        session_app: SessionApplication {
+            user_repo: user_repo.clone(),
+            secret_repo: secret_repo.clone(),
+            token_app: token_app.clone(),
+            lockout_policy: config::LOGIN_LOCKOUT_POLICY.clone(),
+            totp_secret_name: &config::TOTP_SECRET_NAME,
+            totp_policy: config::TOTP_POLICY.clone(),
+            recovery_code_name: &config::RECOVERY_CODE_NAME,
+            pwd_sufix: &config::PWD_SUFIX,
+            introspection_secret: config::INTROSPECTION_SECRET.as_deref(),
+        },
         jwt_header: &config::JWT_HEADER,
+        refresh_header: &config::REFRESH_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+        addr_header: config::ADDR_HEADER.as_deref(),
+        introspection_header: &config::INTROSPECTION_HEADER,
+    });
+
+    let token_server = Arc::new(TokenRestService {
+        keyring: &config::JWT_KEYRING,
+        jwks_max_age: Duration::from_secs(*config::JWKS_MAX_AGE),
     });
 
     info!(
@@ -46,8 +166,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
+            .app_data(Data::new(user_server.clone()))
+            .configure(user_server.router())
             .app_data(Data::new(session_server.clone()))
             .configure(session_server.router())
+            .app_data(Data::new(token_server.clone()))
+            .configure(token_server.router())
     })
     .bind(&*config::SERVER_ADDR)?
     .run()
@@ -0,0 +1,244 @@
+//! Boots the full gRPC and REST API with in-memory backends only, so no external service is required.
+//! All the data gets lost on restart and the emails, instead of being delivered, are readable from the
+//! `/emails` endpoint of the REST server.
+
+#[macro_use]
+extern crate tracing;
+
+use actix_web::web::Data;
+use actix_web::{middleware, App, HttpServer};
+use jsonwebtoken::Algorithm;
+use lazy_static::lazy_static;
+use openssl::{
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::PKey,
+};
+use rauth::{
+    config,
+    mailbox::{rest::MailboxRestService, Mailbox},
+    metadata::memory::InMemoryMetadataRepository,
+    secret::memory::InMemorySecretRepository,
+    session::{
+        application::SessionApplication,
+        grpc::{SessionGrpcService, SessionServer},
+        rest::SessionRestService,
+    },
+    token::{
+        application::TokenApplication,
+        grpc::{TokenAdminGrpcService, TokenAdminServer, TokenGrpcService, TokenServer},
+        keyring::{self, KeyRing},
+        memory::{InMemoryTokenBus, InMemoryTokenRepository},
+        rest::TokenRestService,
+    },
+    user::{
+        application::UserApplication,
+        grpc::{UserGrpcService, UserServer},
+        memory::{InMemoryEventBus, InMemoryUserRepository},
+        rest::UserRestService,
+    },
+};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{error::Error, net::SocketAddr};
+use tonic::transport::Server;
+
+const DEFAULT_REST_ADDR: &str = "127.0.0.1:8001";
+const DEFAULT_PWD_SUFIX: &str = "::standalone";
+const DEFAULT_TOKEN_ISSUER: &str = "rauth";
+
+const ENV_REST_ADDR: &str = "REST_ADDR";
+const ENV_PWD_SUFIX: &str = "PWD_SUFIX";
+const ENV_TOKEN_ISSUER: &str = "TOKEN_ISSUER";
+
+lazy_static! {
+    static ref REST_ADDR: String =
+        env::var(ENV_REST_ADDR).unwrap_or_else(|_| DEFAULT_REST_ADDR.to_string());
+    static ref PWD_SUFIX: String =
+        env::var(ENV_PWD_SUFIX).unwrap_or_else(|_| DEFAULT_PWD_SUFIX.to_string());
+    static ref TOKEN_ISSUER: String =
+        env::var(ENV_TOKEN_ISSUER).unwrap_or_else(|_| DEFAULT_TOKEN_ISSUER.to_string());
+    static ref KEYRING: KeyRing = {
+        // an ephemeral key is generated on each boot, so tokens do not survive restarts either
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let private = pkey.private_key_to_pem_pkcs8().unwrap();
+        let public = pkey.public_key_to_pem().unwrap();
+        KeyRing::new(&keyring::default_kid(&public), Algorithm::ES256, &private, &public)
+    };
+    static ref SECRET_KEY: Vec<u8> = rand::random::<[u8; 32]>().to_vec();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let subscriber = tracing_subscriber::FmtSubscriber::new();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    if let Err(err) = dotenv::dotenv() {
+        warn!(error = err.to_string(), "processing dotenv file");
+    }
+
+    let metadata_repo = Arc::new(InMemoryMetadataRepository::default());
+    let secret_repo = Arc::new(InMemorySecretRepository::new(metadata_repo.clone()));
+    let user_repo = Arc::new(InMemoryUserRepository::new(metadata_repo.clone()));
+    let user_event_bus = Arc::new(InMemoryEventBus::default());
+    let token_repo = Arc::new(InMemoryTokenRepository::default());
+    let token_event_bus = Arc::new(InMemoryTokenBus::default());
+    let mailbox = Arc::new(Mailbox::default());
+
+    let token_app = Arc::new(TokenApplication {
+        token_repo: token_repo.clone(),
+        event_bus: token_event_bus.clone(),
+        ttl_policy: config::TOKEN_TTL_POLICY.clone(),
+        token_format: *config::TOKEN_FORMAT,
+        token_issuer: &TOKEN_ISSUER,
+        keyring: &KEYRING,
+        trusted_issuers: &config::TRUSTED_ISSUERS,
+        secret_key: &SECRET_KEY,
+    });
+
+    let user_app = UserApplication {
+        user_repo: user_repo.clone(),
+        secret_repo: secret_repo.clone(),
+        token_app: token_app.clone(),
+        mailer: mailbox.clone(),
+        event_bus: user_event_bus.clone(),
+        totp_secret_len: *config::TOTP_SECRET_LEN,
+        totp_secret_name: &config::TOTP_SECRET_NAME,
+        totp_issuer: &config::TOTP_ISSUER,
+        totp_policy: config::TOTP_POLICY.clone(),
+        recovery_code_count: *config::RECOVERY_CODE_COUNT,
+        recovery_code_len: *config::RECOVERY_CODE_LEN,
+        recovery_code_name: &config::RECOVERY_CODE_NAME,
+        pwd_sufix: &PWD_SUFIX,
+    };
+
+    let user_grpc_service = UserGrpcService {
+        user_app,
+        jwt_header: &config::JWT_HEADER,
+        totp_header: &config::TOTP_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+    };
+
+    let session_app = SessionApplication {
+        user_repo: user_repo.clone(),
+        secret_repo: secret_repo.clone(),
+        token_app: token_app.clone(),
+        lockout_policy: config::LOGIN_LOCKOUT_POLICY.clone(),
+        totp_secret_name: &config::TOTP_SECRET_NAME,
+        totp_policy: config::TOTP_POLICY.clone(),
+        recovery_code_name: &config::RECOVERY_CODE_NAME,
+        pwd_sufix: &PWD_SUFIX,
+        introspection_secret: config::INTROSPECTION_SECRET.as_deref(),
+    };
+
+    let session_grpc_service = SessionGrpcService {
+        session_app,
+        jwt_header: &config::JWT_HEADER,
+        refresh_header: &config::REFRESH_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+        addr_header: config::ADDR_HEADER.as_deref(),
+        introspection_header: &config::INTROSPECTION_HEADER,
+    };
+
+    let token_grpc_service = TokenGrpcService {
+        keyring: &KEYRING,
+        jwks_max_age: Duration::from_secs(*config::JWKS_MAX_AGE),
+    };
+
+    // the admin service is only exposed if an admin secret has been set
+    let token_admin_grpc_service =
+        config::ADMIN_SECRET
+            .as_deref()
+            .map(|admin_secret| TokenAdminGrpcService {
+                token_app: token_app.clone(),
+                admin_header: &config::ADMIN_HEADER,
+                admin_secret,
+            });
+
+    let user_server = Arc::new(UserRestService {
+        user_app: UserApplication {
+            user_repo: user_repo.clone(),
+            secret_repo: secret_repo.clone(),
+            token_app: token_app.clone(),
+            mailer: mailbox.clone(),
+            event_bus: user_event_bus.clone(),
+            totp_secret_len: *config::TOTP_SECRET_LEN,
+            totp_secret_name: &config::TOTP_SECRET_NAME,
+            totp_issuer: &config::TOTP_ISSUER,
+            totp_policy: config::TOTP_POLICY.clone(),
+            recovery_code_count: *config::RECOVERY_CODE_COUNT,
+            recovery_code_len: *config::RECOVERY_CODE_LEN,
+            recovery_code_name: &config::RECOVERY_CODE_NAME,
+            pwd_sufix: &PWD_SUFIX,
+        },
+        jwt_header: &config::JWT_HEADER,
+        totp_header: &config::TOTP_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+    });
+
+    let session_server = Arc::new(SessionRestService {
+        session_app: SessionApplication {
+            user_repo: user_repo.clone(),
+            secret_repo: secret_repo.clone(),
+            token_app: token_app.clone(),
+            lockout_policy: config::LOGIN_LOCKOUT_POLICY.clone(),
+            totp_secret_name: &config::TOTP_SECRET_NAME,
+            totp_policy: config::TOTP_POLICY.clone(),
+            recovery_code_name: &config::RECOVERY_CODE_NAME,
+            pwd_sufix: &PWD_SUFIX,
+            introspection_secret: config::INTROSPECTION_SECRET.as_deref(),
+        },
+        jwt_header: &config::JWT_HEADER,
+        refresh_header: &config::REFRESH_HEADER,
+        dpop_header: &config::DPOP_HEADER,
+        addr_header: config::ADDR_HEADER.as_deref(),
+        introspection_header: &config::INTROSPECTION_HEADER,
+    });
+
+    let token_server = Arc::new(TokenRestService {
+        keyring: &KEYRING,
+        jwks_max_age: Duration::from_secs(*config::JWKS_MAX_AGE),
+    });
+
+    let mailbox_server = Arc::new(MailboxRestService {
+        mailbox: mailbox.clone(),
+    });
+
+    let grpc_addr: SocketAddr = config::SERVER_ADDR.parse().unwrap();
+    info!(
+        grpc_address = grpc_addr.to_string(),
+        rest_address = *REST_ADDR,
+        "standalone server ready to accept connections"
+    );
+
+    let grpc_server = Server::builder()
+        .add_service(UserServer::new(user_grpc_service))
+        .add_service(SessionServer::new(session_grpc_service))
+        .add_service(TokenServer::new(token_grpc_service))
+        .add_optional_service(token_admin_grpc_service.map(TokenAdminServer::new))
+        .serve(grpc_addr);
+
+    let rest_server = HttpServer::new(move || {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .app_data(Data::new(user_server.clone()))
+            .configure(user_server.router())
+            .app_data(Data::new(session_server.clone()))
+            .configure(session_server.router())
+            .app_data(Data::new(token_server.clone()))
+            .configure(token_server.router())
+            .app_data(Data::new(mailbox_server.clone()))
+            .configure(mailbox_server.router())
+    })
+    .bind(&*REST_ADDR)?
+    .run();
+
+    tokio::try_join!(
+        async { grpc_server.await.map_err(Box::<dyn Error>::from) },
+        async { rest_server.await.map_err(Box::<dyn Error>::from) },
+    )?;
+
+    Ok(())
+}
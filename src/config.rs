@@ -1,11 +1,28 @@
+use crate::crypto;
+use crate::session::domain::LockoutPolicy;
+use crate::token::domain::{TokenFormat, TtlPolicy};
+use crate::token::keyring::{self, JwkSet, KeyRing, TrustedIssuers};
+use crate::user::domain::TotpPolicy;
+#[cfg(any(feature = "postgres", feature = "rabbitmq"))]
 use async_once::AsyncOnce;
 use base64::{engine::general_purpose, Engine as _};
+#[cfg(feature = "rabbitmq")]
 use deadpool_lapin::{Config, Pool, Runtime};
+use jsonwebtoken::Algorithm;
+#[cfg(feature = "rabbitmq")]
 use lapin::{options, types::FieldTable, ExchangeKind};
 use lazy_static::lazy_static;
+use libreauth::hash::HashFunction;
+#[cfg(feature = "redis-cache")]
 use reool::RedisPool;
+#[cfg(feature = "postgres")]
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "redis-cache")]
 use tokio::runtime::Handle;
 
 const DEFAULT_ADDR: &str = "127.0.0.1";
@@ -13,22 +30,67 @@ const DEFAULT_PORT: &str = "8000";
 const DEFAULT_TEMPLATES_PATH: &str = "/etc/rauth/smtp/templates/*.html";
 const DEFAULT_JWT_HEADER: &str = "authorization";
 const DEFAULT_TOTP_HEADER: &str = "x-totp-secret";
+const DEFAULT_REFRESH_HEADER: &str = "x-refresh-token";
+const DEFAULT_ADMIN_HEADER: &str = "x-admin-secret";
+const DEFAULT_INTROSPECTION_HEADER: &str = "x-introspection-secret";
+const DEFAULT_DPOP_HEADER: &str = "dpop";
 const DEFAULT_TOKEN_TIMEOUT: u64 = 7200;
+const DEFAULT_REFRESH_TOKEN_TIMEOUT: u64 = 2592000;
+const DEFAULT_JWKS_MAX_AGE: u64 = 3600;
+const DEFAULT_TOKEN_SWEEP_INTERVAL: u64 = 300;
+const DEFAULT_LOGIN_MAX_ATTEMPTS: u64 = 5;
+const DEFAULT_LOGIN_LOCKOUT: u64 = 60;
+const DEFAULT_LOGIN_MAX_LOCKOUT: u64 = 3600;
+const DEFAULT_JWT_ALGORITHM: &str = "ES256";
+#[cfg(any(feature = "postgres", feature = "redis-cache", feature = "rabbitmq"))]
 const DEFAULT_POOL_SIZE: u32 = 10;
+#[cfg(feature = "rabbitmq")]
+const DEFAULT_RABBITMQ_TOKENS_EXCHANGE: &str = "tokens";
 const DEFAULT_TOTP_SECRET_LEN: usize = 32_usize;
 const DEFAULT_TOTP_SECRET_NAME: &str = "totp";
+const DEFAULT_TOTP_ISSUER: &str = "rauth";
+const DEFAULT_TOTP_DIGITS: usize = 6;
+const DEFAULT_TOTP_PERIOD: u64 = 30;
+const DEFAULT_TOTP_DRIFT: u64 = 0;
+const DEFAULT_RECOVERY_CODE_COUNT: usize = 10;
+const DEFAULT_RECOVERY_CODE_LEN: usize = 10;
+const DEFAULT_RECOVERY_CODE_NAME: &str = "recovery";
 
 const ENV_SERVICE_PORT: &str = "SERVICE_PORT";
 const ENV_SERVICE_ADDR: &str = "SERVICE_ADDR";
+#[cfg(feature = "postgres")]
 const ENV_POSTGRES_DSN: &str = "POSTGRES_DSN";
+#[cfg(feature = "postgres")]
 const ENV_POSTGRES_POOL: &str = "POSTGRES_POOL";
 const ENV_JWT_SECRET: &str = "JWT_SECRET";
 const ENV_JWT_PUBLIC: &str = "JWT_PUBLIC";
+const ENV_JWT_KID: &str = "JWT_KID";
+const ENV_JWT_ALGORITHM: &str = "JWT_ALGORITHM";
+const ENV_JWT_ALGORITHMS: &str = "JWT_ALGORITHMS";
+const ENV_JWT_RETIRED_KEYS: &str = "JWT_RETIRED_KEYS";
+const ENV_TRUSTED_ISSUERS: &str = "TRUSTED_ISSUERS";
+const ENV_TRUSTED_ISSUERS_JWKS: &str = "TRUSTED_ISSUERS_JWKS";
+const ENV_TRUSTED_ISSUERS_STATELESS: &str = "TRUSTED_ISSUERS_STATELESS";
+const ENV_JWKS_MAX_AGE: &str = "JWKS_MAX_AGE";
 const ENV_JWT_HEADER: &str = "JWT_HEADER";
 const ENV_TOTP_HEADER: &str = "TOTP_HEADER";
+const ENV_REFRESH_HEADER: &str = "REFRESH_HEADER";
+const ENV_ADMIN_HEADER: &str = "ADMIN_HEADER";
+const ENV_ADMIN_SECRET: &str = "ADMIN_SECRET";
+const ENV_INTROSPECTION_HEADER: &str = "INTROSPECTION_HEADER";
+const ENV_INTROSPECTION_SECRET: &str = "INTROSPECTION_SECRET";
+const ENV_DPOP_HEADER: &str = "DPOP_HEADER";
+const ENV_ADDR_HEADER: &str = "ADDR_HEADER";
+#[cfg(feature = "redis-cache")]
 const ENV_REDIS_URL: &str = "REDIS_URL";
+#[cfg(feature = "redis-cache")]
 const ENV_REDIS_POOL: &str = "REDIS_POOL";
 const ENV_TOKEN_TIMEOUT: &str = "TOKEN_TIMEOUT";
+const ENV_REFRESH_TOKEN_TIMEOUT: &str = "REFRESH_TOKEN_TIMEOUT";
+const ENV_SESSION_TOKEN_TIMEOUT: &str = "SESSION_TOKEN_TIMEOUT";
+const ENV_VERIFICATION_TOKEN_TIMEOUT: &str = "VERIFICATION_TOKEN_TIMEOUT";
+const ENV_RESET_TOKEN_TIMEOUT: &str = "RESET_TOKEN_TIMEOUT";
+const ENV_SESSION_IDLE_TIMEOUT: &str = "SESSION_IDLE_TIMEOUT";
 const ENV_SMTP_TRANSPORT: &str = "SMTP_TRANSPORT";
 const ENV_SMTP_USERNAME: &str = "SMTP_USERNAME";
 const ENV_SMTP_PASSWORD: &str = "SMTP_PASSWORD";
@@ -36,13 +98,44 @@ const ENV_SMTP_ISSUER: &str = "SMTP_ISSUER";
 const ENV_SMTP_TEMPLATES: &str = "SMTP_TEMPLATES";
 const ENV_SMTP_ORIGIN: &str = "SMTP_ORIGIN";
 const ENV_PWD_SUFIX: &str = "PWD_SUFIX";
+#[cfg(feature = "rabbitmq")]
 const ENV_RABBITMQ_USERS_EXCHANGE: &str = "RABBITMQ_USERS_EXCHANGE";
+#[cfg(feature = "rabbitmq")]
+const ENV_RABBITMQ_TOKENS_EXCHANGE: &str = "RABBITMQ_TOKENS_EXCHANGE";
+#[cfg(feature = "rabbitmq")]
 const ENV_RABBITMQ_URL: &str = "RABBITMQ_URL";
+#[cfg(feature = "rabbitmq")]
 const ENV_RABBITMQ_POOL: &str = "RABBITMQ_POOL";
+#[cfg(feature = "rabbitmq")]
 const ENV_EVENT_ISSUER: &str = "EVENT_ISSUER";
 const ENV_TOTP_SECRET_LEN: &str = "TOTP_SECRET_LEN";
 const ENV_TOTP_SECRET_NAME: &str = "TOTP_SECRET_NAME";
+const ENV_TOTP_ISSUER: &str = "TOTP_ISSUER";
+const ENV_TOTP_DIGITS: &str = "TOTP_DIGITS";
+const ENV_TOTP_PERIOD: &str = "TOTP_PERIOD";
+const ENV_TOTP_HASH: &str = "TOTP_HASH";
+const ENV_TOTP_DRIFT: &str = "TOTP_DRIFT";
+const ENV_RECOVERY_CODE_COUNT: &str = "RECOVERY_CODE_COUNT";
+const ENV_RECOVERY_CODE_LEN: &str = "RECOVERY_CODE_LEN";
+const ENV_RECOVERY_CODE_NAME: &str = "RECOVERY_CODE_NAME";
 const ENV_TOKEN_ISSUER: &str = "TOKEN_ISSUER";
+const ENV_TOKEN_STORE: &str = "TOKEN_STORE";
+const ENV_TOKEN_SWEEP_INTERVAL: &str = "TOKEN_SWEEP_INTERVAL";
+const ENV_LOGIN_MAX_ATTEMPTS: &str = "LOGIN_MAX_ATTEMPTS";
+const ENV_LOGIN_LOCKOUT: &str = "LOGIN_LOCKOUT";
+const ENV_LOGIN_MAX_LOCKOUT: &str = "LOGIN_MAX_LOCKOUT";
+const ENV_TOKEN_FORMAT: &str = "TOKEN_FORMAT";
+const ENV_TOKEN_SECRET_KEY: &str = "TOKEN_SECRET_KEY";
+
+/// Stores the tokens in Redis.
+pub const TOKEN_STORE_REDIS: &str = "redis";
+/// Stores the tokens in the Postgres database, next to any other entity.
+pub const TOKEN_STORE_POSTGRES: &str = "postgres";
+
+/// Hands out the signed JWT to clients.
+const TOKEN_FORMAT_JWT: &str = "jwt";
+/// Hands out an opaque handle to clients, keeping the JWT on the server side.
+const TOKEN_FORMAT_OPAQUE: &str = "opaque";
 
 lazy_static! {
     pub static ref SERVER_ADDR: String = {
@@ -53,16 +146,144 @@ lazy_static! {
     pub static ref TOKEN_TIMEOUT: u64 = env::var(ENV_TOKEN_TIMEOUT)
         .map(|timeout| timeout.parse().unwrap())
         .unwrap_or(DEFAULT_TOKEN_TIMEOUT);
+    pub static ref REFRESH_TOKEN_TIMEOUT: u64 = env::var(ENV_REFRESH_TOKEN_TIMEOUT)
+        .map(|timeout| timeout.parse().unwrap())
+        .unwrap_or(DEFAULT_REFRESH_TOKEN_TIMEOUT);
+    pub static ref TOKEN_TTL_POLICY: TtlPolicy = {
+        // any kind of token without a specific timeout falls back to the general one
+        let timeout_of = |env_name: &str| {
+            env::var(env_name)
+                .map(|timeout| timeout.parse().unwrap())
+                .unwrap_or(*TOKEN_TIMEOUT)
+        };
+
+        TtlPolicy {
+            session: Duration::from_secs(timeout_of(ENV_SESSION_TOKEN_TIMEOUT)),
+            verification: Duration::from_secs(timeout_of(ENV_VERIFICATION_TOKEN_TIMEOUT)),
+            reset: Duration::from_secs(timeout_of(ENV_RESET_TOKEN_TIMEOUT)),
+            refresh: Duration::from_secs(*REFRESH_TOKEN_TIMEOUT),
+            session_idle: env::var(ENV_SESSION_IDLE_TIMEOUT)
+                .ok()
+                .map(|timeout| Duration::from_secs(timeout.parse().unwrap())),
+        }
+    };
     pub static ref JWT_SECRET: Vec<u8> = env::var(ENV_JWT_SECRET)
         .map(|secret| general_purpose::STANDARD.decode(secret).unwrap())
         .expect("jwt secret must be set");
-    pub static ref JWT_PUBLIC: Vec<u8> = env::var(ENV_JWT_PUBLIC)
-        .map(|secret| general_purpose::STANDARD.decode(secret).unwrap())
-        .expect("jwt public key must be set");
+    pub static ref JWT_ALGORITHM: Algorithm = env::var(ENV_JWT_ALGORITHM)
+        .map(|alg| Algorithm::from_str(&alg).expect("jwt algorithm must be supported"))
+        .unwrap_or_else(|_| Algorithm::from_str(DEFAULT_JWT_ALGORITHM).unwrap());
+    pub static ref JWT_PUBLIC: Vec<u8> = match env::var(ENV_JWT_PUBLIC) {
+        Ok(public) => general_purpose::STANDARD.decode(public).unwrap(),
+        // symmetric algorithms verify with the very same secret they sign with
+        Err(_) if crypto::is_symmetric(*JWT_ALGORITHM) => JWT_SECRET.clone(),
+        Err(_) => panic!("jwt public key must be set"),
+    };
+    pub static ref JWT_KID: String = match env::var(ENV_JWT_KID) {
+        Ok(kid) => kid,
+        // the default kid must not disclose anything about a shared secret
+        Err(_) if crypto::is_symmetric(*JWT_ALGORITHM) => panic!("jwt kid must be set"),
+        Err(_) => keyring::default_kid(&JWT_PUBLIC),
+    };
+    pub static ref JWT_KEYRING: KeyRing = {
+        // a retired key must keep verifying tokens for as long as any of them may still be alive
+        let lifetime = TOKEN_TTL_POLICY.max();
+        let keyring = KeyRing::new(&JWT_KID, *JWT_ALGORITHM, &JWT_SECRET, &JWT_PUBLIC);
+
+        let keyring = env::var(ENV_JWT_RETIRED_KEYS)
+            .unwrap_or_default()
+            .split(',')
+            .filter(|entry| !entry.is_empty())
+            .fold(keyring, |keyring, entry| {
+                let mut parts = entry.splitn(4, ':');
+                let (Some(kid), Some(public), Some(retired_at)) = (parts.next(), parts.next(), parts.next()) else {
+                    panic!("retired keys must be formatted as kid:public:retired_at[:algorithm]");
+                };
+
+                let algorithm = parts
+                    .next()
+                    .map(|alg| Algorithm::from_str(alg).expect("jwt algorithm must be supported"))
+                    .unwrap_or(*JWT_ALGORITHM);
+
+                let public = general_purpose::STANDARD.decode(public).unwrap();
+                let retired_at = SystemTime::UNIX_EPOCH + Duration::from_secs(retired_at.parse().unwrap());
+                keyring.with_retired_key(kid, algorithm, &public, retired_at + lifetime)
+            });
+
+        match env::var(ENV_JWT_ALGORITHMS) {
+            Ok(algorithms) => {
+                let algorithms: Vec<Algorithm> = algorithms
+                    .split(',')
+                    .map(|alg| Algorithm::from_str(alg).expect("jwt algorithm must be supported"))
+                    .collect();
+
+                keyring.with_algorithms(&algorithms)
+            }
+            Err(_) => keyring,
+        }
+    };
+    pub static ref TRUSTED_ISSUERS: TrustedIssuers = {
+        let trusted = env::var(ENV_TRUSTED_ISSUERS)
+            .unwrap_or_default()
+            .split(',')
+            .filter(|entry| !entry.is_empty())
+            .fold(TrustedIssuers::default(), |trusted, entry| {
+                let mut parts = entry.splitn(4, ':');
+                let (Some(iss), Some(kid), Some(public)) = (parts.next(), parts.next(), parts.next()) else {
+                    panic!("trusted issuers must be formatted as iss:kid:public[:algorithm]");
+                };
+
+                let algorithm = parts
+                    .next()
+                    .map(|alg| Algorithm::from_str(alg).expect("jwt algorithm must be supported"))
+                    .unwrap_or(*JWT_ALGORITHM);
+
+                if crypto::is_symmetric(algorithm) {
+                    panic!("trusted issuers must not share a secret");
+                }
+
+                let public = general_purpose::STANDARD.decode(public).unwrap();
+                trusted.with_key(iss, kid, algorithm, &public)
+            });
+
+        // the key sets are those published by each issuer, mapped by the issuer's name
+        let trusted = match env::var(ENV_TRUSTED_ISSUERS_JWKS) {
+            Ok(path) => {
+                let content = fs::read_to_string(path).expect("trusted issuers jwks must be readable");
+                let jwks: HashMap<String, JwkSet> = serde_json::from_str(&content)
+                    .expect("trusted issuers jwks must map issuers to key sets");
+
+                jwks.iter().fold(trusted, |trusted, (iss, jwks)| {
+                    trusted.with_jwks(iss, jwks).expect("trusted issuers jwks must be supported")
+                })
+            }
+            Err(_) => trusted,
+        };
+
+        match env::var(ENV_TRUSTED_ISSUERS_STATELESS).as_deref() {
+            Ok("true") => trusted.without_existence_check(),
+            Ok("false") | Err(_) => trusted,
+            Ok(_) => panic!("trusted issuers stateless must be either true or false"),
+        }
+    };
+    pub static ref JWKS_MAX_AGE: u64 = env::var(ENV_JWKS_MAX_AGE)
+        .map(|max_age| max_age.parse().unwrap())
+        .unwrap_or(DEFAULT_JWKS_MAX_AGE);
     pub static ref JWT_HEADER: String =
         env::var(ENV_JWT_HEADER).unwrap_or_else(|_| DEFAULT_JWT_HEADER.to_string());
     pub static ref TOTP_HEADER: String =
         env::var(ENV_TOTP_HEADER).unwrap_or_else(|_| DEFAULT_TOTP_HEADER.to_string());
+    pub static ref REFRESH_HEADER: String =
+        env::var(ENV_REFRESH_HEADER).unwrap_or_else(|_| DEFAULT_REFRESH_HEADER.to_string());
+    pub static ref ADMIN_HEADER: String =
+        env::var(ENV_ADMIN_HEADER).unwrap_or_else(|_| DEFAULT_ADMIN_HEADER.to_string());
+    pub static ref ADMIN_SECRET: Option<String> = env::var(ENV_ADMIN_SECRET).ok();
+    pub static ref INTROSPECTION_HEADER: String = env::var(ENV_INTROSPECTION_HEADER)
+        .unwrap_or_else(|_| DEFAULT_INTROSPECTION_HEADER.to_string());
+    pub static ref INTROSPECTION_SECRET: Option<String> = env::var(ENV_INTROSPECTION_SECRET).ok();
+    pub static ref DPOP_HEADER: String =
+        env::var(ENV_DPOP_HEADER).unwrap_or_else(|_| DEFAULT_DPOP_HEADER.to_string());
+    pub static ref ADDR_HEADER: Option<String> = env::var(ENV_ADDR_HEADER).ok();
     pub static ref SMTP_TRANSPORT: String =
         env::var(ENV_SMTP_TRANSPORT).expect("smtp transport must be set");
     pub static ref SMTP_USERNAME: String = env::var(ENV_SMTP_USERNAME).unwrap_or_default();
@@ -74,6 +295,101 @@ lazy_static! {
     pub static ref SMTP_TEMPLATES: String =
         env::var(ENV_SMTP_TEMPLATES).unwrap_or_else(|_| DEFAULT_TEMPLATES_PATH.to_string());
     pub static ref PWD_SUFIX: String = env::var(ENV_PWD_SUFIX).expect("password sufix must be set");
+    pub static ref TOTP_SECRET_LEN: usize = env::var(ENV_TOTP_SECRET_LEN)
+        .map(|len| len.parse().unwrap())
+        .unwrap_or_else(|_| DEFAULT_TOTP_SECRET_LEN);
+    pub static ref TOTP_SECRET_NAME: String =
+        env::var(ENV_TOTP_SECRET_NAME).unwrap_or_else(|_| DEFAULT_TOTP_SECRET_NAME.to_string());
+    pub static ref TOTP_ISSUER: String =
+        env::var(ENV_TOTP_ISSUER).unwrap_or_else(|_| DEFAULT_TOTP_ISSUER.to_string());
+    pub static ref TOTP_POLICY: TotpPolicy = TotpPolicy {
+        digits: match env::var(ENV_TOTP_DIGITS).map(|digits| digits.parse().unwrap()) {
+            Err(_) => DEFAULT_TOTP_DIGITS,
+            Ok(digits) if (6..=10).contains(&digits) => digits,
+            Ok(_) => panic!("totp digits must be between 6 and 10"),
+        },
+        period: match env::var(ENV_TOTP_PERIOD).map(|period| period.parse().unwrap()) {
+            Err(_) => DEFAULT_TOTP_PERIOD,
+            Ok(period) if (1..=u32::MAX as u64).contains(&period) => period,
+            Ok(_) => panic!("totp period must be between 1 and {} seconds", u32::MAX),
+        },
+        hash_function: match env::var(ENV_TOTP_HASH).map(|hash| hash.to_uppercase()) {
+            Err(_) => HashFunction::Sha256,
+            Ok(hash) if hash == "SHA1" => HashFunction::Sha1,
+            Ok(hash) if hash == "SHA256" => HashFunction::Sha256,
+            Ok(hash) if hash == "SHA512" => HashFunction::Sha512,
+            Ok(_) => panic!("totp hash must be either SHA1, SHA256 or SHA512"),
+        },
+        drift: env::var(ENV_TOTP_DRIFT)
+            .map(|drift| drift.parse().unwrap())
+            .unwrap_or(DEFAULT_TOTP_DRIFT),
+    };
+    pub static ref RECOVERY_CODE_COUNT: usize = env::var(ENV_RECOVERY_CODE_COUNT)
+        .map(|count| count.parse().unwrap())
+        .unwrap_or(DEFAULT_RECOVERY_CODE_COUNT);
+    pub static ref RECOVERY_CODE_LEN: usize = env::var(ENV_RECOVERY_CODE_LEN)
+        .map(|len| len.parse().unwrap())
+        .unwrap_or(DEFAULT_RECOVERY_CODE_LEN);
+    pub static ref RECOVERY_CODE_NAME: String = env::var(ENV_RECOVERY_CODE_NAME)
+        .unwrap_or_else(|_| DEFAULT_RECOVERY_CODE_NAME.to_string());
+    pub static ref TOKEN_ISSUER: String =
+        env::var(ENV_TOKEN_ISSUER).expect("token issuer must be set");
+    pub static ref TOKEN_STORE: String = {
+        let store = env::var(ENV_TOKEN_STORE)
+            .map(|store| store.to_lowercase())
+            .unwrap_or_else(|_| TOKEN_STORE_REDIS.to_string());
+
+        if store != TOKEN_STORE_REDIS && store != TOKEN_STORE_POSTGRES {
+            panic!("token store must be either {TOKEN_STORE_REDIS} or {TOKEN_STORE_POSTGRES}");
+        }
+
+        store
+    };
+    pub static ref TOKEN_SWEEP_INTERVAL: u64 = env::var(ENV_TOKEN_SWEEP_INTERVAL)
+        .map(|interval| interval.parse().unwrap())
+        .unwrap_or(DEFAULT_TOKEN_SWEEP_INTERVAL);
+    pub static ref LOGIN_LOCKOUT_POLICY: LockoutPolicy = LockoutPolicy {
+        max_attempts: env::var(ENV_LOGIN_MAX_ATTEMPTS)
+            .map(|attempts| attempts.parse().unwrap())
+            .unwrap_or(DEFAULT_LOGIN_MAX_ATTEMPTS),
+        lockout: Duration::from_secs(
+            env::var(ENV_LOGIN_LOCKOUT)
+                .map(|lockout| lockout.parse().unwrap())
+                .unwrap_or(DEFAULT_LOGIN_LOCKOUT),
+        ),
+        max_lockout: Duration::from_secs(
+            env::var(ENV_LOGIN_MAX_LOCKOUT)
+                .map(|lockout| lockout.parse().unwrap())
+                .unwrap_or(DEFAULT_LOGIN_MAX_LOCKOUT),
+        ),
+    };
+    pub static ref TOKEN_SECRET_KEY: Vec<u8> = {
+        // not derived from the jwt secret, since rotating the signing key must not break any pending token
+        let key = env::var(ENV_TOKEN_SECRET_KEY)
+            .map(|key| general_purpose::STANDARD.decode(key).unwrap())
+            .expect("token secret key must be set");
+
+        if key.len() != 32 {
+            panic!("token secret key must be 256 bits long");
+        }
+
+        key
+    };
+    pub static ref TOKEN_FORMAT: TokenFormat = {
+        let format = env::var(ENV_TOKEN_FORMAT)
+            .map(|format| format.to_lowercase())
+            .unwrap_or_else(|_| TOKEN_FORMAT_JWT.to_string());
+
+        match format.as_str() {
+            TOKEN_FORMAT_JWT => TokenFormat::Jwt,
+            TOKEN_FORMAT_OPAQUE => TokenFormat::Opaque,
+            _ => panic!("token format must be either {TOKEN_FORMAT_JWT} or {TOKEN_FORMAT_OPAQUE}"),
+        }
+    };
+}
+
+#[cfg(feature = "postgres")]
+lazy_static! {
     pub static ref POSTGRES_POOL: AsyncOnce<PgPool> = AsyncOnce::new(async {
         let postgres_dsn = env::var(ENV_POSTGRES_DSN).expect("postgres dns must be set");
 
@@ -87,6 +403,10 @@ lazy_static! {
             .await
             .unwrap()
     });
+}
+
+#[cfg(feature = "redis-cache")]
+lazy_static! {
     pub static ref REDIS_POOL: RedisPool = {
         let redis_url: String = env::var(ENV_REDIS_URL).expect("redis url must be set");
         let redis_pool: usize = env::var(ENV_REDIS_POOL)
@@ -100,8 +420,14 @@ lazy_static! {
             .finish_redis_rs()
             .unwrap()
     };
+}
+
+#[cfg(feature = "rabbitmq")]
+lazy_static! {
     pub static ref RABBITMQ_USERS_EXCHANGE: String =
         env::var(ENV_RABBITMQ_USERS_EXCHANGE).expect("rabbitmq users bus name must be set");
+    pub static ref RABBITMQ_TOKENS_EXCHANGE: String = env::var(ENV_RABBITMQ_TOKENS_EXCHANGE)
+        .unwrap_or_else(|_| DEFAULT_RABBITMQ_TOKENS_EXCHANGE.to_string());
     pub static ref RABBITMQ_POOL: AsyncOnce<Pool> = AsyncOnce::new(async {
         let rabbitmq_url = env::var(ENV_RABBITMQ_URL).expect("rabbitmq url must be set");
         let rabbitmq_pool = env::var(ENV_RABBITMQ_POOL)
@@ -127,25 +453,20 @@ lazy_static! {
             passive: false,
         };
 
-        channel
-            .exchange_declare(
-                &RABBITMQ_USERS_EXCHANGE,
-                ExchangeKind::Fanout,
-                exchange_options,
-                FieldTable::default(),
-            )
-            .await
-            .unwrap();
+        for exchange in [&*RABBITMQ_USERS_EXCHANGE, &*RABBITMQ_TOKENS_EXCHANGE] {
+            channel
+                .exchange_declare(
+                    exchange,
+                    ExchangeKind::Fanout,
+                    exchange_options,
+                    FieldTable::default(),
+                )
+                .await
+                .unwrap();
+        }
 
         pool
     });
     pub static ref EVENT_ISSUER: String =
         env::var(ENV_EVENT_ISSUER).expect("event issuer must be set");
-    pub static ref TOTP_SECRET_LEN: usize = env::var(ENV_TOTP_SECRET_LEN)
-        .map(|len| len.parse().unwrap())
-        .unwrap_or_else(|_| DEFAULT_TOTP_SECRET_LEN);
-    pub static ref TOTP_SECRET_NAME: String =
-        env::var(ENV_TOTP_SECRET_NAME).unwrap_or_else(|_| DEFAULT_TOTP_SECRET_NAME.to_string());
-    pub static ref TOKEN_ISSUER: String =
-        env::var(ENV_TOKEN_ISSUER).expect("token issuer must be set");
 }
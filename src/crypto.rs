@@ -1,29 +1,85 @@
 //! Criptography utilities for the validation and generation of JWTs as well as RSA encription and decription.
 
+use crate::base64::B64_CUSTOM_ENGINE;
 use crate::result::{Error, Result};
+use crate::time;
+use base32::Alphabet;
+use base64::Engine;
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use libreauth::{
-    hash::HashFunction::Sha256,
-    oath::{TOTPBuilder, TOTP},
-};
+use libreauth::hash::HashFunction;
+use libreauth::oath::{TOTPBuilder, TOTP};
 use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
     encrypt::{Decrypter, Encrypter},
-    pkey::PKey,
-    rsa::Padding,
+    memcmp,
+    nid::Nid,
+    pkey::{Id, PKey},
+    rsa::{Padding, Rsa},
+    symm::{self, Cipher},
 };
 use rand::prelude::*;
 use serde::{de::DeserializeOwned, Serialize};
+use std::num::TryFromIntError;
+use std::time::{Duration, SystemTime};
+
+const AES_GCM_KEY_LEN: usize = 32;
+const AES_GCM_NONCE_LEN: usize = 12;
+const AES_GCM_TAG_LEN: usize = 16;
 
 const SECURE_CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                                 abcdefghijklmnopqrstuvwxyz\
                                 0123456789";
 
-/// Given an elliptic curve secret in PEM format returns the resulting string of signing the provided
-/// payload in a JWT format.
-pub fn sign_jwt<S: Serialize>(secret: &[u8], payload: S) -> Result<String> {
-    let header = Header::new(Algorithm::ES256);
-    let key = EncodingKey::from_ec_pem(secret).map_err(|err| {
-        error!(error = err.to_string(), "encoding elliptic curve keypair",);
+/// Returns true if, and only if, the given algorithm signs and verifies with the same key.
+pub fn is_symmetric(alg: Algorithm) -> bool {
+    matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512)
+}
+
+fn encoding_key(alg: Algorithm, secret: &[u8]) -> jsonwebtoken::errors::Result<EncodingKey> {
+    match alg {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            Ok(EncodingKey::from_secret(secret))
+        }
+        Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(secret),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(secret),
+        _ => EncodingKey::from_rsa_pem(secret),
+    }
+}
+
+fn decoding_key(alg: Algorithm, public: &[u8]) -> jsonwebtoken::errors::Result<DecodingKey> {
+    match alg {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            Ok(DecodingKey::from_secret(public))
+        }
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(public),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(public),
+        _ => DecodingKey::from_rsa_pem(public),
+    }
+}
+
+/// Given a secret in PEM format, or the raw secret for HMAC algorithms, returns the resulting string of
+/// signing the provided payload in a JWT format. If any, the key id is stamped into the header of the token.
+pub fn sign_jwt<S: Serialize>(
+    secret: &[u8],
+    alg: Algorithm,
+    kid: Option<&str>,
+    payload: S,
+) -> Result<String> {
+    let mut header = Header::new(alg);
+    header.kid = kid.map(ToString::to_string);
+    sign_jwt_with_header(secret, header, payload)
+}
+
+/// Same as [`sign_jwt`], but stamping the given header into the token, whose algorithm is the one to sign
+/// with.
+pub fn sign_jwt_with_header<S: Serialize>(
+    secret: &[u8],
+    header: Header,
+    payload: S,
+) -> Result<String> {
+    let key = encoding_key(header.alg, secret).map_err(|err| {
+        error!(error = err.to_string(), "building encoding key",);
         Error::Unknown
     })?;
 
@@ -35,12 +91,13 @@ pub fn sign_jwt<S: Serialize>(secret: &[u8], payload: S) -> Result<String> {
     Ok(token)
 }
 
-/// Given an elliptic curve secret in PEM format returns the token's claim if, and only if, the provided token
-/// is valid. Otherwise an error is returned.
-pub fn decode_jwt<T: DeserializeOwned>(public: &[u8], token: &str) -> Result<T> {
-    let validation = Validation::new(Algorithm::ES256);
-    let key = DecodingKey::from_ec_pem(public).map_err(|err| {
-        error!(error = err.to_string(), "decoding elliptic curve keypair",);
+/// Given a public key in PEM format, or the raw secret for HMAC algorithms, returns the token's claim if,
+/// and only if, the provided token is valid and has been signed using the given algorithm. Otherwise an
+/// error is returned.
+pub fn decode_jwt<T: DeserializeOwned>(public: &[u8], alg: Algorithm, token: &str) -> Result<T> {
+    let validation = Validation::new(alg);
+    let key = decoding_key(alg, public).map_err(|err| {
+        error!(error = err.to_string(), "building decoding key",);
         Error::Unknown
     })?;
 
@@ -52,6 +109,148 @@ pub fn decode_jwt<T: DeserializeOwned>(public: &[u8], token: &str) -> Result<T>
     Ok(token.claims)
 }
 
+/// Returns the id of the key the given token claims to be signed with, if any.
+pub fn decode_jwt_kid(token: &str) -> Result<Option<String>> {
+    decode_jwt_header(token).map(|header| header.kid)
+}
+
+/// Returns the type the given token claims to be of, if any.
+pub fn decode_jwt_typ(token: &str) -> Result<Option<String>> {
+    decode_jwt_header(token).map(|header| header.typ)
+}
+
+/// Returns the issuer the given token claims to be issued by, if any, without checking its signature.
+pub fn decode_jwt_iss(token: &str) -> Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct Issuer {
+        iss: Option<String>,
+    }
+
+    let payload = token.split('.').nth(1).ok_or_else(|| {
+        warn!("finding token's payload");
+        Error::InvalidToken
+    })?;
+
+    let payload = B64_CUSTOM_ENGINE.decode(payload).map_err(|err| {
+        warn!(
+            error = err.to_string(),
+            "decoding token's payload from base64"
+        );
+        Error::InvalidToken
+    })?;
+
+    serde_json::from_slice::<Issuer>(&payload)
+        .map(|claims| claims.iss)
+        .map_err(|err| {
+            warn!(error = err.to_string(), "deserializing token's payload");
+            Error::InvalidToken
+        })
+}
+
+fn decode_jwt_header(token: &str) -> Result<Header> {
+    jsonwebtoken::decode_header(token).map_err(|err| {
+        warn!(error = err.to_string(), "decoding token's header",);
+        Error::InvalidToken
+    })
+}
+
+/// Given an elliptic curve public key in PEM format returns the x and y coordinates of its point, padded
+/// to the curve's size as required by a JSON Web Key.
+pub fn ec_coordinates(public: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let ec_key = PKey::public_key_from_pem(public)
+        .and_then(|pkey| pkey.ec_key())
+        .map_err(|err| {
+            error!(error = err.to_string(), "parsing elliptic curve public key");
+            Error::Unknown
+        })?;
+
+    let (x, y) = BigNumContext::new()
+        .and_then(|mut ctx| {
+            let mut x = BigNum::new()?;
+            let mut y = BigNum::new()?;
+            ec_key
+                .public_key()
+                .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut ctx)?;
+
+            let len = (ec_key.group().degree() as i32 + 7) / 8;
+            Ok((x.to_vec_padded(len)?, y.to_vec_padded(len)?))
+        })
+        .map_err(|err: openssl::error::ErrorStack| {
+            error!(
+                error = err.to_string(),
+                "computing elliptic curve coordinates"
+            );
+            Error::Unknown
+        })?;
+
+    Ok((x, y))
+}
+
+/// Given a RSA public key in PEM format returns its modulus and public exponent, as required by a JSON
+/// Web Key.
+pub fn rsa_components(public: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let rsa = PKey::public_key_from_pem(public)
+        .and_then(|pkey| pkey.rsa())
+        .map_err(|err| {
+            error!(error = err.to_string(), "parsing rsa public key");
+            Error::Unknown
+        })?;
+
+    Ok((rsa.n().to_vec(), rsa.e().to_vec()))
+}
+
+/// Given an Edwards curve public key in PEM format returns its raw bytes, as required by a JSON Web Key.
+pub fn ed_public_bytes(public: &[u8]) -> Result<Vec<u8>> {
+    PKey::public_key_from_pem(public)
+        .and_then(|pkey| pkey.raw_public_key())
+        .map_err(|err| {
+            error!(error = err.to_string(), "parsing edwards curve public key");
+            Error::Unknown
+        })
+}
+
+/// Given the x and y coordinates of a point in the given elliptic curve returns the public key in PEM
+/// format.
+pub fn ec_public_from_coordinates(curve: Nid, x: &[u8], y: &[u8]) -> Result<Vec<u8>> {
+    EcGroup::from_curve_name(curve)
+        .and_then(|group| {
+            let x = BigNum::from_slice(x)?;
+            let y = BigNum::from_slice(y)?;
+            EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+        })
+        .and_then(PKey::from_ec_key)
+        .and_then(|pkey| pkey.public_key_to_pem())
+        .map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "building elliptic curve public key"
+            );
+            Error::Unknown
+        })
+}
+
+/// Given the modulus and public exponent of a RSA public key returns the key in PEM format.
+pub fn rsa_public_from_components(n: &[u8], e: &[u8]) -> Result<Vec<u8>> {
+    BigNum::from_slice(n)
+        .and_then(|n| Rsa::from_public_components(n, BigNum::from_slice(e)?))
+        .and_then(PKey::from_rsa)
+        .and_then(|pkey| pkey.public_key_to_pem())
+        .map_err(|err| {
+            error!(error = err.to_string(), "building rsa public key");
+            Error::Unknown
+        })
+}
+
+/// Given the raw bytes of an Ed25519 public key returns the key in PEM format.
+pub fn ed_public_from_bytes(x: &[u8]) -> Result<Vec<u8>> {
+    PKey::public_key_from_raw_bytes(x, Id::ED25519)
+        .and_then(|pkey| pkey.public_key_to_pem())
+        .map_err(|err| {
+            error!(error = err.to_string(), "building edwards curve public key");
+            Error::Unknown
+        })
+}
+
 /// Returns an url safe random string.
 pub fn get_random_string(size: usize) -> String {
     let token: String = (0..size)
@@ -65,11 +264,54 @@ pub fn get_random_string(size: usize) -> String {
     token
 }
 
-/// Given an array of bytes to use as secret, generates a TOTP instance.
-pub fn generate_totp(secret: &[u8]) -> Result<TOTP> {
+/// How TOTPs are generated and verified
+#[derive(Clone)]
+pub struct TotpPolicy {
+    pub digits: usize,
+    /// For how long, in seconds, each TOTP is valid.
+    pub period: u64,
+    pub hash_function: HashFunction,
+    /// Amount of periods, before and after the current one, whose TOTP is still accepted to make up for the
+    /// clock drift between the server and the device.
+    pub drift: u64,
+}
+
+impl Default for TotpPolicy {
+    fn default() -> Self {
+        TotpPolicy {
+            digits: 6,
+            period: 30,
+            hash_function: HashFunction::Sha256,
+            drift: 0,
+        }
+    }
+}
+
+impl TotpPolicy {
+    /// Returns for how long the latest period a TOTP has been accepted for must be kept in mind, so no TOTP
+    /// from that period, nor any previous one, gets accepted again.
+    pub fn replay_window(&self) -> Duration {
+        Duration::from_secs(self.period.saturating_mul(2 * self.drift + 2))
+    }
+}
+
+/// Given an array of bytes to use as secret, generates a TOTP instance for the current period.
+pub fn generate_totp(secret: &[u8], policy: &TotpPolicy) -> Result<TOTP> {
+    let now = time::unix_timestamp(SystemTime::now());
+    generate_totp_at(secret, policy, now as u64)
+}
+
+/// Given an array of bytes to use as secret, generates a TOTP instance for the period of the given timestamp.
+fn generate_totp_at(secret: &[u8], policy: &TotpPolicy, timestamp: u64) -> Result<TOTP> {
     TOTPBuilder::new()
         .key(secret)
-        .hash_function(Sha256)
+        .output_len(policy.digits)
+        .period(policy.period.try_into().map_err(|err: TryFromIntError| {
+            error!(error = err.to_string(), "parsing totp period to u32");
+            Error::Unknown
+        })?)
+        .hash_function(policy.hash_function)
+        .timestamp(timestamp as i64)
         .finalize()
         .map_err(|err| {
             error!(
@@ -80,11 +322,41 @@ pub fn generate_totp(secret: &[u8]) -> Result<TOTP> {
         })
 }
 
-/// Given an array of bytes to use as TOTP's secret and a candidate of pwd, returns true if, and only if, pwd
-/// has the same value as the TOTP.  
-pub fn verify_totp(secret: &[u8], pwd: &str) -> Result<bool> {
-    let totp = generate_totp(secret)?;
-    Ok(totp.is_valid(pwd))
+/// Given an array of bytes returns its base32 representation, which is how authenticator apps expect TOTP's
+/// secrets to be typed in.
+pub fn encode_base32(data: &[u8]) -> String {
+    base32::encode(Alphabet::RFC4648 { padding: false }, data)
+}
+
+/// Given an array of bytes to use as TOTP's secret, returns the otpauth uri authenticator apps get provisioned
+/// with. Besides the secret, the uri tells the algorithm, digits and period the TOTP is generated with.
+pub fn totp_uri(secret: &[u8], policy: &TotpPolicy, issuer: &str, account: &str) -> Result<String> {
+    let totp = generate_totp(secret, policy)?;
+    Ok(totp.key_uri_format(issuer, account).finalize())
+}
+
+/// Given an array of bytes to use as TOTP's secret and a candidate of pwd, returns the counter of the period
+/// pwd belongs to if, and only if, it matches the TOTP of the current period or any other within the drift.
+pub fn verify_totp(secret: &[u8], policy: &TotpPolicy, pwd: &str) -> Result<Option<u64>> {
+    let now = time::unix_timestamp(SystemTime::now()) as u64;
+    let Some(counter) = now.checked_div(policy.period) else {
+        error!("verifying totp with no period");
+        return Err(Error::Unknown);
+    };
+
+    for candidate in counter.saturating_sub(policy.drift)..=counter + policy.drift {
+        let totp = generate_totp_at(secret, policy, candidate * policy.period)?;
+        if secure_eq(totp.generate().as_bytes(), pwd.as_bytes()) {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns true if, and only if, both slices are equal, taking the same time no matter where they differ.
+pub fn secure_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && memcmp::eq(a, b)
 }
 
 /// Given a subject str and a sufix returns the sha256 digest of apending them both.
@@ -159,23 +431,171 @@ pub fn _decrypt(private: &[u8], data: &[u8]) -> Result<Vec<u8>> {
     Ok((*decrypted).to_vec())
 }
 
+/// Given a 256 bits key returns the value of data encrypted with AES-256-GCM, authenticating the additional
+/// data along with it. The result is the random nonce followed by the ciphertext and its tag.
+pub fn encrypt_aes_gcm(key: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if key.len() != AES_GCM_KEY_LEN {
+        error!(key_len = key.len(), "checking aes-256-gcm key length");
+        return Err(Error::Unknown);
+    }
+
+    let nonce: [u8; AES_GCM_NONCE_LEN] = rand::thread_rng().gen();
+    let mut tag = [0; AES_GCM_TAG_LEN];
+
+    let ciphertext = symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        aad,
+        data,
+        &mut tag,
+    )
+    .map_err(|err| {
+        error!(error = err.to_string(), "encrypting data with aes-256-gcm");
+        Error::Unknown
+    })?;
+
+    Ok([&nonce[..], &ciphertext, &tag].concat())
+}
+
+/// Given a 256 bits key returns the value of data decrypted with AES-256-GCM if, and only if, neither the
+/// data nor the additional data have been tampered with. Otherwise an error is returned.
+pub fn decrypt_aes_gcm(key: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if key.len() != AES_GCM_KEY_LEN {
+        error!(key_len = key.len(), "checking aes-256-gcm key length");
+        return Err(Error::Unknown);
+    }
+
+    if data.len() < AES_GCM_NONCE_LEN + AES_GCM_TAG_LEN {
+        warn!(data_len = data.len(), "checking aes-256-gcm data length");
+        return Err(Error::InvalidToken);
+    }
+
+    let (nonce, data) = data.split_at(AES_GCM_NONCE_LEN);
+    let (ciphertext, tag) = data.split_at(data.len() - AES_GCM_TAG_LEN);
+
+    symm::decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        aad,
+        ciphertext,
+        tag,
+    )
+    .map_err(|err| {
+        warn!(error = err.to_string(), "decrypting data with aes-256-gcm");
+        Error::InvalidToken
+    })
+}
+
 #[cfg(test)]
 pub mod tests {
-    use super::{generate_totp, verify_totp};
+    use super::TotpPolicy;
+    use super::{
+        decrypt_aes_gcm, encode_base32, encrypt_aes_gcm, generate_totp, generate_totp_at, totp_uri,
+        verify_totp,
+    };
+    use crate::result::Error;
+    use crate::time;
+    use std::time::SystemTime;
 
     #[test]
     fn verify_totp_ok_should_not_fail() {
         const SECRET: &[u8] = "hello world".as_bytes();
 
-        let code = generate_totp(SECRET).unwrap().generate();
+        let policy = TotpPolicy::default();
+        let code = generate_totp(SECRET, &policy).unwrap().generate();
+        let counter = time::unix_timestamp(SystemTime::now()) as u64 / policy.period;
 
         assert_eq!(code.len(), 6);
-        assert!(verify_totp(SECRET, &code).is_ok());
+        assert!(verify_totp(SECRET, &policy, &code)
+            .unwrap()
+            .is_some_and(|accepted| accepted >= counter));
+    }
+
+    #[test]
+    fn verify_totp_with_no_period_should_fail() {
+        const SECRET: &[u8] = "hello world".as_bytes();
+        let policy = TotpPolicy {
+            period: 0,
+            ..Default::default()
+        };
+
+        verify_totp(SECRET, &policy, "123456")
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unknown.to_string()))
+            .unwrap_err();
     }
 
     #[test]
     fn verify_totp_ko_should_not_fail() {
         const SECRET: &[u8] = "hello world".as_bytes();
-        assert!(!verify_totp(SECRET, "tester").unwrap());
+        assert!(verify_totp(SECRET, &TotpPolicy::default(), "tester")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn verify_totp_within_drift_should_not_fail() {
+        const SECRET: &[u8] = "hello world".as_bytes();
+
+        let mut policy = TotpPolicy {
+            digits: 8,
+            ..Default::default()
+        };
+
+        let now = time::unix_timestamp(SystemTime::now()) as u64;
+        let code = generate_totp_at(SECRET, &policy, now - 2 * policy.period)
+            .unwrap()
+            .generate();
+
+        assert_eq!(code.len(), 8);
+        assert!(verify_totp(SECRET, &policy, &code).unwrap().is_none());
+
+        policy.drift = 2;
+        assert!(verify_totp(SECRET, &policy, &code).unwrap().is_some());
+    }
+
+    #[test]
+    fn totp_uri_should_not_fail() {
+        const SECRET: &[u8] = "hello world".as_bytes();
+
+        let uri = totp_uri(SECRET, &TotpPolicy::default(), "rauth", "dummy@test.com").unwrap();
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains(&format!("secret={}", encode_base32(SECRET))));
+        assert!(uri.contains("algorithm=SHA256"));
+    }
+
+    #[test]
+    fn aes_gcm_should_not_fail() {
+        const KEY: &[u8] = &[7; 32];
+
+        let encrypted = encrypt_aes_gcm(KEY, b"aad", b"hello world").unwrap();
+        assert_ne!(encrypted, b"hello world");
+        assert_eq!(encrypted.len(), 12 + 11 + 16);
+
+        let decrypted = decrypt_aes_gcm(KEY, b"aad", &encrypted).unwrap();
+        assert_eq!(decrypted, b"hello world");
+    }
+
+    #[test]
+    fn aes_gcm_wrong_aad_should_fail() {
+        const KEY: &[u8] = &[7; 32];
+
+        let encrypted = encrypt_aes_gcm(KEY, b"aad", b"hello world").unwrap();
+        decrypt_aes_gcm(KEY, b"other", &encrypted)
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn aes_gcm_tampered_data_should_fail() {
+        const KEY: &[u8] = &[7; 32];
+
+        let mut encrypted = encrypt_aes_gcm(KEY, b"aad", b"hello world").unwrap();
+        encrypted[12] ^= 1;
+
+        decrypt_aes_gcm(KEY, b"aad", &encrypted)
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
     }
 }
@@ -1,10 +1,14 @@
 //! gRPC utilities for managing request's headers.
 
 use crate::base64;
+use crate::token::dpop::DpopRequest;
 use tonic::{Request, Status};
 
 use crate::result::Error;
 
+// The header telling for how many seconds the client has to wait before trying again
+const RETRY_AFTER_HEADER: &str = "retry-after";
+
 impl From<Error> for Status {
     fn from(value: Error) -> Self {
         match value {
@@ -17,10 +21,35 @@ impl From<Error> for Status {
             }
             Error::WrongCredentials => Status::unauthenticated(value),
             Error::RegexNotMatch => Status::failed_precondition(value),
+            Error::Locked(retry_after) => {
+                let mut status = Status::resource_exhausted(value);
+                status
+                    .metadata_mut()
+                    .insert(RETRY_AFTER_HEADER, retry_after.into());
+                status
+            }
         }
     }
 }
 
+/// Given a gRPC request, returns the address of the client, as found in the provided header's key if
+/// any, like when behind a proxy, or the address of the peer otherwise.
+pub fn get_client_addr<T>(request: &Request<T>, header: Option<&str>) -> Option<String> {
+    let Some(header) = header else {
+        return request.remote_addr().map(|addr| addr.ip().to_string());
+    };
+
+    // each proxy appends the address of its peer to the list, while anything before may have been set by the
+    // client itself, so only the last one, as appended by the proxy in front of this service, can be trusted
+    let addrs = get_header(request, header).ok()?;
+    addrs
+        .rsplit(',')
+        .next()
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(ToString::to_string)
+}
+
 /// Given a gPRC request, returns the value of the provided header's key if any, otherwise an error
 /// is returned.
 pub fn get_header<T>(req: &Request<T>, header: &str) -> Result<String, Status> {
@@ -45,3 +74,46 @@ pub fn get_encoded_header<T>(request: &Request<T>, header: &str) -> Result<Strin
         Error::InvalidHeader.into()
     })
 }
+
+/// Given a gRPC request, returns the DPoP proof in the provided header's key, if any, as presented for the
+/// given method along with the given access token. Since gRPC calls are all POST requests, the method
+/// path is the only thing to tell them apart.
+pub fn get_dpop_request<T>(
+    request: &Request<T>,
+    header: &str,
+    path: &str,
+    access_token: Option<&str>,
+) -> Result<Option<DpopRequest>, Error> {
+    let Some(proof) = request.metadata().get(header) else {
+        return Ok(None);
+    };
+
+    let proof = proof.to_str().map_err(|err| {
+        warn!(error = err.to_string(), "parsing header data to str",);
+        Error::InvalidHeader
+    })?;
+
+    let dpop = DpopRequest::new(proof, "POST", path);
+    Ok(Some(match access_token {
+        Some(access_token) => dpop.with_access_token(access_token),
+        None => dpop,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_client_addr;
+    use tonic::Request;
+
+    #[test]
+    fn get_client_addr_should_ignore_spoofed_entries() {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("x-forwarded-for", "6.6.6.6, 10.0.0.1".parse().unwrap());
+
+        // only the last entry is appended by the proxy, any other is whatever the client sent
+        let addr = get_client_addr(&request, Some("x-forwarded-for"));
+        assert_eq!(addr.as_deref(), Some("10.0.0.1"));
+    }
+}
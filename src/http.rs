@@ -1,6 +1,7 @@
 use crate::base64;
 use crate::result::{Error, Result};
-use actix_web::{HttpRequest, HttpResponse};
+use crate::token::dpop::DpopRequest;
+use actix_web::{http::header, HttpRequest, HttpResponse};
 
 impl From<Error> for HttpResponse {
     fn from(value: Error) -> Self {
@@ -15,11 +16,16 @@ impl From<Error> for HttpResponse {
 
             Error::WrongCredentials => HttpResponse::Forbidden().finish(),
             Error::RegexNotMatch => HttpResponse::NotAcceptable().finish(),
+            Error::Locked(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after))
+                .body(value.to_string()),
         }
     }
 }
 
-fn get_header(req: HttpRequest, header: &str) -> Result<String> {
+/// Given an http request, returns the value of the provided header's key if any, otherwise an error is
+/// returned.
+pub fn get_header(req: HttpRequest, header: &str) -> Result<String> {
     req.headers()
         .get(header)
         .ok_or(Error::NotFound)
@@ -38,3 +44,58 @@ pub fn get_encoded_header(req: HttpRequest, header: &str) -> Result<String> {
     let header = get_header(req, header)?;
     base64::decode_str(&header)
 }
+
+/// Given an http request, returns the DPoP proof in the provided header's key, if any, as presented for
+/// that very same request along with the given access token.
+pub fn get_dpop_request(
+    req: &HttpRequest,
+    header: &str,
+    access_token: Option<&str>,
+) -> Result<Option<DpopRequest>> {
+    let proof = match get_header(req.clone(), header) {
+        Ok(proof) => proof,
+        Err(Error::NotFound) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let dpop = DpopRequest::new(&proof, req.method().as_str(), req.path());
+    Ok(Some(match access_token {
+        Some(access_token) => dpop.with_access_token(access_token),
+        None => dpop,
+    }))
+}
+
+/// Given an http request, returns the address of the client, as found in the provided header's key if
+/// any, like when behind a proxy, or the address of the peer otherwise.
+pub fn get_client_addr(req: &HttpRequest, header: Option<&str>) -> Option<String> {
+    let Some(header) = header else {
+        return req.peer_addr().map(|addr| addr.ip().to_string());
+    };
+
+    // each proxy appends the address of its peer to the list, while anything before may have been set by the
+    // client itself, so only the last one, as appended by the proxy in front of this service, can be trusted
+    let addrs = get_header(req.clone(), header).ok()?;
+    addrs
+        .rsplit(',')
+        .next()
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_client_addr;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn get_client_addr_should_ignore_spoofed_entries() {
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "6.6.6.6, 10.0.0.1"))
+            .to_http_request();
+
+        // only the last entry is appended by the proxy, any other is whatever the client sent
+        let addr = get_client_addr(&req, Some("x-forwarded-for"));
+        assert_eq!(addr.as_deref(), Some("10.0.0.1"));
+    }
+}
@@ -5,6 +5,8 @@ extern crate serde;
 
 #[cfg(feature = "config")]
 pub mod config;
+#[cfg(feature = "in-memory")]
+pub mod mailbox;
 pub mod metadata;
 pub mod secret;
 pub mod session;
@@ -19,6 +21,8 @@ mod email;
 mod grpc;
 #[cfg(feature = "rest")]
 mod http;
+mod qr;
+#[cfg(feature = "rabbitmq")]
 mod rabbitmq;
 mod regex;
 mod result;
@@ -0,0 +1,129 @@
+//! In-memory implementation of a [`Mailer`](user_app::Mailer) keeping all the sent emails readable from
+//! a local endpoint instead of delivering them.
+
+use crate::base64::B64_CUSTOM_ENGINE;
+use crate::result::{Error, Result};
+use crate::user::application as user_app;
+use base64::Engine;
+use std::sync::RwLock;
+
+const EMAIL_VERIFICATION_SUBJECT: &str = "Email verification";
+const EMAIL_RESET_SUBJECT: &str = "Reset password";
+
+/// Email represents a message that has been sent to the [`Mailbox`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub token: String,
+}
+
+/// Mailbox represents an email sender that keeps all the emails in the process' memory.
+#[derive(Default)]
+pub struct Mailbox {
+    emails: RwLock<Vec<Email>>,
+}
+
+impl Mailbox {
+    /// Returns all the emails sent so far, optionally filtered by their destination.
+    pub fn emails(&self, to: Option<&str>) -> Result<Vec<Email>> {
+        let emails = self.emails.read().map_err(|err| {
+            error!(error = err.to_string(), "locking mailbox for reading");
+            Error::Unknown
+        })?;
+
+        Ok(emails
+            .iter()
+            .filter(|email| to.map(|to| email.to == to).unwrap_or(true))
+            .cloned()
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    fn send_email(&self, to: &str, subject: &str, token: &str) -> Result<()> {
+        let mut emails = self.emails.write().map_err(|err| {
+            error!(error = err.to_string(), "locking mailbox for writing");
+            Error::Unknown
+        })?;
+
+        // the token is encoded the same way the smtp templates do
+        emails.push(Email {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            token: B64_CUSTOM_ENGINE.encode(token),
+        });
+
+        Ok(())
+    }
+}
+
+impl user_app::Mailer for Mailbox {
+    fn send_verification_signup_email(&self, email: &str, token: &str) -> Result<()> {
+        self.send_email(email, EMAIL_VERIFICATION_SUBJECT, token)
+    }
+
+    fn send_verification_reset_email(&self, email: &str, token: &str) -> Result<()> {
+        self.send_email(email, EMAIL_RESET_SUBJECT, token)
+    }
+}
+
+#[cfg(feature = "rest")]
+pub mod rest {
+    use super::Mailbox;
+    use actix_web::{web, HttpResponse, Responder};
+    use std::sync::Arc;
+
+    #[derive(Debug, Deserialize)]
+    pub struct EmailsQuery {
+        to: Option<String>,
+    }
+
+    pub struct MailboxRestService {
+        pub mailbox: Arc<Mailbox>,
+    }
+
+    impl MailboxRestService {
+        pub fn router(&self) -> impl Fn(&mut web::ServiceConfig) {
+            |cfg: &mut web::ServiceConfig| {
+                cfg.service(web::resource("/emails").route(web::get().to(Self::get_emails)));
+            }
+        }
+
+        #[instrument(skip(app_data))]
+        async fn get_emails(
+            app_data: web::Data<Arc<MailboxRestService>>,
+            query: web::Query<EmailsQuery>,
+        ) -> impl Responder {
+            match app_data.mailbox.emails(query.to.as_deref()) {
+                Ok(emails) => HttpResponse::Ok().json(emails),
+                Err(err) => HttpResponse::from(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Mailbox;
+    use crate::base64::B64_CUSTOM_ENGINE;
+    use crate::user::application::Mailer;
+    use base64::Engine;
+
+    #[test]
+    fn mailbox_emails_should_not_fail() {
+        let mailbox = Mailbox::default();
+        mailbox
+            .send_verification_signup_email("alice@example.com", "signup")
+            .unwrap();
+        mailbox
+            .send_verification_reset_email("bob@example.com", "reset")
+            .unwrap();
+
+        assert_eq!(mailbox.emails(None).unwrap().len(), 2);
+
+        let emails = mailbox.emails(Some("bob@example.com")).unwrap();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].subject, "Reset password");
+        assert_eq!(emails[0].token, B64_CUSTOM_ENGINE.encode("reset"));
+    }
+}
@@ -0,0 +1,100 @@
+use super::{application::MetadataRepository, domain::Metadata};
+use crate::result::{Error, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// A [`MetadataRepository`] holding all the metadata in the process' memory, so it gets lost on restart.
+#[derive(Default)]
+pub struct InMemoryMetadataRepository {
+    rows: RwLock<HashMap<i32, Metadata>>,
+    last_id: RwLock<i32>,
+}
+
+#[async_trait]
+impl MetadataRepository for InMemoryMetadataRepository {
+    #[instrument(skip(self))]
+    async fn find(&self, id: i32) -> Result<Metadata> {
+        let rows = self.rows.read().map_err(|err| {
+            error!(error = err.to_string(), "locking metadata for reading");
+            Error::Unknown
+        })?;
+
+        rows.get(&id).cloned().ok_or(Error::NotFound)
+    }
+
+    #[instrument(skip(self))]
+    async fn create(&self, meta: &mut Metadata) -> Result<()> {
+        let mut rows = self.rows.write().map_err(|err| {
+            error!(error = err.to_string(), "locking metadata for writing");
+            Error::Unknown
+        })?;
+
+        let mut last_id = self.last_id.write().map_err(|err| {
+            error!(error = err.to_string(), "locking metadata sequence");
+            Error::Unknown
+        })?;
+
+        *last_id += 1;
+        meta.id = *last_id;
+        rows.insert(meta.id, meta.clone());
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn save(&self, meta: &Metadata) -> Result<()> {
+        let mut rows = self.rows.write().map_err(|err| {
+            error!(error = err.to_string(), "locking metadata for writing");
+            Error::Unknown
+        })?;
+
+        if let Some(row) = rows.get_mut(&meta.id) {
+            *row = meta.clone();
+            row.touch();
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, meta: &Metadata) -> Result<()> {
+        let mut rows = self.rows.write().map_err(|err| {
+            error!(error = err.to_string(), "locking metadata for writing");
+            Error::Unknown
+        })?;
+
+        rows.remove(&meta.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::InMemoryMetadataRepository;
+    use crate::metadata::application::MetadataRepository;
+    use crate::metadata::domain::Metadata;
+    use crate::result::Error;
+
+    #[tokio::test]
+    async fn in_memory_metadata_lifecycle_should_not_fail() {
+        let repo = InMemoryMetadataRepository::default();
+        let mut first = Metadata::default();
+        let mut second = Metadata::default();
+
+        repo.create(&mut first).await.unwrap();
+        repo.create(&mut second).await.unwrap();
+        assert_eq!(first.id, 1);
+        assert_eq!(second.id, 2);
+
+        let mut meta = repo.find(first.id).await.unwrap();
+        meta.deleted_at = Some(meta.created_at);
+        repo.save(&meta).await.unwrap();
+        assert!(repo.find(first.id).await.unwrap().deleted_at.is_some());
+
+        repo.delete(&meta).await.unwrap();
+        repo.find(first.id)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,36 @@
+//! QR code related utilities, like rendering provisioning uris for authenticator apps to scan.
+
+use crate::result::{Error, Result};
+use qrcode::{render::svg, QrCode};
+
+const QR_MIN_DIMENSION: u32 = 200;
+
+/// Given a str returns the SVG image of the QR code encoding it
+pub fn svg(data: &str) -> Result<String> {
+    QrCode::new(data)
+        .map(|code| {
+            code.render::<svg::Color>()
+                .min_dimensions(QR_MIN_DIMENSION, QR_MIN_DIMENSION)
+                .build()
+        })
+        .map_err(|err| {
+            error!(error = err.to_string(), "rendering qr code");
+            Error::Unknown
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::svg;
+
+    #[test]
+    fn svg_should_not_fail() {
+        let image = svg("otpauth://totp/rauth:dummy@test.com?secret=NBSWY3DP").unwrap();
+        assert!(image.contains("<svg"));
+    }
+
+    #[test]
+    fn svg_too_long_data_should_fail() {
+        assert!(svg(&"A".repeat(8000)).is_err());
+    }
+}
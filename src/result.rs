@@ -27,6 +27,9 @@ pub enum Error {
     WrongCredentials,
     #[strum(serialize = "E009")]
     RegexNotMatch,
+    /// Too many failed attempts, so no further one is allowed for the given amount of seconds.
+    #[strum(serialize = "E010")]
+    Locked(u64),
 }
 
 impl From<Error> for String {
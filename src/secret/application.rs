@@ -6,9 +6,11 @@ use async_trait::async_trait;
 pub trait SecretRepository {
     async fn find(&self, id: i32) -> Result<Secret>;
     async fn find_by_user_and_name(&self, user: i32, name: &str) -> Result<Secret>;
+    async fn find_by_user(&self, user: i32) -> Result<Vec<Secret>>;
     async fn create(&self, secret: &mut Secret) -> Result<()>;
     async fn save(&self, secret: &Secret) -> Result<()>;
-    async fn delete(&self, secret: &Secret) -> Result<()>;
+    /// Removes the given secret, returning false if it did not exist anymore.
+    async fn delete(&self, secret: &Secret) -> Result<bool>;
 }
 
 #[cfg(test)]
@@ -21,14 +23,17 @@ pub mod tests {
     type MockFnFind = Option<fn(this: &SecretRepositoryMock, id: i32) -> Result<Secret>>;
     type MockFnFindByUserAndName =
         Option<fn(this: &SecretRepositoryMock, user: i32, name: &str) -> Result<Secret>>;
+    type MockFnFindByUser =
+        Option<fn(this: &SecretRepositoryMock, user: i32) -> Result<Vec<Secret>>>;
     type MockFnCreate = Option<fn(this: &SecretRepositoryMock, secret: &mut Secret) -> Result<()>>;
     type MockFnSave = Option<fn(this: &SecretRepositoryMock, secret: &Secret) -> Result<()>>;
-    type MockFnDelete = Option<fn(this: &SecretRepositoryMock, secret: &Secret) -> Result<()>>;
+    type MockFnDelete = Option<fn(this: &SecretRepositoryMock, secret: &Secret) -> Result<bool>>;
 
     #[derive(Default)]
     pub struct SecretRepositoryMock {
         pub fn_find: MockFnFind,
         pub fn_find_by_user_and_name: MockFnFindByUserAndName,
+        pub fn_find_by_user: MockFnFindByUser,
         pub fn_create: MockFnCreate,
         pub fn_save: MockFnSave,
         pub fn_delete: MockFnDelete,
@@ -54,6 +59,15 @@ pub mod tests {
             Ok(new_secret())
         }
 
+        #[instrument(skip(self))]
+        async fn find_by_user(&self, user: i32) -> Result<Vec<Secret>> {
+            if let Some(f) = self.fn_find_by_user {
+                return f(self, user);
+            }
+
+            Ok(Vec::new())
+        }
+
         #[instrument(skip(self))]
         async fn create(&self, secret: &mut Secret) -> Result<()> {
             if let Some(f) = self.fn_create {
@@ -73,12 +87,12 @@ pub mod tests {
         }
 
         #[instrument(skip(self))]
-        async fn delete(&self, secret: &Secret) -> Result<()> {
+        async fn delete(&self, secret: &Secret) -> Result<bool> {
             if let Some(f) = self.fn_delete {
                 return f(self, secret);
             }
 
-            Ok(())
+            Ok(true)
         }
     }
 }
@@ -30,6 +30,10 @@ impl Secret {
         self.id
     }
 
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     pub fn is_deleted(&self) -> bool {
         self.meta.deleted_at.is_some()
     }
@@ -0,0 +1,235 @@
+use super::{application::SecretRepository, domain::Secret};
+use crate::metadata::application::MetadataRepository;
+use crate::result::{Error, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+type InMemorySecretRow = (i32, String, Vec<u8>, i32, i32); // id, name, data, user_id, meta_id
+
+/// A [`SecretRepository`] holding all the secrets in the process' memory, so they get lost on restart.
+pub struct InMemorySecretRepository<M: MetadataRepository> {
+    pub metadata_repo: Arc<M>,
+    rows: RwLock<HashMap<i32, InMemorySecretRow>>,
+    last_id: RwLock<i32>,
+}
+
+impl<M: MetadataRepository> InMemorySecretRepository<M> {
+    pub fn new(metadata_repo: Arc<M>) -> Self {
+        InMemorySecretRepository {
+            metadata_repo,
+            rows: Default::default(),
+            last_id: Default::default(),
+        }
+    }
+
+    async fn build(&self, row: InMemorySecretRow) -> Result<Secret> {
+        let meta = self.metadata_repo.find(row.4).await?;
+
+        Ok(Secret {
+            id: row.0,
+            name: row.1,
+            data: row.2,
+            owner: row.3,
+            meta,
+        })
+    }
+
+    fn find_row<P: Fn(&InMemorySecretRow) -> bool>(
+        &self,
+        predicate: P,
+    ) -> Result<InMemorySecretRow> {
+        let rows = self.rows.read().map_err(|err| {
+            error!(error = err.to_string(), "locking secrets for reading");
+            Error::Unknown
+        })?;
+
+        rows.values()
+            .find(|row| predicate(row))
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+}
+
+#[async_trait]
+impl<M: MetadataRepository + Sync + Send> SecretRepository for InMemorySecretRepository<M> {
+    #[instrument(skip(self))]
+    async fn find(&self, target: i32) -> Result<Secret> {
+        let row = self.find_row(|row| row.0 == target)?;
+        self.build(row).await
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_user_and_name(&self, user: i32, name: &str) -> Result<Secret> {
+        let row = self.find_row(|row| row.3 == user && row.1 == name)?;
+        self.build(row).await
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_user(&self, user: i32) -> Result<Vec<Secret>> {
+        let rows: Vec<InMemorySecretRow> = {
+            // block is required because of lock release
+            let rows = self.rows.read().map_err(|err| {
+                error!(error = err.to_string(), "locking secrets for reading");
+                Error::Unknown
+            })?;
+
+            rows.values().filter(|row| row.3 == user).cloned().collect()
+        };
+
+        let mut secrets = Vec::with_capacity(rows.len());
+        for row in rows {
+            secrets.push(self.build(row).await?);
+        }
+
+        Ok(secrets)
+    }
+
+    #[instrument(skip(self))]
+    async fn create(&self, secret: &mut Secret) -> Result<()> {
+        if self
+            .find_row(|row| row.3 == secret.owner && row.1 == secret.name)
+            .is_ok()
+        {
+            error!(name = secret.name, "violating secrets unique constraint");
+            return Err(Error::Unknown);
+        }
+
+        self.metadata_repo.create(&mut secret.meta).await?;
+
+        let mut rows = self.rows.write().map_err(|err| {
+            error!(error = err.to_string(), "locking secrets for writing");
+            Error::Unknown
+        })?;
+
+        let mut last_id = self.last_id.write().map_err(|err| {
+            error!(error = err.to_string(), "locking secrets sequence");
+            Error::Unknown
+        })?;
+
+        *last_id += 1;
+        secret.id = *last_id;
+        rows.insert(
+            secret.id,
+            (
+                secret.id,
+                secret.name.clone(),
+                secret.data.clone(),
+                secret.owner,
+                secret.meta.get_id(),
+            ),
+        );
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn save(&self, secret: &Secret) -> Result<()> {
+        let Ok(row) = self.find_row(|row| row.0 == secret.id) else {
+            return Ok(()); // as an update, does nothing if the secret does not exist
+        };
+
+        if row.1 != secret.name || row.2 != secret.data || row.3 != secret.owner {
+            // same as the database trigger, secrets are immutable
+            error!(id = secret.id, "updating immutable secret fields");
+            return Err(Error::Unknown);
+        }
+
+        self.metadata_repo.save(&secret.meta).await
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, secret: &Secret) -> Result<bool> {
+        let deleted = {
+            // block is required because of lock release
+            let mut rows = self.rows.write().map_err(|err| {
+                error!(error = err.to_string(), "locking secrets for writing");
+                Error::Unknown
+            })?;
+
+            rows.remove(&secret.id).is_some()
+        };
+
+        if !deleted {
+            return Ok(false);
+        }
+
+        self.metadata_repo.delete(&secret.meta).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::InMemorySecretRepository;
+    use crate::metadata::memory::InMemoryMetadataRepository;
+    use crate::result::Error;
+    use crate::secret::application::SecretRepository;
+    use crate::secret::domain::Secret;
+    use crate::user::domain::tests::new_user;
+    use chrono::Utc;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn in_memory_secret_lifecycle_should_not_fail() {
+        let repo = InMemorySecretRepository::new(Arc::new(InMemoryMetadataRepository::default()));
+        let user = new_user();
+        let mut secret = Secret::new(&user, "totp", b"secret data");
+        secret.set_deleted_at(Some(Utc::now().naive_utc()));
+        repo.create(&mut secret).await.unwrap();
+
+        let mut found = repo
+            .find_by_user_and_name(user.get_id(), "totp")
+            .await
+            .unwrap();
+        assert_eq!(found.get_id(), secret.get_id());
+        assert!(found.is_deleted());
+
+        found.set_deleted_at(None);
+        repo.save(&found).await.unwrap();
+        assert!(!repo.find(secret.get_id()).await.unwrap().is_deleted());
+
+        assert!(repo.delete(&found).await.unwrap());
+        assert!(!repo.delete(&found).await.unwrap());
+        repo.find(secret.get_id())
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_secret_update_data_should_fail() {
+        let repo = InMemorySecretRepository::new(Arc::new(InMemoryMetadataRepository::default()));
+        let user = new_user();
+        let mut secret = Secret::new(&user, "totp", b"secret data");
+        repo.create(&mut secret).await.unwrap();
+
+        secret.data = b"another data".to_vec();
+        repo.save(&secret)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unknown.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_secret_find_by_user_should_not_fail() {
+        let repo = InMemorySecretRepository::new(Arc::new(InMemoryMetadataRepository::default()));
+        let user = new_user();
+        for name in ["totp", "recovery::0"] {
+            let mut secret = Secret::new(&user, name, b"secret data");
+            repo.create(&mut secret).await.unwrap();
+        }
+
+        let mut names: Vec<String> = repo
+            .find_by_user(user.get_id())
+            .await
+            .unwrap()
+            .iter()
+            .map(|secret| secret.get_name().to_string())
+            .collect();
+
+        names.sort();
+        assert_eq!(names, vec!["recovery::0", "totp"]);
+        assert!(repo.find_by_user(0).await.unwrap().is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod application;
 pub mod domain;
+#[cfg(feature = "in-memory")]
+pub mod memory;
 #[cfg(feature = "postgres")]
 pub mod repository;
@@ -12,6 +12,8 @@ const QUERY_FIND_SECRET: &str =
     "SELECT id, name, data, user_id, meta_id FROM secrets WHERE id = $1";
 const QUERY_FIND_SECRET_BY_USER_AND_NAME: &str =
     "SELECT id, name, data, user_id, meta_id FROM secrets WHERE user_id = $1 AND name = $2";
+const QUERY_FIND_SECRETS_BY_USER: &str =
+    "SELECT id, name, data, user_id, meta_id FROM secrets WHERE user_id = $1";
 const QUERY_UPDATE_SECRET: &str =
     "UPDATE secrets SET name = $2, data = $3, user_id = $4, meta_id = $5, password = $4 FROM secrets WHERE id = $1";
 const QUERY_DELETE_SECRET: &str = "DELETE FROM secrets WHERE id = $1";
@@ -94,6 +96,31 @@ impl<'a, M: MetadataRepository + std::marker::Sync + std::marker::Send> SecretRe
         self.build(&row).await // another connection consumed here
     }
 
+    #[instrument(skip(self))]
+    async fn find_by_user(&self, user: i32) -> Result<Vec<Secret>> {
+        let rows: Vec<PostgresSecretRow> = {
+            // block is required because of connection release
+            sqlx::query_as(QUERY_FIND_SECRETS_BY_USER)
+                .bind(user)
+                .fetch_all(self.pool)
+                .await
+                .map_err(|err| {
+                    error!(
+                        error = err.to_string(),
+                        "performing select by user query on postgres",
+                    );
+                    Error::Unknown
+                })?
+        };
+
+        let mut secrets = Vec::with_capacity(rows.len());
+        for row in &rows {
+            secrets.push(self.build(row).await?); // another connection consumed here
+        }
+
+        Ok(secrets)
+    }
+
     #[instrument(skip(self))]
     async fn create(&self, secret: &mut Secret) -> Result<()> {
         self.metadata_repo.create(&mut secret.meta).await?;
@@ -139,12 +166,12 @@ impl<'a, M: MetadataRepository + std::marker::Sync + std::marker::Send> SecretRe
     }
 
     #[instrument(skip(self))]
-    async fn delete(&self, secret: &Secret) -> Result<()> {
-        {
+    async fn delete(&self, secret: &Secret) -> Result<bool> {
+        let deleted = {
             // block is required because of connection release
             sqlx::query(QUERY_DELETE_SECRET)
                 .bind(secret.id)
-                .fetch_one(self.pool)
+                .execute(self.pool)
                 .await
                 .map_err(|err| {
                     error!(
@@ -152,10 +179,15 @@ impl<'a, M: MetadataRepository + std::marker::Sync + std::marker::Send> SecretRe
                         "performing delete query on postgres",
                     );
                     Error::Unknown
-                })?;
+                })?
+        };
+
+        if deleted.rows_affected() == 0 {
+            // another request may have deleted it in the meantime
+            return Ok(false);
         }
 
         self.metadata_repo.delete(&secret.meta).await?; // another connection consumed here
-        Ok(())
+        Ok(true)
     }
 }
@@ -1,84 +1,343 @@
-use crate::crypto;
+use super::domain::{ActiveSession, LockoutPolicy};
 use crate::regex;
 use crate::result::{Error, Result};
 use crate::secret::application::SecretRepository;
-use crate::token::application::GenerateOptions;
 use crate::token::application::TokenApplication;
-use crate::token::application::TokenRepository;
-use crate::token::application::VerifyOptions;
-use crate::token::domain::TokenKind;
-use crate::user::application::UserRepository;
+use crate::token::application::{GenerateOptions, VerifyOptions};
+use crate::token::application::{TokenEventBus, TokenRepository};
+use crate::token::domain::{Introspection, Token, TokenDefinition, TokenKind, TokenPair};
+use crate::token::dpop::DpopRequest;
+use crate::user::application::{is_totp_enabled, TotpVerifier, UserRepository};
+use crate::user::domain::{TotpPolicy, User};
+use crate::{crypto, time};
+use std::num::ParseIntError;
 use std::sync::Arc;
+use std::time::SystemTime;
 
-pub struct SessionApplication<'a, T: TokenRepository, U: UserRepository, E: SecretRepository> {
+const LOGIN_ATTEMPTS_PREFIX: &str = "Attempts";
+const LOGIN_LOCKOUT_PREFIX: &str = "Lockout";
+
+pub struct SessionApplication<
+    'a,
+    T: TokenRepository,
+    U: UserRepository,
+    E: SecretRepository,
+    B: TokenEventBus,
+> {
     pub user_repo: Arc<U>,
     pub secret_repo: Arc<E>,
-    pub token_app: Arc<TokenApplication<'a, T>>,
+    pub token_app: Arc<TokenApplication<'a, T, B>>,
+    pub lockout_policy: LockoutPolicy,
     pub totp_secret_name: &'a str,
+    pub totp_policy: TotpPolicy,
+    pub recovery_code_name: &'a str,
     pub pwd_sufix: &'a str,
+    /// The secret resource servers must provide in order to introspect tokens, if any.
+    pub introspection_secret: Option<&'a str>,
 }
 
-impl<'a, T: TokenRepository, U: UserRepository, E: SecretRepository>
-    SessionApplication<'a, T, U, E>
+impl<'a, T: TokenRepository, U: UserRepository, E: SecretRepository, B: TokenEventBus>
+    SessionApplication<'a, T, U, E, B>
 {
+    /// Logs in the user with the given identity, either its email or name, unless too many attempts have
+    /// failed recently for the same user or client address, in which case no credentials are checked at all.
     #[instrument(skip(self))]
-    pub async fn login(&self, ident: &str, pwd: &str, totp: &str) -> Result<String> {
+    pub async fn login(
+        &self,
+        ident: &str,
+        pwd: &str,
+        totp: &str,
+        options: GenerateOptions,
+        dpop: Option<&DpopRequest>,
+        addr: Option<&str>,
+    ) -> Result<TokenPair> {
         let user = {
             if regex::match_regex(regex::EMAIL, ident).is_ok() {
                 self.user_repo.find_by_email(ident).await
             } else {
                 self.user_repo.find_by_name(ident).await
             }
+        };
+
+        // attempts are kept by user, when it exists, so switching between its email and name makes no difference
+        let ident_scope = match &user {
+            Ok(user) => format!("User::{}", user.get_id()),
+            Err(_) => format!("Ident::{}", ident.to_lowercase()),
+        };
+
+        let mut scopes = vec![ident_scope];
+        scopes.extend(addr.map(|addr| format!("Addr::{}", addr)));
+        self.check_lockout(&scopes).await?;
+
+        let user = match self.authenticate(user, pwd, totp).await {
+            Err(err @ (Error::WrongCredentials | Error::Unauthorized)) => {
+                self.register_failure(&scopes).await?;
+                return Err(err);
+            }
+            other => other?,
+        };
+
+        // only the failed attempts of the user are forgiven, otherwise a client could try as many identities
+        // as wanted as long as logging in as itself from time to time
+        if self.lockout_policy.is_enabled() {
+            self.token_app
+                .token_repo
+                .delete(&Self::attempts_key(&scopes[0]))
+                .await?;
         }
-        .map_err(|_| Error::WrongCredentials)?;
+
+        // if a proof is presented, the whole token family gets bound to the key it has been signed with
+        let jkt = match dpop {
+            Some(dpop) => Some(self.token_app.prove_possession(dpop).await?),
+            None => None,
+        };
+
+        self.token_app
+            .generate_pair(
+                &user.get_id().to_string(),
+                GenerateOptions { jkt, ..options },
+            )
+            .await
+    }
+
+    async fn authenticate(&self, user: Result<User>, pwd: &str, totp: &str) -> Result<User> {
+        let user = user.map_err(|_| Error::WrongCredentials)?;
 
         let pwd = crypto::obfuscate(pwd, self.pwd_sufix);
         if !user.match_password(&pwd) {
             return Err(Error::WrongCredentials);
         }
 
+        let verifier = TotpVerifier {
+            secret_repo: &*self.secret_repo,
+            token_repo: &*self.token_app.token_repo,
+            totp_secret_name: self.totp_secret_name,
+            totp_policy: &self.totp_policy,
+            recovery_code_name: self.recovery_code_name,
+            pwd_sufix: self.pwd_sufix,
+        };
+
         // if, and only if, the user has activated the totp
-        if let Ok(secret) = self
-            .secret_repo
-            .find_by_user_and_name(user.get_id(), self.totp_secret_name)
-            .await
+        let authenticators = verifier.find_authenticators(user.get_id()).await?;
+        if is_totp_enabled(&authenticators)
+            && !verifier
+                .verify(user.get_id(), &authenticators, totp)
+                .await?
         {
-            if !secret.is_deleted() {
-                let data = secret.get_data();
-                if !crypto::verify_totp(data, totp)? {
-                    return Err(Error::Unauthorized);
-                }
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(user)
+    }
+
+    fn attempts_key(scope: &str) -> String {
+        format!("{}::{}", LOGIN_ATTEMPTS_PREFIX, scope)
+    }
+
+    fn lockout_key(scope: &str) -> String {
+        format!("{}::{}", LOGIN_LOCKOUT_PREFIX, scope)
+    }
+
+    /// Fails with [`Error::Locked`] if any of the given scopes is locked out.
+    async fn check_lockout(&self, scopes: &[String]) -> Result<()> {
+        if !self.lockout_policy.is_enabled() {
+            return Ok(());
+        }
+
+        let keys: Vec<String> = scopes
+            .iter()
+            .map(|scope| Self::lockout_key(scope))
+            .collect();
+        let locked_until = self
+            .token_app
+            .token_repo
+            .find_many(&keys)
+            .await?
+            .into_iter()
+            .flatten()
+            .try_fold(0, |latest, until| {
+                let until: usize = until.parse().map_err(|err: ParseIntError| {
+                    error!(error = err.to_string(), "parsing lockout deadline");
+                    Error::Unknown
+                })?;
+
+                Ok(latest.max(until))
+            })?;
+
+        let now = time::unix_timestamp(SystemTime::now());
+        if locked_until > now {
+            warn!(?scopes, locked_until, "login attempt while locked out");
+            return Err(Error::Locked((locked_until - now) as u64));
+        }
+
+        Ok(())
+    }
+
+    /// Keeps track of a failed attempt for each of the given scopes, locking out those that have reached the
+    /// maximum amount of attempts.
+    async fn register_failure(&self, scopes: &[String]) -> Result<()> {
+        if !self.lockout_policy.is_enabled() {
+            return Ok(());
+        }
+
+        let now = time::unix_timestamp(SystemTime::now());
+        for scope in scopes {
+            let failures = self
+                .token_app
+                .token_repo
+                .increment(
+                    &Self::attempts_key(scope),
+                    self.lockout_policy.max_lockout.as_secs(),
+                )
+                .await?;
+
+            let lockout = self.lockout_policy.lockout_for(failures).as_secs();
+            if lockout == 0 {
+                continue;
             }
+
+            warn!(
+                scope = scope.as_str(),
+                failures, lockout, "locking out login attempts"
+            );
+            let locked_until = now + lockout as usize;
+            self.token_app
+                .token_repo
+                .save(
+                    &Self::lockout_key(scope),
+                    &locked_until.to_string(),
+                    Some(lockout),
+                )
+                .await?;
         }
 
-        self.token_app
-            .generate(
-                TokenKind::Session,
-                &user.get_id().to_string(),
-                None,
-                GenerateOptions::default(),
-            )
-            .await
-            .map(|token| token.signature().to_string())
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn refresh(&self, token: &str, dpop: Option<&DpopRequest>) -> Result<TokenPair> {
+        self.token_app.refresh(token, dpop).await
+    }
+
+    /// Returns the state of the given token on behalf of a resource server, which must authenticate itself
+    /// with the introspection secret. No token is introspected at all if there is no such secret.
+    #[instrument(skip(self, secret))]
+    pub async fn introspect(
+        &self,
+        secret: &str,
+        token: &str,
+        options: VerifyOptions,
+    ) -> Result<Introspection> {
+        let authorized = self
+            .introspection_secret
+            .is_some_and(|introspection_secret| {
+                crypto::secure_eq(secret.as_bytes(), introspection_secret.as_bytes())
+            });
+
+        if !authorized {
+            warn!("checking introspection secret");
+            return Err(Error::Unauthorized);
+        }
+
+        self.token_app.introspect(token, options).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn logout(&self, token: &str, dpop: Option<&DpopRequest>) -> Result<()> {
+        logout_strategy::<T, B>(&self.token_app, token, dpop).await
     }
 
     #[instrument(skip(self))]
-    pub async fn logout(&self, token: &str) -> Result<()> {
-        logout_strategy::<T>(&self.token_app, token).await
+    pub async fn list_sessions(
+        &self,
+        token: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<Vec<ActiveSession>> {
+        list_sessions_strategy::<T, B>(&self.token_app, token, dpop).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke_session(
+        &self,
+        token: &str,
+        session_id: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<()> {
+        revoke_session_strategy::<T, B>(&self.token_app, token, session_id, dpop).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn logout_everywhere(&self, token: &str, dpop: Option<&DpopRequest>) -> Result<()> {
+        logout_everywhere_strategy::<T, B>(&self.token_app, token, dpop).await
     }
 }
 
-pub(super) async fn logout_strategy<'b, R: TokenRepository>(
-    token_app: &TokenApplication<'b, R>,
+async fn verify_session<'b, R: TokenRepository, B: TokenEventBus>(
+    token_app: &TokenApplication<'b, R, B>,
     token: &str,
-) -> Result<()> {
+    dpop: Option<&DpopRequest>,
+) -> Result<Token> {
     let token = token_app.decode(token).await?;
 
-    token_app
-        .verify(&token, VerifyOptions::new(TokenKind::Session))
-        .await?;
+    let options = VerifyOptions {
+        dpop: dpop.cloned(),
+        ..VerifyOptions::new(TokenKind::Session)
+    };
+
+    token_app.verify(&token, options).await?;
+
+    Ok(token)
+}
+
+pub(super) async fn logout_strategy<'b, R: TokenRepository, B: TokenEventBus>(
+    token_app: &TokenApplication<'b, R, B>,
+    token: &str,
+    dpop: Option<&DpopRequest>,
+) -> Result<()> {
+    let token = verify_session(token_app, token, dpop).await?;
+
+    token_app.revoke(&token).await?;
+    if let Some(family) = token.get_family() {
+        // the refresh token must not outlive the session it was issued with
+        token_app.revoke_family(family).await?;
+    }
+
+    Ok(())
+}
+
+pub(super) async fn list_sessions_strategy<'b, R: TokenRepository, B: TokenEventBus>(
+    token_app: &TokenApplication<'b, R, B>,
+    token: &str,
+    dpop: Option<&DpopRequest>,
+) -> Result<Vec<ActiveSession>> {
+    let token = verify_session(token_app, token, dpop).await?;
+    let tokens = token_app.find_by_subject(&token.sub).await?;
+    Ok(ActiveSession::from_tokens(&tokens, token.get_family()))
+}
+
+pub(super) async fn revoke_session_strategy<'b, R: TokenRepository, B: TokenEventBus>(
+    token_app: &TokenApplication<'b, R, B>,
+    token: &str,
+    session_id: &str,
+    dpop: Option<&DpopRequest>,
+) -> Result<()> {
+    let sessions = list_sessions_strategy(token_app, token, dpop).await?;
+    if !sessions.iter().any(|session| session.id == session_id) {
+        // a user must not be able to revoke sessions from anyone else
+        warn!(session_id, "finding session by id");
+        return Err(Error::NotFound);
+    }
+
+    token_app.revoke_family(session_id).await
+}
 
-    token_app.revoke(&token).await
+pub(super) async fn logout_everywhere_strategy<'b, R: TokenRepository, B: TokenEventBus>(
+    token_app: &TokenApplication<'b, R, B>,
+    token: &str,
+    dpop: Option<&DpopRequest>,
+) -> Result<()> {
+    let token = verify_session(token_app, token, dpop).await?;
+    token_app.revoke_subject(&token.sub).await
 }
 
 #[cfg(test)]
@@ -87,36 +346,71 @@ pub mod tests {
     use crate::secret::application::tests::SecretRepositoryMock;
     use crate::secret::domain::tests::TEST_DEFAULT_SECRET_DATA;
     use crate::secret::domain::Secret;
+    use crate::session::domain::LockoutPolicy;
     use crate::token::application::tests::{
-        new_token, new_token_application, PRIVATE_KEY, PUBLIC_KEY,
+        new_family_token, new_token, new_token_application, TokenEventBusMock, KEYRING,
     };
-    use crate::token::domain::{Token, TokenKind};
+    use crate::token::application::GenerateOptions;
+    use crate::token::domain::{Token, TokenFamily, TokenKind};
+    use crate::token::dpop::tests::{dpop_thumbprint, new_dpop_proof};
+    use crate::token::dpop::DpopRequest;
     use crate::user::domain::tests::TEST_DEFAULT_PWD_SUFIX;
     use crate::user::{
-        application::tests::{UserRepositoryMock, TEST_FIND_BY_EMAIL_ID, TEST_FIND_BY_NAME_ID},
+        application::tests::{
+            new_authenticator, new_recovery_code, UserRepositoryMock, TEST_AUTHENTICATOR_DATA,
+            TEST_FIND_BY_EMAIL_ID, TEST_FIND_BY_NAME_ID, TEST_RECOVERY_CODE,
+        },
         domain::tests::{
             TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_NAME, TEST_DEFAULT_USER_PASSWORD,
         },
-        domain::User,
+        domain::{TotpPolicy, User},
     };
     use crate::{
         crypto,
         result::{Error, Result},
+        time,
     };
     use async_trait::async_trait;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    const TEST_INTROSPECTION_SECRET: &str = "dummy_introspection_secret";
 
     type MockFnFind = Option<fn(this: &TokenRepositoryMock, key: &str) -> Result<String>>;
+    type MockFnFindMany =
+        Option<fn(this: &TokenRepositoryMock, keys: &[String]) -> Result<Vec<Option<String>>>>;
     type MockFnSave = Option<
         fn(this: &TokenRepositoryMock, key: &str, token: &str, expire: Option<u64>) -> Result<()>,
     >;
+    type MockFnInsert = Option<
+        fn(this: &TokenRepositoryMock, key: &str, token: &str, expire: Option<u64>) -> Result<bool>,
+    >;
     type MockFnDelete = Option<fn(this: &TokenRepositoryMock, key: &str) -> Result<()>>;
+    type MockFnIncrement =
+        Option<fn(this: &TokenRepositoryMock, key: &str, expire: u64) -> Result<u64>>;
+    type MockFnTouch = Option<fn(this: &TokenRepositoryMock, key: &str, expire: u64) -> Result<()>>;
+    type MockFnConsume = Option<fn(this: &TokenRepositoryMock, key: &str) -> Result<String>>;
+    type MockFnIndex = Option<
+        fn(this: &TokenRepositoryMock, sub: &str, key: &str, expire: Option<u64>) -> Result<()>,
+    >;
+    type MockFnFindBySubject =
+        Option<fn(this: &TokenRepositoryMock, sub: &str) -> Result<Vec<String>>>;
+    type MockFnDeleteBySubject = Option<fn(this: &TokenRepositoryMock, sub: &str) -> Result<()>>;
 
     #[derive(Default, Clone)]
     pub struct TokenRepositoryMock {
         pub fn_find: MockFnFind,
+        pub fn_find_many: MockFnFindMany,
         pub fn_save: MockFnSave,
+        pub fn_insert: MockFnInsert,
         pub fn_delete: MockFnDelete,
+        pub fn_increment: MockFnIncrement,
+        pub fn_touch: MockFnTouch,
+        pub fn_consume: MockFnConsume,
+        pub fn_index: MockFnIndex,
+        pub fn_find_by_subject: MockFnFindBySubject,
+        pub fn_delete_by_subject: MockFnDeleteBySubject,
         pub token: String,
     }
 
@@ -130,6 +424,14 @@ pub mod tests {
             Ok(self.token.clone())
         }
 
+        async fn find_many(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+            if let Some(fn_find_many) = self.fn_find_many {
+                return fn_find_many(self, keys);
+            }
+
+            Ok(vec![None; keys.len()])
+        }
+
         async fn save(&self, key: &str, token: &str, expire: Option<u64>) -> Result<()> {
             if let Some(fn_save) = self.fn_save {
                 return fn_save(self, key, token, expire);
@@ -138,6 +440,14 @@ pub mod tests {
             Ok(())
         }
 
+        async fn insert(&self, key: &str, token: &str, expire: Option<u64>) -> Result<bool> {
+            if let Some(fn_insert) = self.fn_insert {
+                return fn_insert(self, key, token, expire);
+            }
+
+            Ok(true)
+        }
+
         async fn delete(&self, key: &str) -> Result<()> {
             if let Some(fn_delete) = self.fn_delete {
                 return fn_delete(self, key);
@@ -145,11 +455,60 @@ pub mod tests {
 
             Ok(())
         }
+
+        async fn increment(&self, key: &str, expire: u64) -> Result<u64> {
+            if let Some(fn_increment) = self.fn_increment {
+                return fn_increment(self, key, expire);
+            }
+
+            Ok(1)
+        }
+
+        async fn touch(&self, key: &str, expire: u64) -> Result<()> {
+            if let Some(fn_touch) = self.fn_touch {
+                return fn_touch(self, key, expire);
+            }
+
+            Ok(())
+        }
+
+        async fn consume(&self, key: &str) -> Result<String> {
+            if let Some(fn_consume) = self.fn_consume {
+                return fn_consume(self, key);
+            }
+
+            Ok(self.token.clone())
+        }
+
+        async fn index(&self, sub: &str, key: &str, expire: Option<u64>) -> Result<()> {
+            if let Some(fn_index) = self.fn_index {
+                return fn_index(self, sub, key, expire);
+            }
+
+            Ok(())
+        }
+
+        async fn find_by_subject(&self, sub: &str) -> Result<Vec<String>> {
+            if let Some(fn_find_by_subject) = self.fn_find_by_subject {
+                return fn_find_by_subject(self, sub);
+            }
+
+            Ok(vec![self.token.clone()])
+        }
+
+        async fn delete_by_subject(&self, sub: &str) -> Result<()> {
+            if let Some(fn_delete_by_subject) = self.fn_delete_by_subject {
+                return fn_delete_by_subject(self, sub);
+            }
+
+            Ok(())
+        }
     }
 
     pub fn new_session_application<'a, T: TokenRepository + Default>(
         token_repo: Option<T>,
-    ) -> SessionApplication<'a, T, UserRepositoryMock, SecretRepositoryMock> {
+    ) -> SessionApplication<'a, T, UserRepositoryMock, SecretRepositoryMock, TokenEventBusMock>
+    {
         let user_repo = UserRepositoryMock::default();
         let secret_repo = SecretRepositoryMock::default();
         let token_app = new_token_application(token_repo);
@@ -158,8 +517,16 @@ pub mod tests {
             user_repo: Arc::new(user_repo),
             secret_repo: Arc::new(secret_repo),
             token_app: Arc::new(token_app),
+            lockout_policy: LockoutPolicy {
+                max_attempts: 3,
+                lockout: Duration::from_secs(60),
+                max_lockout: Duration::from_secs(600),
+            },
             totp_secret_name: ".dummy_totp_secret",
+            totp_policy: TotpPolicy::default(),
+            recovery_code_name: ".dummy_recovery_code",
             pwd_sufix: TEST_DEFAULT_PWD_SUFIX,
+            introspection_secret: Some(TEST_INTROSPECTION_SECRET),
         }
     }
 
@@ -178,7 +545,14 @@ pub mod tests {
         app.secret_repo = Arc::new(secret_repo);
 
         let token = app
-            .login(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD, "")
+            .login(
+                TEST_DEFAULT_USER_EMAIL,
+                TEST_DEFAULT_USER_PASSWORD,
+                "",
+                GenerateOptions::default(),
+                None,
+                None,
+            )
             .await
             .map_err(|err| {
                 println!(
@@ -187,9 +561,46 @@ pub mod tests {
                 )
             })
             .unwrap();
-        let session: Token = crypto::decode_jwt(&PUBLIC_KEY, &token).unwrap();
+        let session: Token = KEYRING.decode(token.session().signature()).unwrap();
+        let refresh: Token = KEYRING.decode(token.refresh().signature()).unwrap();
 
         assert_eq!(session.sub, TEST_FIND_BY_EMAIL_ID.to_string());
+        assert_eq!(refresh.sub, TEST_FIND_BY_EMAIL_ID.to_string());
+        assert_eq!(refresh.knd, TokenKind::Refresh);
+    }
+
+    #[tokio::test]
+    async fn login_with_dpop_proof_should_bind_tokens() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, _: &str| -> Result<Secret> {
+                    Err(Error::NotFound)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_session_application::<TokenRepositoryMock>(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        let proof = new_dpop_proof("POST", "/session.Session/Login", None);
+        let dpop = DpopRequest::new(&proof, "POST", "/session.Session/Login");
+        let token = app
+            .login(
+                TEST_DEFAULT_USER_EMAIL,
+                TEST_DEFAULT_USER_PASSWORD,
+                "",
+                GenerateOptions::default(),
+                Some(&dpop),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let session: Token = KEYRING.decode(token.session().signature()).unwrap();
+        let refresh: Token = KEYRING.decode(token.refresh().signature()).unwrap();
+        assert_eq!(session.cnf.map(|cnf| cnf.jkt), Some(dpop_thumbprint()));
+        assert_eq!(refresh.cnf.map(|cnf| cnf.jkt), Some(dpop_thumbprint()));
     }
 
     #[tokio::test]
@@ -206,7 +617,14 @@ pub mod tests {
         let mut app = new_session_application::<TokenRepositoryMock>(None);
         app.secret_repo = Arc::new(secret_repo);
         let token = app
-            .login(TEST_DEFAULT_USER_NAME, TEST_DEFAULT_USER_PASSWORD, "")
+            .login(
+                TEST_DEFAULT_USER_NAME,
+                TEST_DEFAULT_USER_PASSWORD,
+                "",
+                GenerateOptions::default(),
+                None,
+                None,
+            )
             .await
             .map_err(|err| {
                 println!(
@@ -215,18 +633,26 @@ pub mod tests {
                 )
             })
             .unwrap();
-        let session: Token = crypto::decode_jwt(&PUBLIC_KEY, &token).unwrap();
+        let session: Token = KEYRING.decode(token.session().signature()).unwrap();
         assert_eq!(session.sub, TEST_FIND_BY_NAME_ID.to_string());
     }
 
     #[tokio::test]
     async fn login_with_totp_should_not_fail() {
         let app = new_session_application::<TokenRepositoryMock>(None);
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
         let token = app
-            .login(TEST_DEFAULT_USER_NAME, TEST_DEFAULT_USER_PASSWORD, &code)
+            .login(
+                TEST_DEFAULT_USER_NAME,
+                TEST_DEFAULT_USER_PASSWORD,
+                &code,
+                GenerateOptions::default(),
+                None,
+                None,
+            )
             .await
             .map_err(|err| {
                 println!(
@@ -235,7 +661,70 @@ pub mod tests {
                 )
             })
             .unwrap();
-        let session: Token = crypto::decode_jwt(&PUBLIC_KEY, &token).unwrap();
+        let session: Token = KEYRING.decode(token.session().signature()).unwrap();
+        assert_eq!(session.sub, TEST_FIND_BY_NAME_ID.to_string());
+    }
+
+    #[tokio::test]
+    async fn login_with_recovery_code_should_not_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                Ok(vec![new_recovery_code()])
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_session_application::<TokenRepositoryMock>(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        let token = app
+            .login(
+                TEST_DEFAULT_USER_NAME,
+                TEST_DEFAULT_USER_PASSWORD,
+                TEST_RECOVERY_CODE,
+                GenerateOptions::default(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let session: Token = KEYRING.decode(token.session().signature()).unwrap();
+        assert_eq!(session.sub, TEST_FIND_BY_NAME_ID.to_string());
+    }
+
+    #[tokio::test]
+    async fn login_with_any_authenticator_should_not_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, _: &str| -> Result<Secret> {
+                    Err(Error::NotFound)
+                },
+            ),
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                Ok(vec![new_authenticator()])
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_session_application::<TokenRepositoryMock>(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        let code =
+            crypto::generate_totp(TEST_AUTHENTICATOR_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        let token = app
+            .login(
+                TEST_DEFAULT_USER_NAME,
+                TEST_DEFAULT_USER_PASSWORD,
+                &code,
+                GenerateOptions::default(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let session: Token = KEYRING.decode(token.session().signature()).unwrap();
         assert_eq!(session.sub, TEST_FIND_BY_NAME_ID.to_string());
     }
 
@@ -251,26 +740,121 @@ pub mod tests {
         let mut app = new_session_application::<TokenRepositoryMock>(None);
         app.user_repo = Arc::new(user_repo);
 
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
 
-        app.login(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD, &code)
-            .await
-            .map_err(|err| assert_eq!(err.to_string(), Error::WrongCredentials.to_string()))
-            .unwrap_err();
+        app.login(
+            TEST_DEFAULT_USER_EMAIL,
+            TEST_DEFAULT_USER_PASSWORD,
+            &code,
+            GenerateOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .map_err(|err| assert_eq!(err.to_string(), Error::WrongCredentials.to_string()))
+        .unwrap_err();
     }
 
     #[tokio::test]
     async fn login_wrong_password_should_fail() {
         let app = new_session_application::<TokenRepositoryMock>(None);
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.login(TEST_DEFAULT_USER_NAME, "fake_password", &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.login(
+            TEST_DEFAULT_USER_NAME,
+            "fake_password",
+            &code,
+            GenerateOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .map_err(|err| assert_eq!(err.to_string(), Error::WrongCredentials.to_string()))
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn login_locked_out_should_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_find_many: Some(
+                |_: &TokenRepositoryMock, keys: &[String]| -> Result<Vec<Option<String>>> {
+                    assert_eq!(
+                        keys,
+                        [
+                            format!("Lockout::User::{}", TEST_FIND_BY_NAME_ID),
+                            "Lockout::Addr::127.0.0.1".to_string()
+                        ]
+                    );
+
+                    let now = time::unix_timestamp(SystemTime::now());
+                    Ok(vec![None, Some((now + 30).to_string())])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_session_application(Some(token_repo));
+        let err = app
+            .login(
+                TEST_DEFAULT_USER_NAME,
+                TEST_DEFAULT_USER_PASSWORD,
+                "",
+                GenerateOptions::default(),
+                None,
+                Some("127.0.0.1"),
+            )
             .await
-            .map_err(|err| assert_eq!(err.to_string(), Error::WrongCredentials.to_string()))
             .unwrap_err();
+
+        assert!(matches!(err, Error::Locked(retry_after) if (29..=30).contains(&retry_after)));
+    }
+
+    #[tokio::test]
+    async fn login_too_many_failures_should_lock_out() {
+        let token_repo = TokenRepositoryMock {
+            fn_increment: Some(
+                |_: &TokenRepositoryMock, key: &str, expire: u64| -> Result<u64> {
+                    assert_eq!(key, "Attempts::Ident::unknown@example.com");
+                    assert_eq!(expire, 600);
+                    Ok(3)
+                },
+            ),
+            fn_save: Some(
+                |_: &TokenRepositoryMock, key: &str, _: &str, expire: Option<u64>| -> Result<()> {
+                    assert_eq!(key, "Lockout::Ident::unknown@example.com");
+                    assert_eq!(expire, Some(60));
+                    Err(Error::NotAvailable)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let user_repo = UserRepositoryMock {
+            fn_find_by_email: Some(|_: &UserRepositoryMock, _: &str| -> Result<User> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_session_application(Some(token_repo));
+        app.user_repo = Arc::new(user_repo);
+
+        app.login(
+            "Unknown@example.com",
+            TEST_DEFAULT_USER_PASSWORD,
+            "",
+            GenerateOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .map_err(|err| assert_eq!(err.to_string(), Error::NotAvailable.to_string()))
+        .unwrap_err();
     }
 
     #[tokio::test]
@@ -281,6 +865,47 @@ pub mod tests {
             TEST_DEFAULT_USER_NAME,
             TEST_DEFAULT_USER_PASSWORD,
             "fake_totp",
+            GenerateOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn login_with_replayed_totp_should_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_find_many: Some(
+                |_: &TokenRepositoryMock, keys: &[String]| -> Result<Vec<Option<String>>> {
+                    if !keys[0].starts_with("Totp::") {
+                        return Ok(vec![None; keys.len()]);
+                    }
+
+                    // the current time-step has already been accepted
+                    let now = time::unix_timestamp(SystemTime::now());
+                    Ok(vec![Some(
+                        (now as u64 / TotpPolicy::default().period).to_string(),
+                    )])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_session_application(Some(token_repo));
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+
+        app.login(
+            TEST_DEFAULT_USER_NAME,
+            TEST_DEFAULT_USER_PASSWORD,
+            &code,
+            GenerateOptions::default(),
+            None,
+            None,
         )
         .await
         .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
@@ -289,30 +914,104 @@ pub mod tests {
 
     #[tokio::test]
     async fn logout_should_not_fail() {
-        let token = crypto::sign_jwt(&PRIVATE_KEY, new_token(TokenKind::Session)).unwrap();
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
         let token_repo = TokenRepositoryMock {
             token: token.clone(),
             ..Default::default()
         };
 
         let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
-        app.logout(&token)
+        app.logout(&token, None)
             .await
             .map_err(|err| println!("-\tlogout_should_not_fail has failed with error {}", err))
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn logout_family_should_not_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: "Refresh::dummy".to_string(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.logout(&token, None)
+            .await
+            .map_err(|err| {
+                println!(
+                    "-\tlogout_family_should_not_fail has failed with error {}",
+                    err
+                )
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn logout_family_with_bus_unavailable_should_not_fail() {
+        static FAMILY_REVOKED: AtomicBool = AtomicBool::new(false);
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: "Refresh::dummy".to_string(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            fn_delete: Some(|_: &TokenRepositoryMock, key: &str| -> Result<()> {
+                if key.starts_with("Family::") {
+                    FAMILY_REVOKED.store(true, Ordering::Relaxed);
+                }
+
+                Ok(())
+            }),
+            ..Default::default()
+        };
+
+        let mut token_app = new_token_application(Some(token_repo));
+        token_app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, _: &Token| -> Result<()> {
+                Err(Error::NotAvailable)
+            }),
+        });
+
+        let mut app = new_session_application::<TokenRepositoryMock>(None);
+        app.token_app = Arc::new(token_app);
+
+        app.logout(&token, None).await.unwrap();
+        assert!(FAMILY_REVOKED.load(Ordering::Relaxed));
+    }
+
     #[tokio::test]
     async fn logout_verification_token_kind_should_fail() {
         let token = new_token(TokenKind::Verification);
-        let token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: token.clone(),
             ..Default::default()
         };
 
         let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
-        app.logout(&token)
+        app.logout(&token, None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -321,16 +1020,210 @@ pub mod tests {
     #[tokio::test]
     async fn logout_reset_token_kind_should_fail() {
         let token = new_token(TokenKind::Reset);
-        let token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: token.clone(),
             ..Default::default()
         };
 
         let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
-        app.logout(&token)
+        app.logout(&token, None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
     }
+
+    #[tokio::test]
+    async fn list_sessions_should_not_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find_by_subject: Some(
+                |this: &TokenRepositoryMock, _: &str| -> Result<Vec<String>> {
+                    let refresh = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+                    let mut other = new_token(TokenKind::Session);
+                    other.fam = Some("other_family".to_string());
+                    let other = KEYRING.sign(other).unwrap();
+
+                    Ok(vec![this.token.clone(), refresh, other])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        let sessions = app.list_sessions(&token, None).await.unwrap();
+
+        assert_eq!(sessions.len(), 2);
+        let current = sessions.iter().find(|session| session.current).unwrap();
+        assert_eq!(current.id, "dummy_family");
+        assert!(sessions.iter().any(|session| session.id == "other_family"));
+    }
+
+    #[tokio::test]
+    async fn revoke_session_should_not_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                assert_eq!(key, "Family::other_family");
+                let family = TokenFamily {
+                    session: "Session::other".to_string(),
+                    refresh: "Refresh::other".to_string(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            fn_find_by_subject: Some(
+                |this: &TokenRepositoryMock, _: &str| -> Result<Vec<String>> {
+                    let mut other = new_token(TokenKind::Session);
+                    other.fam = Some("other_family".to_string());
+                    let other = KEYRING.sign(other).unwrap();
+
+                    Ok(vec![this.token.clone(), other])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.revoke_session(&token, "other_family", None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoke_session_not_owned_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.revoke_session(&token, "someone_else_family", None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn logout_everywhere_should_not_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                Err(Error::NotFound)
+            }),
+            fn_delete_by_subject: Some(|_: &TokenRepositoryMock, sub: &str| -> Result<()> {
+                assert_eq!(sub, "999");
+                Ok(())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.logout_everywhere(&token, None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn logout_everywhere_refresh_token_kind_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.logout_everywhere(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn introspect_with_secret_should_not_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        let introspection = app
+            .introspect(TEST_INTROSPECTION_SECRET, &token, Default::default())
+            .await
+            .unwrap();
+
+        assert!(introspection.active);
+    }
+
+    #[tokio::test]
+    async fn introspect_without_secret_should_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.introspect("", &token, Default::default())
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+
+        app.introspect("wrong secret", &token, Default::default())
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn introspect_with_no_secret_set_should_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let mut app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.introspection_secret = None;
+        app.introspect("", &token, Default::default())
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn introspect_opaque_token_without_secret_should_fail() {
+        static RESOLVED: AtomicBool = AtomicBool::new(false);
+        let token_repo = TokenRepositoryMock {
+            token: KEYRING.sign(new_token(TokenKind::Session)).unwrap(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if key.starts_with("Handle::") {
+                    RESOLVED.store(true, Ordering::Relaxed);
+                }
+
+                Ok(this.token.clone())
+            }),
+            ..Default::default()
+        };
+
+        // the claims behind a handle must not be disclosed to whoever holds it
+        let app = new_session_application::<TokenRepositoryMock>(Some(token_repo));
+        app.introspect("", "abc123", Default::default())
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+
+        assert!(!RESOLVED.load(Ordering::Relaxed));
+    }
 }
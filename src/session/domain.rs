@@ -0,0 +1,127 @@
+use crate::token::domain::{Token, TokenDefinition};
+use std::time::Duration;
+
+/// An active login of a user, this is, all the tokens descending from the same login.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActiveSession {
+    pub id: String,    // id of the token family
+    pub iat: usize,    // issued at of the latest token in the session (as UTC timestamp)
+    pub exp: usize, // expiration time of the longest-lived token in the session (as UTC timestamp)
+    pub current: bool, // whether the session is the one the request has been made from
+}
+
+impl ActiveSession {
+    /// Groups the given tokens by the session they belong to, most recent first. Tokens that belong to no
+    /// family are ignored.
+    pub fn from_tokens(tokens: &[Token], current: Option<&str>) -> Vec<ActiveSession> {
+        let mut sessions: Vec<ActiveSession> = Vec::new();
+        for token in tokens {
+            let Some(family) = token.get_family() else {
+                continue;
+            };
+
+            match sessions.iter_mut().find(|session| session.id == family) {
+                Some(session) => {
+                    session.iat = session.iat.max(token.iat);
+                    session.exp = session.exp.max(token.exp);
+                }
+                None => sessions.push(ActiveSession {
+                    id: family.to_string(),
+                    iat: token.iat,
+                    exp: token.exp,
+                    current: current == Some(family),
+                }),
+            }
+        }
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.iat));
+        sessions
+    }
+}
+
+/// How many failed login attempts are tolerated, for a same user or client, before rejecting any further
+/// one for a while. Failed attempts are not tolerated at all if `max_attempts` is zero.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LockoutPolicy {
+    pub max_attempts: u64,
+    /// Lockout applied once `max_attempts` is reached, doubled on each further failed attempt.
+    pub lockout: Duration,
+    /// Longest lockout possible, as well as for how long a failed attempt is kept in mind.
+    pub max_lockout: Duration,
+}
+
+impl LockoutPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_attempts > 0
+    }
+
+    /// Returns for how long any login attempt must be rejected right after the given amount of failed
+    /// attempts in a row.
+    pub fn lockout_for(&self, failures: u64) -> Duration {
+        if !self.is_enabled() || failures < self.max_attempts {
+            return Duration::ZERO;
+        }
+
+        let exponent = (failures - self.max_attempts).min(u32::BITS as u64 - 1) as u32;
+        self.lockout
+            .saturating_mul(2_u32.pow(exponent))
+            .min(self.max_lockout)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{ActiveSession, LockoutPolicy};
+    use crate::time;
+    use crate::token::application::tests::new_token;
+    use crate::token::domain::TokenKind;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn active_sessions_from_tokens_should_not_fail() {
+        let mut session = new_token(TokenKind::Session);
+        session.fam = Some("current".to_string());
+
+        let mut refresh = new_token(TokenKind::Refresh);
+        refresh.fam = Some("current".to_string());
+        refresh.exp = session.exp + 100;
+
+        let mut other = new_token(TokenKind::Session);
+        other.fam = Some("other".to_string());
+        other.iat = time::unix_timestamp(SystemTime::now() - Duration::from_secs(60));
+
+        let orphan = new_token(TokenKind::Session);
+
+        let sessions =
+            ActiveSession::from_tokens(&[other, session, refresh.clone(), orphan], Some("current"));
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, "current");
+        assert!(sessions[0].current);
+        assert_eq!(sessions[0].exp, refresh.exp);
+        assert_eq!(sessions[1].id, "other");
+        assert!(!sessions[1].current);
+    }
+
+    #[test]
+    fn lockout_policy_should_double_lockout() {
+        let policy = LockoutPolicy {
+            max_attempts: 3,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(100),
+        };
+
+        assert_eq!(policy.lockout_for(2), Duration::ZERO);
+        assert_eq!(policy.lockout_for(3), Duration::from_secs(30));
+        assert_eq!(policy.lockout_for(4), Duration::from_secs(60));
+        assert_eq!(policy.lockout_for(5), Duration::from_secs(100));
+        assert_eq!(policy.lockout_for(u64::MAX), Duration::from_secs(100));
+    }
+
+    #[test]
+    fn disabled_lockout_policy_should_never_lock() {
+        let policy = LockoutPolicy::default();
+        assert!(!policy.is_enabled());
+        assert_eq!(policy.lockout_for(100), Duration::ZERO);
+    }
+}
@@ -1,11 +1,15 @@
 use super::application::SessionApplication;
+use super::domain;
 use crate::base64::B64_CUSTOM_ENGINE;
 use crate::secret::application::SecretRepository;
-use crate::token::application::TokenRepository;
+use crate::token::application::{GenerateOptions, TokenEventBus, TokenRepository, VerifyOptions};
+use crate::token::domain::{split_scope, Introspection, TokenPair};
+use crate::token::dpop::DpopRequest;
 use crate::user::application::UserRepository;
 use crate::{grpc, result::Error};
 use base64::Engine;
 use tonic::metadata::errors::InvalidMetadataValue;
+use tonic::metadata::{Ascii, MetadataValue};
 use tonic::{Request, Response, Status};
 
 // Import the generated rust code into module
@@ -13,20 +17,101 @@ mod proto {
     tonic::include_proto!("session");
 }
 
+// The path DPoP proofs are issued for when calling the service's methods
+const SERVICE_PATH: &str = "/session.Session";
+
 // Proto generated server traits
 use proto::session_server::Session;
 pub use proto::session_server::SessionServer;
 
 // Proto message structs
-use proto::{Empty, LoginRequest};
+use proto::{
+    ActiveSession, Empty, IntrospectRequest, IntrospectResponse, LoginRequest,
+    RevokeSessionRequest, SessionList,
+};
 
 pub struct SessionGrpcService<
     T: TokenRepository + Sync + Send,
     U: UserRepository + Sync + Send,
     E: SecretRepository + Sync + Send,
+    B: TokenEventBus + Sync + Send,
 > {
-    pub session_app: SessionApplication<'static, T, U, E>,
+    pub session_app: SessionApplication<'static, T, U, E, B>,
     pub jwt_header: &'static str,
+    pub refresh_header: &'static str,
+    pub dpop_header: &'static str,
+    /// The header to read the client address from, if any, instead of the address of the peer.
+    pub addr_header: Option<&'static str>,
+    /// The header resource servers set the introspection secret in.
+    pub introspection_header: &'static str,
+}
+
+impl<
+        T: TokenRepository + Sync + Send,
+        U: UserRepository + Sync + Send,
+        E: SecretRepository + Sync + Send,
+        B: TokenEventBus + Sync + Send,
+    > SessionGrpcService<T, U, E, B>
+{
+    fn pair_response(&self, pair: TokenPair) -> Result<Response<Empty>, Error> {
+        let mut res = Response::new(Empty {});
+        res.metadata_mut()
+            .append(self.jwt_header, encode_token(pair.session().signature())?);
+        res.metadata_mut().append(
+            self.refresh_header,
+            encode_token(pair.refresh().signature())?,
+        );
+
+        Ok(res)
+    }
+
+    fn dpop_request<R>(
+        &self,
+        request: &Request<R>,
+        method: &str,
+        access_token: Option<&str>,
+    ) -> Result<Option<DpopRequest>, Error> {
+        let path = format!("{}/{}", SERVICE_PATH, method);
+        grpc::get_dpop_request(request, self.dpop_header, &path, access_token)
+    }
+}
+
+impl From<Introspection> for IntrospectResponse {
+    fn from(value: Introspection) -> Self {
+        IntrospectResponse {
+            active: value.active,
+            sub: value.sub.unwrap_or_default(),
+            exp: value.exp.unwrap_or_default() as u64,
+            iat: value.iat.unwrap_or_default() as u64,
+            knd: value.knd.map(|knd| knd.to_string()).unwrap_or_default(),
+            iss: value.iss.unwrap_or_default(),
+            expires_in: value.expires_in.unwrap_or_default() as u64,
+            aud: value.aud.unwrap_or_default(),
+            scope: value.scope.unwrap_or_default(),
+            jkt: value.cnf.map(|cnf| cnf.jkt).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<domain::ActiveSession> for ActiveSession {
+    fn from(value: domain::ActiveSession) -> Self {
+        ActiveSession {
+            id: value.id,
+            iat: value.iat as u64,
+            exp: value.exp as u64,
+            current: value.current,
+        }
+    }
+}
+
+fn encode_token(token: &str) -> Result<MetadataValue<Ascii>, Error> {
+    B64_CUSTOM_ENGINE
+        .encode(token)
+        .parse()
+        .map_err(|err: InvalidMetadataValue| {
+            error!(error = err.to_string(), "parsing token to header");
+            Error::Unknown
+        })
 }
 
 #[tonic::async_trait]
@@ -34,32 +119,130 @@ impl<
         T: 'static + TokenRepository + Sync + Send,
         U: 'static + UserRepository + Sync + Send,
         E: 'static + SecretRepository + Sync + Send,
-    > Session for SessionGrpcService<T, U, E>
+        B: 'static + TokenEventBus + Sync + Send,
+    > Session for SessionGrpcService<T, U, E, B>
 {
     #[instrument(skip(self))]
     async fn login(&self, request: Request<LoginRequest>) -> Result<Response<Empty>, Status> {
+        let dpop = self.dpop_request(&request, "Login", None)?;
+        let addr = grpc::get_client_addr(&request, self.addr_header);
+        let msg_ref = request.into_inner();
+        let options = GenerateOptions {
+            audience: (!msg_ref.audience.is_empty()).then_some(msg_ref.audience),
+            scopes: split_scope(&msg_ref.scope),
+            ..Default::default()
+        };
+
+        let pair = self
+            .session_app
+            .login(
+                &msg_ref.ident,
+                &msg_ref.pwd,
+                &msg_ref.totp,
+                options,
+                dpop.as_ref(),
+                addr.as_deref(),
+            )
+            .await
+            .map_err(|err| match err {
+                // the lockout status carries the time to wait before trying again
+                Error::Locked(_) => err.into(),
+                err => Status::aborted(err.to_string()),
+            })?;
+
+        self.pair_response(pair).map_err(Into::into)
+    }
+
+    #[instrument(skip(self))]
+    async fn logout(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = self.dpop_request(&request, "Logout", Some(&token))?;
+        if let Err(err) = self.session_app.logout(&token, dpop.as_ref()).await {
+            return Err(Status::aborted(err.to_string()));
+        }
+
+        Ok(Response::new(Empty {}))
+    }
+
+    #[instrument(skip(self))]
+    async fn refresh(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        let token = grpc::get_encoded_header(&request, self.refresh_header)?;
+        let dpop = self.dpop_request(&request, "Refresh", None)?;
+        let pair = self
+            .session_app
+            .refresh(&token, dpop.as_ref())
+            .await
+            .map_err(|err| Status::aborted(err.to_string()))?;
+
+        self.pair_response(pair).map_err(Into::into)
+    }
+
+    #[instrument(skip(self))]
+    async fn introspect(
+        &self,
+        request: Request<IntrospectRequest>,
+    ) -> Result<Response<IntrospectResponse>, Status> {
+        let secret = grpc::get_header(&request, self.introspection_header).unwrap_or_default();
         let msg_ref = request.into_inner();
-        let token = self
+        let options = VerifyOptions {
+            audience: (!msg_ref.audience.is_empty()).then_some(msg_ref.audience),
+            scopes: split_scope(&msg_ref.scope),
+            ..Default::default()
+        };
+
+        let introspection = self
+            .session_app
+            .introspect(&secret, &msg_ref.token, options)
+            .await?;
+        Ok(Response::new(introspection.into()))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_sessions(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<SessionList>, Status> {
+        let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = self.dpop_request(&request, "ListSessions", Some(&token))?;
+        let sessions = self
             .session_app
-            .login(&msg_ref.ident, &msg_ref.pwd, &msg_ref.totp)
+            .list_sessions(&token, dpop.as_ref())
             .await
-            .map(|token| B64_CUSTOM_ENGINE.encode(token))
             .map_err(|err| Status::aborted(err.to_string()))?;
 
-        let mut res = Response::new(Empty {});
-        let token = token.parse().map_err(|err: InvalidMetadataValue| {
-            error!(error = err.to_string(), "parsing token to header");
-            Into::<Status>::into(Error::Unknown)
-        })?;
+        Ok(Response::new(SessionList {
+            sessions: sessions.into_iter().map(Into::into).collect(),
+        }))
+    }
 
-        res.metadata_mut().append(self.jwt_header, token);
-        Ok(res)
+    #[instrument(skip(self))]
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = self.dpop_request(&request, "RevokeSession", Some(&token))?;
+        let msg_ref = request.into_inner();
+        if let Err(err) = self
+            .session_app
+            .revoke_session(&token, &msg_ref.id, dpop.as_ref())
+            .await
+        {
+            return Err(Status::aborted(err.to_string()));
+        }
+
+        Ok(Response::new(Empty {}))
     }
 
     #[instrument(skip(self))]
-    async fn logout(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+    async fn logout_everywhere(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
         let token = grpc::get_encoded_header(&request, self.jwt_header)?;
-        if let Err(err) = self.session_app.logout(&token).await {
+        let dpop = self.dpop_request(&request, "LogoutEverywhere", Some(&token))?;
+        if let Err(err) = self
+            .session_app
+            .logout_everywhere(&token, dpop.as_ref())
+            .await
+        {
             return Err(Status::aborted(err.to_string()));
         }
 
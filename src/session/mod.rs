@@ -1,5 +1,6 @@
 pub mod application;
-#[cfg(all(feature = "grpc", feature = "postgres"))]
+pub mod domain;
+#[cfg(feature = "grpc")]
 pub mod grpc;
-#[cfg(all(feature = "rest", feature = "postgres"))]
+#[cfg(feature = "rest")]
 pub mod rest;
@@ -1,56 +1,184 @@
+use super::application::SessionApplication;
+use crate::base64::B64_CUSTOM_ENGINE;
+use crate::secret::application::SecretRepository;
+use crate::user::application::UserRepository;
 use crate::{
     http,
-    token::application::{TokenApplication, TokenRepository},
-    token::{application::VerifyOptions, domain::TokenKind},
+    token::application::{GenerateOptions, TokenEventBus, TokenRepository},
+    token::{
+        application::VerifyOptions,
+        domain::{split_scope, TokenKind, TokenPair},
+    },
 };
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
 use std::sync::Arc;
 
-use super::application;
+#[derive(Deserialize)]
+struct LoginBody {
+    ident: String,
+    pwd: String,
+    #[serde(default)]
+    totp: String,
+    audience: Option<String>,
+    scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IntrospectForm {
+    token: String,
+    audience: Option<String>,
+    scope: Option<String>,
+}
+
+/// The audience and scopes a token must satisfy in order to be accepted.
+#[derive(Debug, Deserialize)]
+struct AudienceQuery {
+    audience: Option<String>,
+    scope: Option<String>,
+}
+
+impl AudienceQuery {
+    fn verify_options(&self, kind: Option<TokenKind>) -> VerifyOptions {
+        VerifyOptions {
+            kind,
+            audience: self.audience.clone(),
+            scopes: self.scope.as_deref().map(split_scope).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
 
-pub struct SessionRestService<T: TokenRepository + Sync + Send> {
-    pub token_app: TokenApplication<'static, T>,
+pub struct SessionRestService<
+    T: TokenRepository + Sync + Send,
+    U: UserRepository + Sync + Send,
+    E: SecretRepository + Sync + Send,
+    B: TokenEventBus + Sync + Send,
+> {
+    pub session_app: SessionApplication<'static, T, U, E, B>,
     pub jwt_header: &'static str,
+    pub refresh_header: &'static str,
+    pub dpop_header: &'static str,
+    /// The header to read the client address from, if any, instead of the address of the peer.
+    pub addr_header: Option<&'static str>,
+    /// The header resource servers set the introspection secret in.
+    pub introspection_header: &'static str,
 }
 
-impl<T: 'static + TokenRepository + Sync + Send> SessionRestService<T> {
+impl<
+        T: 'static + TokenRepository + Sync + Send,
+        U: 'static + UserRepository + Sync + Send,
+        E: 'static + SecretRepository + Sync + Send,
+        B: 'static + TokenEventBus + Sync + Send,
+    > SessionRestService<T, U, E, B>
+{
     pub fn router(&self) -> impl Fn(&mut web::ServiceConfig) {
         |cfg: &mut web::ServiceConfig| {
             cfg.service(web::resource("/session").route(web::get().to(Self::get_session)));
+            cfg.service(web::resource("/session").route(web::post().to(Self::create_session)));
             cfg.service(web::resource("/session").route(web::delete().to(Self::delete_session)));
+            cfg.service(
+                web::resource("/session/refresh").route(web::post().to(Self::refresh_session)),
+            );
+            cfg.service(web::resource("/sessions").route(web::get().to(Self::list_sessions)));
+            cfg.service(
+                web::resource("/sessions").route(web::delete().to(Self::delete_all_sessions)),
+            );
+            cfg.service(
+                web::resource("/sessions/{id}").route(web::delete().to(Self::delete_one_session)),
+            );
+            cfg.service(
+                web::resource("/session/introspect")
+                    .route(web::post().to(Self::introspect_session)),
+            );
+            cfg.service(
+                web::resource("/session/revocations").route(web::get().to(Self::list_revocations)),
+            );
         }
     }
 
+    fn pair_response(&self, pair: TokenPair) -> HttpResponse {
+        HttpResponse::Ok()
+            .insert_header((
+                self.jwt_header,
+                B64_CUSTOM_ENGINE.encode(pair.session().signature()),
+            ))
+            .insert_header((
+                self.refresh_header,
+                B64_CUSTOM_ENGINE.encode(pair.refresh().signature()),
+            ))
+            .finish()
+    }
+
     #[instrument(skip(app_data))]
     async fn get_session(
-        app_data: web::Data<Arc<SessionRestService<T>>>,
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
         req: HttpRequest,
+        query: web::Query<AudienceQuery>,
     ) -> impl Responder {
         match async move {
-            let token = http::get_encoded_header(req, app_data.jwt_header)?;
-            let token = app_data.token_app.decode(&token).await?;
+            let token_app = &app_data.session_app.token_app;
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            let token = token_app.decode(&token).await?;
+
+            let options = VerifyOptions {
+                dpop,
+                ..query.verify_options(Some(TokenKind::Session))
+            };
+
+            token_app.verify(&token, options).await.map(|_| token)
+        }
+        .await
+        {
+            Ok(token) => HttpResponse::Accepted().json(token),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data, body))]
+    async fn create_session(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+        req: HttpRequest,
+        body: web::Json<LoginBody>,
+    ) -> impl Responder {
+        match async {
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, None)?;
+            let addr = http::get_client_addr(&req, app_data.addr_header);
+            let options = GenerateOptions {
+                audience: body.audience.clone(),
+                scopes: body.scope.as_deref().map(split_scope).unwrap_or_default(),
+                ..Default::default()
+            };
 
             app_data
-                .token_app
-                .verify(&token, VerifyOptions::new(TokenKind::Session))
+                .session_app
+                .login(
+                    &body.ident,
+                    &body.pwd,
+                    &body.totp,
+                    options,
+                    dpop.as_ref(),
+                    addr.as_deref(),
+                )
                 .await
-                .map(|_| token)
         }
         .await
         {
-            Ok(token) => HttpResponse::Accepted().json(token),
+            Ok(pair) => app_data.pair_response(pair),
             Err(err) => HttpResponse::from(err),
         }
     }
 
     #[instrument(skip(app_data))]
     async fn delete_session(
-        app_data: web::Data<Arc<SessionRestService<T>>>,
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
         req: HttpRequest,
     ) -> impl Responder {
         match async move {
-            let token = http::get_encoded_header(req, app_data.jwt_header)?;
-            application::logout_strategy::<T>(&app_data.token_app, &token).await
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data.session_app.logout(&token, dpop.as_ref()).await
         }
         .await
         {
@@ -58,4 +186,114 @@ impl<T: 'static + TokenRepository + Sync + Send> SessionRestService<T> {
             Err(err) => HttpResponse::from(err),
         }
     }
+
+    #[instrument(skip(app_data))]
+    async fn refresh_session(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        match async {
+            let token = http::get_encoded_header(req.clone(), app_data.refresh_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, None)?;
+            app_data.session_app.refresh(&token, dpop.as_ref()).await
+        }
+        .await
+        {
+            Ok(pair) => app_data.pair_response(pair),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data))]
+    async fn list_sessions(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        match async move {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .session_app
+                .list_sessions(&token, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(sessions) => HttpResponse::Ok().json(sessions),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data))]
+    async fn delete_one_session(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+        req: HttpRequest,
+        path: web::Path<String>,
+    ) -> impl Responder {
+        match async move {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .session_app
+                .revoke_session(&token, &path, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(_) => HttpResponse::Ok().finish(),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data))]
+    async fn delete_all_sessions(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        match async move {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .session_app
+                .logout_everywhere(&token, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(_) => HttpResponse::Ok().finish(),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data, req, form))]
+    async fn introspect_session(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+        req: HttpRequest,
+        form: web::Form<IntrospectForm>,
+    ) -> impl Responder {
+        let secret = http::get_header(req, app_data.introspection_header).unwrap_or_default();
+        let query = AudienceQuery {
+            audience: form.audience.clone(),
+            scope: form.scope.clone(),
+        };
+
+        match app_data
+            .session_app
+            .introspect(&secret, &form.token, query.verify_options(None))
+            .await
+        {
+            Ok(introspection) => HttpResponse::Ok().json(introspection),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data))]
+    async fn list_revocations(
+        app_data: web::Data<Arc<SessionRestService<T, U, E, B>>>,
+    ) -> impl Responder {
+        match app_data.session_app.token_app.revocations().await {
+            Ok(revocations) => HttpResponse::Ok().json(revocations),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
 }
@@ -1,34 +1,87 @@
-use super::domain::SignedToken;
-use super::domain::{Token, TokenDefinition, TokenKind};
-use crate::crypto;
+use super::domain::{Confirmation, Introspection, Revocation, RevocationScope};
+use super::domain::{SignedToken, TokenFamily};
+use super::domain::{Token, TokenDefinition, TokenFormat, TokenKind, TtlPolicy};
+use super::domain::{TokenPair, TOKEN_TYPE_GENERIC};
+use super::dpop::{DpopProof, DpopRequest, DPOP_PROOF_MAX_AGE};
+use super::keyring::{KeyRing, TrustedIssuers};
 use crate::result::{Error, Result};
+use crate::{crypto, time};
 use async_trait::async_trait;
+use std::num::ParseIntError;
+use std::slice;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::SystemTime;
+
+const TOKEN_FAMILY_PREFIX: &str = "Family";
+const TOKEN_FAMILY_ID_LEN: usize = 32;
+const TOKEN_HANDLE_PREFIX: &str = "Handle";
+const TOKEN_NOT_BEFORE_PREFIX: &str = "NotBefore";
+const TOKEN_DPOP_PREFIX: &str = "Dpop";
+const TOKEN_REVOKED_PREFIX: &str = "Revoked";
+// the subject all the entries of the revocation list are registered for
+const TOKEN_REVOCATION_LIST: &str = "::revocations";
+const TOKEN_HANDLE_LEN: usize = 48;
 
 #[async_trait]
 pub trait TokenRepository {
     async fn find(&self, key: &str) -> Result<String>;
+    /// Returns the tokens for the given keys in the same order, or none for those keys not present.
+    async fn find_many(&self, keys: &[String]) -> Result<Vec<Option<String>>>;
     async fn save(&self, key: &str, token: &str, expire: Option<u64>) -> Result<()>;
+    /// Saves the given token only if there is no other token with the same key, as a single atomic
+    /// operation. Returns whether the token has been saved or not.
+    async fn insert(&self, key: &str, token: &str, expire: Option<u64>) -> Result<bool>;
     async fn delete(&self, key: &str) -> Result<()>;
+    /// Increments by one the counter with the given key, starting from zero if there is no such counter,
+    /// and returns its new value, as a single atomic operation. The counter expires after the given time
+    /// since its very last increment.
+    async fn increment(&self, key: &str, expire: u64) -> Result<u64>;
+    /// Sets the remaining time to live of an already existing token, failing if there is no such token.
+    async fn touch(&self, key: &str, expire: u64) -> Result<()>;
+    /// Removes the token with the given key and returns it, as a single atomic operation.
+    async fn consume(&self, key: &str) -> Result<String>;
+    /// Registers the given key as belonging to the given subject for, at least, the given time.
+    async fn index(&self, sub: &str, key: &str, expire: Option<u64>) -> Result<()>;
+    /// Returns all the tokens still present among those registered for the given subject.
+    async fn find_by_subject(&self, sub: &str) -> Result<Vec<String>>;
+    /// Removes all the tokens registered for the given subject.
+    async fn delete_by_subject(&self, sub: &str) -> Result<()>;
+}
+
+#[async_trait]
+pub trait TokenEventBus {
+    async fn emit_token_revoked(&self, token: &Token) -> Result<()>;
 }
 
-pub struct TokenApplication<'a, T: TokenRepository> {
+pub struct TokenApplication<'a, T: TokenRepository, B: TokenEventBus> {
     pub token_repo: Arc<T>,
-    pub timeout: Duration,
+    pub event_bus: Arc<B>,
+    pub ttl_policy: TtlPolicy,
+    pub token_format: TokenFormat,
     pub token_issuer: &'a str,
-    pub private_key: &'a [u8],
-    pub public_key: &'a [u8],
+    pub keyring: &'a KeyRing,
+    pub trusted_issuers: &'a TrustedIssuers,
+    pub secret_key: &'a [u8],
 }
 
 #[derive(Debug, Clone)]
 pub struct GenerateOptions {
     pub store: bool,
+    pub family: Option<String>,
+    pub audience: Option<String>,
+    pub scopes: Vec<String>,
+    pub jkt: Option<String>, // thumbprint of the DPoP key the token gets bound to
 }
 
 impl Default for GenerateOptions {
     fn default() -> Self {
-        Self { store: true }
+        Self {
+            store: true,
+            family: None,
+            audience: None,
+            scopes: Vec::new(),
+            jkt: None,
+        }
     }
 }
 
@@ -36,6 +89,9 @@ impl Default for GenerateOptions {
 pub struct VerifyOptions {
     pub must_exists: bool,
     pub kind: Option<TokenKind>,
+    pub audience: Option<String>,
+    pub scopes: Vec<String>,
+    pub dpop: Option<DpopRequest>, // required by those tokens bound to a DPoP key
 }
 
 impl Default for VerifyOptions {
@@ -43,6 +99,9 @@ impl Default for VerifyOptions {
         Self {
             must_exists: true,
             kind: None,
+            audience: None,
+            scopes: Vec::new(),
+            dpop: None,
         }
     }
 }
@@ -56,7 +115,7 @@ impl VerifyOptions {
     }
 }
 
-impl<'a, T: TokenRepository> TokenApplication<'a, T> {
+impl<'a, T: TokenRepository, B: TokenEventBus> TokenApplication<'a, T, B> {
     #[instrument(skip(self))]
     pub async fn generate(
         &self,
@@ -65,24 +124,423 @@ impl<'a, T: TokenRepository> TokenApplication<'a, T> {
         secret: Option<&str>,
         options: GenerateOptions,
     ) -> Result<SignedToken> {
-        let token = Token::new(self.token_issuer, sub, self.timeout, kind, secret);
-        let signed = crypto::sign_jwt(self.private_key, &token)?;
+        let timeout = self.ttl_policy.ttl(&kind);
+        let expire = self.ttl_policy.storage_ttl(&kind);
+        let mut token = Token::new(self.token_issuer, sub, timeout, kind, secret);
+        token.fam = options.family;
+        token.aud = options.audience;
+        token.scope = (!options.scopes.is_empty()).then(|| options.scopes.join(" "));
+        token.cnf = options.jkt.map(|jkt| Confirmation { jkt });
+        token.seal_secret(self.secret_key)?;
+
+        let signed = self.keyring.sign_typed(&token, token.knd.typ())?;
+
+        if !options.store {
+            return Ok(SignedToken {
+                id: token.get_id(),
+                signature: signed,
+            });
+        }
+
+        self.token_repo
+            .save(&token.get_id(), &signed, Some(expire.as_secs()))
+            .await?;
 
-        if options.store {
-            self.token_repo
-                .save(&token.get_id(), &signed, Some(self.timeout.as_secs()))
-                .await?;
+        if self.token_format == TokenFormat::Jwt {
+            return Ok(SignedToken {
+                id: token.get_id(),
+                signature: signed,
+            });
         }
 
+        // the handle outlives the token whenever the latter is revoked, yet it does not matter since any
+        // token is checked to be present in the repository when verified
+        let handle = crypto::get_random_string(TOKEN_HANDLE_LEN);
+        self.token_repo
+            .save(&Self::handle_key(&handle), &signed, Some(timeout.as_secs()))
+            .await?;
+
         Ok(SignedToken {
             id: token.get_id(),
-            signature: signed,
+            signature: handle,
+        })
+    }
+
+    /// Generates a new session token along with its refresh token, both of them belonging to a brand new
+    /// token family, no matter the family set in the given options.
+    #[instrument(skip(self))]
+    pub async fn generate_pair(&self, sub: &str, options: GenerateOptions) -> Result<TokenPair> {
+        let family = crypto::get_random_string(TOKEN_FAMILY_ID_LEN);
+        self.generate_family_pair(sub, &family, options).await
+    }
+
+    /// Given a refresh token, revokes it and returns a new pair of tokens from the same family. If the
+    /// refresh token has already been used, the whole family gets revoked. The DPoP proof is required
+    /// if, and only if, the refresh token is bound to a key, in which case so is the new pair.
+    #[instrument(skip(self))]
+    pub async fn refresh(&self, token: &str, dpop: Option<&DpopRequest>) -> Result<TokenPair> {
+        let claims = self.decode(token).await?;
+
+        // the possession is proven once the token gets consumed, since a proof can only be used once
+        self.verify_claims(
+            &claims,
+            VerifyOptions {
+                must_exists: false,
+                ..VerifyOptions::new(TokenKind::Refresh)
+            },
+            false,
+        )
+        .await?;
+
+        let family_id = claims.get_family().ok_or_else(|| {
+            warn!(token_id = claims.get_id(), "refresh token has no family");
+            Error::InvalidToken
+        })?;
+
+        let family = self.find_family(family_id).await?;
+        if family.refresh != claims.get_id() {
+            warn!(
+                token_id = claims.get_id(),
+                family_id, "refresh token reuse detected, revoking family",
+            );
+
+            self.revoke_family(family_id).await?;
+            return Err(Error::InvalidToken);
+        }
+
+        // consuming the refresh token prevents two concurrent calls from renewing the family twice
+        let options = VerifyOptions {
+            dpop: dpop.cloned(),
+            ..VerifyOptions::new(TokenKind::Refresh)
+        };
+
+        self.consume(&claims, options).await?;
+
+        self.discard(&family.session).await?;
+
+        // the renewed pair is granted the very same audience, scopes and key as the original one
+        let options = GenerateOptions {
+            audience: claims.aud.clone(),
+            scopes: claims.scopes().map(ToString::to_string).collect(),
+            jkt: claims.cnf.as_ref().map(|cnf| cnf.jkt.clone()),
+            ..Default::default()
+        };
+
+        self.generate_family_pair(&claims.sub, family_id, options)
+            .await
+    }
+
+    async fn generate_family_pair(
+        &self,
+        sub: &str,
+        family_id: &str,
+        options: GenerateOptions,
+    ) -> Result<TokenPair> {
+        let options = GenerateOptions {
+            store: true,
+            family: Some(family_id.to_string()),
+            ..options
+        };
+
+        let session = self
+            .generate(TokenKind::Session, sub, None, options.clone())
+            .await?;
+
+        let refresh = self
+            .generate(TokenKind::Refresh, sub, None, options)
+            .await?;
+
+        self.token_repo
+            .index(sub, session.id(), Some(self.ttl_policy.session.as_secs()))
+            .await?;
+        self.token_repo
+            .index(sub, refresh.id(), Some(self.ttl_policy.refresh.as_secs()))
+            .await?;
+
+        let family = TokenFamily {
+            session: session.id().to_string(),
+            refresh: refresh.id().to_string(),
+        };
+
+        let family = serde_json::to_string(&family).map_err(|err| {
+            error!(error = err.to_string(), "serializing token family to json");
+            Error::Unknown
+        })?;
+
+        self.token_repo
+            .save(
+                &Self::family_key(family_id),
+                &family,
+                Some(self.ttl_policy.refresh.as_secs()),
+            )
+            .await?;
+
+        Ok(TokenPair { session, refresh })
+    }
+
+    async fn find_family(&self, family_id: &str) -> Result<TokenFamily> {
+        let family = self
+            .token_repo
+            .find(&Self::family_key(family_id))
+            .await
+            // some repositories find an empty value for any missing key
+            .and_then(|family| {
+                if family.is_empty() {
+                    return Err(Error::NotFound);
+                }
+
+                Ok(family)
+            })
+            .map_err(|err| {
+                warn!(error = err.to_string(), family_id, "finding token family");
+                Error::InvalidToken
+            })?;
+
+        serde_json::from_str(&family).map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "deserializing token family from json"
+            );
+            Error::Unknown
+        })
+    }
+
+    /// Revokes all the tokens that are still alive for the given family.
+    #[instrument(skip(self))]
+    pub async fn revoke_family(&self, family_id: &str) -> Result<()> {
+        let family = self.find_family(family_id).await?;
+        self.discard(&family.session).await?;
+        self.discard(&family.refresh).await?;
+        self.token_repo.delete(&Self::family_key(family_id)).await
+    }
+
+    /// Removes the token with the given id from the repository, if still there, and announces its
+    /// revocation.
+    async fn discard(&self, key: &str) -> Result<()> {
+        let signed = match self.token_repo.find(key).await {
+            Ok(signed) => signed,
+            Err(Error::NotFound) => return Ok(()), // the token has already expired or been revoked
+            Err(err) => return Err(err),
+        };
+
+        self.token_repo.delete(key).await?;
+
+        // a token that cannot be decoded anymore is not going to be accepted by anyone either
+        match self.decode_signed(&signed) {
+            Ok(token) => self.announce_revocation(&token).await,
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn revoked_key(jti: &str) -> String {
+        format!("{}::{}", TOKEN_REVOKED_PREFIX, jti)
+    }
+
+    /// Adds the given token to the revocation list until it expires and emits the corresponding event, so
+    /// those verifying tokens by their own stop accepting it as well. The event is emitted on a best-effort
+    /// basis, so an unavailable bus never leaves a revocation half done.
+    #[instrument(skip(self))]
+    pub async fn announce_revocation(&self, token: &Token) -> Result<()> {
+        let now = time::unix_timestamp(SystemTime::now());
+        let expire = token.exp.saturating_sub(now) as u64;
+        if expire == 0 {
+            // an expired token is rejected anyway
+            return Ok(());
+        }
+
+        let revocation = serde_json::to_string(&Revocation::from(token)).map_err(|err| {
+            error!(error = err.to_string(), "serializing revocation to json");
+            Error::Unknown
+        })?;
+
+        let key = Self::revoked_key(&token.jti);
+        self.token_repo
+            .save(&key, &revocation, Some(expire))
+            .await?;
+        self.token_repo
+            .index(TOKEN_REVOCATION_LIST, &key, Some(expire))
+            .await?;
+
+        // whoever misses the event can still catch up from the revocation list
+        if let Err(err) = self.event_bus.emit_token_revoked(token).await {
+            error!(
+                error = err.to_string(),
+                token_id = token.get_id(),
+                "emitting token revoked event",
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns all the tokens that have been revoked before their expiration and have not expired yet.
+    #[instrument(skip(self))]
+    pub async fn revocations(&self) -> Result<Vec<Revocation>> {
+        let mut revocations = self
+            .token_repo
+            .find_by_subject(TOKEN_REVOCATION_LIST)
+            .await?
+            .iter()
+            .map(|revocation| {
+                serde_json::from_str(revocation).map_err(|err| {
+                    error!(
+                        error = err.to_string(),
+                        "deserializing revocation from json"
+                    );
+                    Error::Unknown
+                })
+            })
+            .collect::<Result<Vec<Revocation>>>()?;
+
+        revocations.sort_by_key(|revocation| revocation.exp);
+        Ok(revocations)
+    }
+
+    /// Returns the claims of all the tokens issued to the given subject that are still alive.
+    #[instrument(skip(self))]
+    pub async fn find_by_subject(&self, sub: &str) -> Result<Vec<Token>> {
+        let tokens = self.token_repo.find_by_subject(sub).await?;
+        Ok(tokens
+            .iter()
+            .filter_map(|token| self.decode_signed(token).ok())
+            .collect())
+    }
+
+    /// Revokes all the tokens issued to the given subject, as well as the families they belong to.
+    #[instrument(skip(self))]
+    pub async fn revoke_subject(&self, sub: &str) -> Result<()> {
+        let tokens = self.find_by_subject(sub).await?;
+        let mut families: Vec<&str> = tokens
+            .iter()
+            .filter_map(|token| token.get_family())
+            .collect();
+
+        families.sort();
+        families.dedup();
+
+        for family_id in families {
+            match self.revoke_family(family_id).await {
+                Ok(_) | Err(Error::InvalidToken) => {} // the family may be already gone
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.token_repo.delete_by_subject(sub).await?;
+
+        // the tokens within a family have already been announced when revoking the family
+        for token in tokens.iter().filter(|token| token.get_family().is_none()) {
+            self.announce_revocation(token).await?;
+        }
+
+        Ok(())
+    }
+
+    fn family_key(family_id: &str) -> String {
+        format!("{}::{}", TOKEN_FAMILY_PREFIX, family_id)
+    }
+
+    fn not_before_key(scope: &RevocationScope) -> String {
+        format!("{}::{}", TOKEN_NOT_BEFORE_PREFIX, scope)
+    }
+
+    /// Makes all the tokens within the given scope that were issued before the given time, as a unix
+    /// timestamp, no longer valid, even if they are still present in the repository. Those issued within
+    /// the very second of the epoch are kept valid, so a session started right after revoking is not
+    /// rejected. An epoch can only be moved forward, so any later epoch already set for the same scope
+    /// is kept.
+    #[instrument(skip(self))]
+    pub async fn revoke_before(&self, scope: &RevocationScope, epoch: usize) -> Result<()> {
+        let key = Self::not_before_key(scope);
+        let epoch = epoch.max(self.not_before(slice::from_ref(&key)).await?);
+
+        // once every token issued before the epoch has expired, the epoch is no longer required
+        let now = time::unix_timestamp(SystemTime::now());
+        let expire = self.ttl_policy.max().as_secs() + epoch.saturating_sub(now) as u64;
+
+        self.token_repo
+            .save(&key, &epoch.to_string(), Some(expire))
+            .await
+    }
+
+    /// Returns the latest among the epochs stored under the given keys, or zero if there is none.
+    async fn not_before(&self, keys: &[String]) -> Result<usize> {
+        let epochs = self.token_repo.find_many(keys).await?;
+        epochs.into_iter().flatten().try_fold(0, |latest, epoch| {
+            let epoch: usize = epoch.parse().map_err(|err: ParseIntError| {
+                error!(error = err.to_string(), "parsing not before epoch");
+                Error::Unknown
+            })?;
+
+            Ok(latest.max(epoch))
         })
     }
 
+    fn handle_key(handle: &str) -> String {
+        format!("{}::{}", TOKEN_HANDLE_PREFIX, handle)
+    }
+
+    /// Returns the claims of the given token, which may be either a JWT or the handle of an opaque token.
     #[instrument(skip(self))]
     pub async fn decode(&self, token: &str) -> Result<Token> {
-        crypto::decode_jwt(self.public_key, token)
+        if token.contains('.') {
+            return self.decode_signed(token);
+        }
+
+        let signed = self
+            .token_repo
+            .find(&Self::handle_key(token))
+            .await
+            .map_err(|err| {
+                warn!(error = err.to_string(), "finding token by handle");
+                Error::InvalidToken
+            })?;
+
+        self.decode_signed(&signed)
+    }
+
+    fn decode_signed(&self, signed: &str) -> Result<Token> {
+        let iss = crypto::decode_jwt_iss(signed)?;
+        let mut claims: Token = match iss.filter(|iss| self.is_peer(iss)) {
+            Some(iss) => self.decode_peer(&iss, signed)?,
+            None => self.keyring.decode(signed)?,
+        };
+
+        // tokens issued before the type header was set per kind are all of the generic type
+        let typ = crypto::decode_jwt_typ(signed)?;
+        if let Some(typ) = typ.filter(|typ| typ != TOKEN_TYPE_GENERIC) {
+            if typ != claims.knd.typ() {
+                warn!(
+                    token_id = claims.get_id(),
+                    token_type = typ,
+                    "checking token's type",
+                );
+                return Err(Error::InvalidToken);
+            }
+        }
+
+        claims.unseal_secret(self.secret_key)?;
+        Ok(claims)
+    }
+
+    /// Returns true if, and only if, the given issuer is a trusted peer rather than this very issuer.
+    fn is_peer(&self, iss: &str) -> bool {
+        iss != self.token_issuer && self.trusted_issuers.is_trusted(iss)
+    }
+
+    /// Returns the claims of the given token issued by a trusted peer, which must be a session token, since
+    /// no other kind of token is meant to leave the issuer that signed it.
+    fn decode_peer(&self, iss: &str, signed: &str) -> Result<Token> {
+        let claims: Token = self.trusted_issuers.decode(iss, signed)?;
+        if claims.iss != iss || *claims.get_kind() != TokenKind::Session {
+            warn!(
+                token_id = claims.get_id(),
+                token_issuer = claims.iss,
+                "checking peer token",
+            );
+            return Err(Error::InvalidToken);
+        }
+
+        Ok(claims)
     }
 
     #[instrument(skip(self))]
@@ -92,8 +550,21 @@ impl<'a, T: TokenRepository> TokenApplication<'a, T> {
         Ok(claims)
     }
 
+    /// Checks the given token satisfies all the given options and, if it is bound to a DPoP key, that the
+    /// proof of possession of that key is valid.
     #[instrument(skip(self))]
     pub async fn verify(&self, token: &Token, options: VerifyOptions) -> Result<()> {
+        self.verify_claims(token, options, true).await
+    }
+
+    /// Same as [`TokenApplication::verify`], but the proof of possession is only checked if `prove` is
+    /// set to true.
+    async fn verify_claims(
+        &self,
+        token: &Token,
+        options: VerifyOptions,
+        prove: bool,
+    ) -> Result<()> {
         if let Some(kind) = options.kind {
             if *token.get_kind() != kind {
                 warn!(
@@ -106,7 +577,47 @@ impl<'a, T: TokenRepository> TokenApplication<'a, T> {
             }
         }
 
-        if options.must_exists {
+        if let Some(audience) = options.audience {
+            if token.aud.as_ref() != Some(&audience) {
+                warn!(
+                    token_id = token.get_id(),
+                    token_audience = token.aud,
+                    expected_audience = audience,
+                    "checking token's audience",
+                );
+                return Err(Error::InvalidToken);
+            }
+        }
+
+        if !token.has_scopes(&options.scopes) {
+            warn!(
+                token_id = token.get_id(),
+                token_scope = token.scope,
+                expected_scopes = options.scopes.join(" "),
+                "checking token's scopes",
+            );
+            return Err(Error::InvalidToken);
+        }
+
+        let keys: Vec<String> = RevocationScope::of(token)
+            .iter()
+            .map(Self::not_before_key)
+            .collect();
+
+        let not_before = self.not_before(&keys).await?;
+        if token.iat < not_before {
+            warn!(
+                token_id = token.get_id(),
+                not_before, "checking token's issuing time",
+            );
+            return Err(Error::InvalidToken);
+        }
+
+        // the tokens of trusted peers may not have been stored locally at all
+        let must_exists =
+            options.must_exists && (!self.is_peer(&token.iss) || self.trusted_issuers.must_exist());
+
+        if must_exists {
             let key = token.get_id();
             let present_data = self.token_repo.find(&key).await.map_err(|err| {
                 warn!(
@@ -122,11 +633,161 @@ impl<'a, T: TokenRepository> TokenApplication<'a, T> {
                 error!(token_id = key, "token does not match");
                 return Err(Error::InvalidToken);
             }
+
+            if *token.get_kind() == TokenKind::Session {
+                self.extend_idle_session(token).await?;
+            }
+        }
+
+        if prove {
+            self.check_possession(token, options.dpop.as_ref()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the proof of possession of the key the given token is bound to, if any.
+    async fn check_possession(&self, token: &Token, dpop: Option<&DpopRequest>) -> Result<()> {
+        let Some(cnf) = &token.cnf else {
+            return Ok(()); // bearer tokens require no proof at all
+        };
+
+        let Some(dpop) = dpop else {
+            warn!(token_id = token.get_id(), "finding dpop proof");
+            return Err(Error::InvalidToken);
+        };
+
+        let jkt = self.prove_possession(dpop).await?;
+        if !crypto::secure_eq(jkt.as_bytes(), cnf.jkt.as_bytes()) {
+            warn!(token_id = token.get_id(), jkt, "checking dpop proof's key");
+            return Err(Error::InvalidToken);
         }
 
         Ok(())
     }
 
+    /// Checks the given DPoP proof has been issued for the given request and has never been used before.
+    /// Returns the thumbprint of the key it has been signed with.
+    #[instrument(skip(self))]
+    pub async fn prove_possession(&self, dpop: &DpopRequest) -> Result<String> {
+        let proof = DpopProof::from_request(dpop)?;
+
+        // a proof is accepted for a limited time, so it only needs to be remembered for as long
+        let key = Self::dpop_key(&proof.jkt, &proof.claims.jti);
+        let expire = 2 * DPOP_PROOF_MAX_AGE.as_secs();
+        let inserted = self
+            .token_repo
+            .insert(&key, &proof.claims.iat.to_string(), Some(expire))
+            .await?;
+
+        if !inserted {
+            warn!(jti = proof.claims.jti, "dpop proof replay detected");
+            return Err(Error::InvalidToken);
+        }
+
+        Ok(proof.jkt)
+    }
+
+    fn dpop_key(jkt: &str, jti: &str) -> String {
+        format!("{}::{}::{}", TOKEN_DPOP_PREFIX, jkt, jti)
+    }
+
+    /// Pushes forward the expiration of an idle-timed session, but never beyond its absolute maximum
+    /// lifetime as set in its claims.
+    async fn extend_idle_session(&self, token: &Token) -> Result<()> {
+        let Some(idle) = self.ttl_policy.session_idle else {
+            return Ok(());
+        };
+
+        let remaining = token
+            .exp
+            .saturating_sub(time::unix_timestamp(SystemTime::now()));
+
+        let expire = idle.as_secs().min(remaining as u64);
+        if expire == 0 {
+            return Ok(()); // the token is about to expire anyway
+        }
+
+        self.token_repo
+            .touch(&token.get_id(), expire)
+            .await
+            .map_err(|err| {
+                warn!(
+                    error = err.to_string(),
+                    token_id = token.get_id(),
+                    "extending idle session",
+                );
+                Error::InvalidToken
+            })
+    }
+
+    /// Same as [`TokenApplication::verify`], but the token gets removed from the repository in the same
+    /// atomic operation that checks its presence. Therefore, no matter how many concurrent calls are
+    /// made, only one of them can succeed. Since the token may still be restored, announcing its revocation
+    /// is up to the caller, once the action it was consumed for has succeeded. Returns the token as it was
+    /// stored, so it can be restored.
+    #[instrument(skip(self))]
+    pub async fn consume(&self, token: &Token, options: VerifyOptions) -> Result<String> {
+        self.verify(
+            token,
+            VerifyOptions {
+                must_exists: false,
+                ..options
+            },
+        )
+        .await?;
+
+        let key = token.get_id();
+        let present_data = self.token_repo.consume(&key).await.map_err(|err| {
+            warn!(
+                error = err.to_string(),
+                token_id = key,
+                "consuming token by id",
+            );
+            Error::InvalidToken
+        })?;
+
+        let present_token = self.decode(&present_data).await?;
+        if token != &present_token {
+            error!(token_id = key, "token does not match");
+            return Err(Error::InvalidToken);
+        }
+
+        Ok(present_data)
+    }
+
+    /// Puts back a token that has been consumed, for as long as it was meant to live, so it can be used
+    /// once again. This is intended for those actions that failed after consuming their token.
+    #[instrument(skip(self))]
+    pub async fn restore(&self, token: &Token, signed: &str) -> Result<()> {
+        let now = time::unix_timestamp(SystemTime::now());
+        let Some(expire) = token.exp.checked_sub(now).filter(|expire| *expire > 0) else {
+            return Ok(()); // the token has expired meanwhile, so there is nothing to restore
+        };
+
+        self.token_repo
+            .save(&token.get_id(), signed, Some(expire as u64))
+            .await
+    }
+
+    /// Returns the state of the given token, which is inactive for any token that is either invalid, no
+    /// longer present in the repository or not satisfying the given options.
+    #[instrument(skip(self))]
+    pub async fn introspect(&self, token: &str, options: VerifyOptions) -> Result<Introspection> {
+        let claims = match self.decode(token).await {
+            Ok(claims) => claims,
+            Err(Error::InvalidToken) => return Ok(Introspection::inactive()),
+            Err(err) => return Err(err),
+        };
+
+        // the proof of possession is up to the resource server, which gets the key in the introspection
+        match self.verify_claims(&claims, options, false).await {
+            Ok(_) => Ok(Introspection::from(&claims)),
+            Err(Error::InvalidToken) => Ok(Introspection::inactive()),
+            Err(err) => Err(err),
+        }
+    }
+
     #[instrument(skip(self))]
     pub async fn revoke(&self, token: &Token) -> Result<()> {
         let key = token.get_id();
@@ -139,23 +800,30 @@ impl<'a, T: TokenRepository> TokenApplication<'a, T> {
             Error::InvalidToken
         })?;
 
-        self.token_repo.delete(&key).await
+        self.token_repo.delete(&key).await?;
+        self.announce_revocation(token).await
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use super::{TokenApplication, TokenRepository};
-    use crate::time;
-    use crate::token::application::VerifyOptions;
-    use crate::token::domain::{Token, TokenKind};
-    use crate::{
-        crypto,
-        result::{Error, Result},
+    use super::{TokenApplication, TokenEventBus, TokenRepository};
+    use crate::result::{Error, Result};
+    use crate::token::application::{GenerateOptions, VerifyOptions};
+    use crate::token::domain::{
+        Confirmation, Introspection, Revocation, RevocationScope, Token, TokenDefinition,
+        TokenFamily, TokenFormat, TokenKind, TtlPolicy,
     };
+    use crate::token::dpop::tests::{dpop_thumbprint, new_dpop_proof};
+    use crate::token::dpop::DpopRequest;
+    use crate::token::keyring::tests::{ANOTHER_PRIVATE_KEY, ANOTHER_PUBLIC_KEY};
+    use crate::token::keyring::{KeyRing, TrustedIssuers};
+    use crate::{crypto, time};
     use async_trait::async_trait;
     use base64::{engine::general_purpose, Engine as _};
+    use jsonwebtoken::Algorithm;
     use lazy_static::lazy_static;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::time::{Duration, SystemTime};
 
@@ -166,13 +834,33 @@ pub mod tests {
         pub static ref PUBLIC_KEY: Vec<u8> = general_purpose::STANDARD.decode(
             b"LS0tLS1CRUdJTiBQVUJMSUMgS0VZLS0tLS0KTUZrd0V3WUhLb1pJemowQ0FRWUlLb1pJemowREFRY0RRZ0FFVm5sdE1MTnI0b0dmOHl1RnFZdXhabi9oRHFLcQo1bjBVZm45YjVPc3I2UmNCOTMySGRtSHVjc2FMNVl1RGZsVE9rMWswUGpYaExIM3pIK2pRQU5tZFpnPT0KLS0tLS1FTkQgUFVCTElDIEtFWS0tLS0tCg=="
         ).unwrap();
+        pub static ref KEYRING: KeyRing = KeyRing::new("test", Algorithm::ES256, &PRIVATE_KEY, &PUBLIC_KEY);
+        pub static ref PEER_KEYRING: KeyRing = KeyRing::new("peer", Algorithm::ES256, &ANOTHER_PRIVATE_KEY, &ANOTHER_PUBLIC_KEY);
+        pub static ref TRUSTED_ISSUERS: TrustedIssuers = TrustedIssuers::default().with_key("peer", "peer", Algorithm::ES256, &ANOTHER_PUBLIC_KEY);
     }
 
+    pub const SECRET_KEY: &[u8] = &[7; 32];
+
     type MockFnFind = Option<fn(this: &TokenRepositoryMock, key: &str) -> Result<String>>;
+    type MockFnFindMany =
+        Option<fn(this: &TokenRepositoryMock, keys: &[String]) -> Result<Vec<Option<String>>>>;
     type MockFnSave = Option<
         fn(this: &TokenRepositoryMock, key: &str, token: &str, expire: Option<u64>) -> Result<()>,
     >;
+    type MockFnInsert = Option<
+        fn(this: &TokenRepositoryMock, key: &str, token: &str, expire: Option<u64>) -> Result<bool>,
+    >;
     type MockFnDelete = Option<fn(this: &TokenRepositoryMock, key: &str) -> Result<()>>;
+    type MockFnIncrement =
+        Option<fn(this: &TokenRepositoryMock, key: &str, expire: u64) -> Result<u64>>;
+    type MockFnTouch = Option<fn(this: &TokenRepositoryMock, key: &str, expire: u64) -> Result<()>>;
+    type MockFnConsume = Option<fn(this: &TokenRepositoryMock, key: &str) -> Result<String>>;
+    type MockFnIndex = Option<
+        fn(this: &TokenRepositoryMock, sub: &str, key: &str, expire: Option<u64>) -> Result<()>,
+    >;
+    type MockFnFindBySubject =
+        Option<fn(this: &TokenRepositoryMock, sub: &str) -> Result<Vec<String>>>;
+    type MockFnDeleteBySubject = Option<fn(this: &TokenRepositoryMock, sub: &str) -> Result<()>>;
 
     pub const TEST_DEFAULT_TOKEN_TIMEOUT: u64 = 60;
 
@@ -187,8 +875,16 @@ pub mod tests {
     #[derive(Default, Clone)]
     pub struct TokenRepositoryMock {
         pub fn_find: MockFnFind,
+        pub fn_find_many: MockFnFindMany,
         pub fn_save: MockFnSave,
+        pub fn_insert: MockFnInsert,
         pub fn_delete: MockFnDelete,
+        pub fn_increment: MockFnIncrement,
+        pub fn_touch: MockFnTouch,
+        pub fn_consume: MockFnConsume,
+        pub fn_index: MockFnIndex,
+        pub fn_find_by_subject: MockFnFindBySubject,
+        pub fn_delete_by_subject: MockFnDeleteBySubject,
         pub token: String,
     }
 
@@ -202,6 +898,14 @@ pub mod tests {
             Ok(self.token.clone())
         }
 
+        async fn find_many(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+            if let Some(fn_find_many) = self.fn_find_many {
+                return fn_find_many(self, keys);
+            }
+
+            Ok(vec![None; keys.len()])
+        }
+
         async fn save(&self, key: &str, token: &str, expire: Option<u64>) -> Result<()> {
             if let Some(fn_save) = self.fn_save {
                 return fn_save(self, key, token, expire);
@@ -210,6 +914,14 @@ pub mod tests {
             Ok(())
         }
 
+        async fn insert(&self, key: &str, token: &str, expire: Option<u64>) -> Result<bool> {
+            if let Some(fn_insert) = self.fn_insert {
+                return fn_insert(self, key, token, expire);
+            }
+
+            Ok(true)
+        }
+
         async fn delete(&self, key: &str) -> Result<()> {
             if let Some(fn_delete) = self.fn_delete {
                 return fn_delete(self, key);
@@ -217,36 +929,835 @@ pub mod tests {
 
             Ok(())
         }
-    }
 
-    pub fn new_token_application<'a, T: TokenRepository + Default>(
-        token_repo: Option<T>,
-    ) -> TokenApplication<'a, T> {
-        TokenApplication {
-            token_repo: Arc::new(token_repo.unwrap_or_default()),
-            timeout: Duration::from_secs(999),
-            token_issuer: "dummy",
-            private_key: &PRIVATE_KEY,
-            public_key: &PUBLIC_KEY,
+        async fn increment(&self, key: &str, expire: u64) -> Result<u64> {
+            if let Some(fn_increment) = self.fn_increment {
+                return fn_increment(self, key, expire);
+            }
+
+            Ok(1)
         }
-    }
 
-    #[tokio::test]
-    async fn verify_token_should_not_fail() {
-        let token = crypto::sign_jwt(&PRIVATE_KEY, new_token(TokenKind::Session)).unwrap();
-        let token_repo = TokenRepositoryMock {
-            token: token.clone(),
-            fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
-                Ok(this.token.clone())
-            }),
-            ..Default::default()
-        };
+        async fn touch(&self, key: &str, expire: u64) -> Result<()> {
+            if let Some(fn_touch) = self.fn_touch {
+                return fn_touch(self, key, expire);
+            }
+
+            Ok(())
+        }
+
+        async fn consume(&self, key: &str) -> Result<String> {
+            if let Some(fn_consume) = self.fn_consume {
+                return fn_consume(self, key);
+            }
+
+            Ok(self.token.clone())
+        }
+
+        async fn index(&self, sub: &str, key: &str, expire: Option<u64>) -> Result<()> {
+            if let Some(fn_index) = self.fn_index {
+                return fn_index(self, sub, key, expire);
+            }
+
+            Ok(())
+        }
+
+        async fn find_by_subject(&self, sub: &str) -> Result<Vec<String>> {
+            if let Some(fn_find_by_subject) = self.fn_find_by_subject {
+                return fn_find_by_subject(self, sub);
+            }
+
+            Ok(vec![self.token.clone()])
+        }
+
+        async fn delete_by_subject(&self, sub: &str) -> Result<()> {
+            if let Some(fn_delete_by_subject) = self.fn_delete_by_subject {
+                return fn_delete_by_subject(self, sub);
+            }
+
+            Ok(())
+        }
+    }
+
+    type MockFnEmitTokenRevoked = Option<fn(this: &TokenEventBusMock, token: &Token) -> Result<()>>;
+
+    #[derive(Default)]
+    pub struct TokenEventBusMock {
+        pub fn_emit_token_revoked: MockFnEmitTokenRevoked,
+    }
+
+    #[async_trait]
+    impl TokenEventBus for TokenEventBusMock {
+        async fn emit_token_revoked(&self, token: &Token) -> Result<()> {
+            if let Some(f) = self.fn_emit_token_revoked {
+                return f(self, token);
+            }
+
+            Ok(())
+        }
+    }
+
+    pub fn new_token_application<'a, T: TokenRepository + Default>(
+        token_repo: Option<T>,
+    ) -> TokenApplication<'a, T, TokenEventBusMock> {
+        TokenApplication {
+            token_repo: Arc::new(token_repo.unwrap_or_default()),
+            event_bus: Arc::new(TokenEventBusMock::default()),
+            ttl_policy: TtlPolicy {
+                session: Duration::from_secs(999),
+                verification: Duration::from_secs(999),
+                reset: Duration::from_secs(99),
+                refresh: Duration::from_secs(9999),
+                session_idle: None,
+            },
+            token_format: TokenFormat::Jwt,
+            token_issuer: "dummy",
+            keyring: &KEYRING,
+            trusted_issuers: &TRUSTED_ISSUERS,
+            secret_key: SECRET_KEY,
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_token_should_not_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Ok(this.token.clone())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn generate_token_should_stamp_type() {
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        for kind in [TokenKind::Session, TokenKind::Reset] {
+            let token = app
+                .generate(kind.clone(), "999", None, GenerateOptions::default())
+                .await
+                .unwrap();
+
+            let typ = crypto::decode_jwt_typ(token.signature()).unwrap();
+            assert_eq!(typ.as_deref(), Some(kind.typ()));
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_token_mismatching_type_should_fail() {
+        let token = KEYRING
+            .sign_typed(new_token(TokenKind::Session), TokenKind::Refresh.typ())
+            .unwrap();
+
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        app.decode(&token)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn generate_token_with_secret_should_seal_it() {
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock, _: &str, token: &str, _: Option<u64>| -> Result<()> {
+                    let claims: Token = KEYRING.decode(token).unwrap();
+                    assert!(claims.scr.is_some());
+                    assert_ne!(claims.scr.as_deref(), Some("secret"));
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let token = app
+            .generate(
+                TokenKind::Verification,
+                "999",
+                Some("secret"),
+                GenerateOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        let claims = app.decode(token.signature()).await.unwrap();
+        assert_eq!(claims.get_secret(), Some("secret"));
+    }
+
+    #[tokio::test]
+    async fn decode_token_with_plain_secret_should_not_fail() {
+        // as issued before secrets got sealed
+        let claims = Token::new(
+            "test",
+            "999",
+            Duration::from_secs(60),
+            TokenKind::Verification,
+            Some("secret"),
+        );
+
+        let token = KEYRING.sign(claims).unwrap();
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        let claims = app.decode(&token).await.unwrap();
+        assert_eq!(claims.get_secret(), Some("secret"));
+    }
+
+    #[tokio::test]
+    async fn generate_opaque_token_should_not_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock,
+                 key: &str,
+                 token: &str,
+                 expire: Option<u64>|
+                 -> Result<()> {
+                    assert!(key.starts_with("Session::") || key.starts_with("Handle::"));
+                    assert!(token.contains('.'));
+                    assert_eq!(expire, Some(999));
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_token_application(Some(token_repo));
+        app.token_format = TokenFormat::Opaque;
+
+        let token = app
+            .generate(TokenKind::Session, "999", None, GenerateOptions::default())
+            .await
+            .unwrap();
+
+        assert!(token.id().starts_with("Session::"));
+        assert_eq!(token.signature().len(), 48);
+        assert!(!token.signature().contains('.'));
+    }
+
+    #[tokio::test]
+    async fn decode_opaque_token_should_not_fail() {
+        let claims = new_token(TokenKind::Session);
+        let token_repo = TokenRepositoryMock {
+            token: KEYRING.sign(&claims).unwrap(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                assert_eq!(key, "Handle::abc123");
+                Ok(this.token.clone())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        assert_eq!(app.decode("abc123").await.unwrap(), claims);
+    }
+
+    #[tokio::test]
+    async fn decode_unknown_opaque_token_should_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.decode("abc123")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn verify_token_issued_before_epoch_should_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find_many: Some(
+                |_: &TokenRepositoryMock, keys: &[String]| -> Result<Vec<Option<String>>> {
+                    assert_eq!(
+                        keys,
+                        [
+                            "NotBefore::Global",
+                            "NotBefore::Subject::999",
+                            "NotBefore::Issuer::test"
+                        ]
+                    );
+
+                    let epoch = time::unix_timestamp(SystemTime::now() + Duration::from_secs(10));
+                    Ok(vec![None, Some(epoch.to_string()), None])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn verify_token_issued_after_epoch_should_not_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find_many: Some(
+                |_: &TokenRepositoryMock, _: &[String]| -> Result<Vec<Option<String>>> {
+                    let epoch = time::unix_timestamp(SystemTime::now() - Duration::from_secs(10));
+                    Ok(vec![Some(epoch.to_string()), None, None])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_token_issued_at_epoch_should_not_fail() {
+        static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+        let claims = new_token(TokenKind::Session);
+        EPOCH.store(claims.iat, Ordering::Relaxed);
+
+        let token = KEYRING.sign(claims).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find_many: Some(
+                |_: &TokenRepositoryMock, _: &[String]| -> Result<Vec<Option<String>>> {
+                    let epoch = EPOCH.load(Ordering::Relaxed);
+                    Ok(vec![None, Some(epoch.to_string()), None])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoke_before_should_not_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock,
+                 key: &str,
+                 token: &str,
+                 expire: Option<u64>|
+                 -> Result<()> {
+                    assert_eq!(key, "NotBefore::Subject::999");
+                    assert_eq!(token, "1000");
+                    assert_eq!(expire, Some(9999));
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.revoke_before(&RevocationScope::Subject("999".to_string()), 1000)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoke_before_should_keep_latest_epoch() {
+        let token_repo = TokenRepositoryMock {
+            fn_find_many: Some(
+                |_: &TokenRepositoryMock, keys: &[String]| -> Result<Vec<Option<String>>> {
+                    assert_eq!(keys, ["NotBefore::Global"]);
+                    Ok(vec![Some("2000".to_string())])
+                },
+            ),
+            fn_save: Some(
+                |_: &TokenRepositoryMock, _: &str, token: &str, _: Option<u64>| -> Result<()> {
+                    assert_eq!(token, "2000");
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.revoke_before(&RevocationScope::Global, 1000)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_token_should_not_touch_without_idle_timeout() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_touch: Some(|_: &TokenRepositoryMock, _: &str, _: u64| -> Result<()> {
+                panic!("touch should not be called");
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn generate_idle_session_should_store_idle_timeout() {
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock, _: &str, _: &str, expire: Option<u64>| -> Result<()> {
+                    assert_eq!(expire, Some(60));
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_token_application(Some(token_repo));
+        app.ttl_policy.session_idle = Some(Duration::from_secs(60));
+
+        let before = time::unix_timestamp(SystemTime::now());
+        let token = app
+            .generate(TokenKind::Session, "999", None, GenerateOptions::default())
+            .await
+            .unwrap();
+
+        let claims = app.decode(token.signature()).await.unwrap();
+        assert!(claims.exp >= before + 999 && claims.exp <= before + 1000);
+    }
+
+    #[tokio::test]
+    async fn verify_idle_session_should_extend_expiration() {
+        let claims = Token::new(
+            "test",
+            "999",
+            Duration::from_secs(999),
+            TokenKind::Session,
+            None,
+        );
+
+        let token_repo = TokenRepositoryMock {
+            token: KEYRING.sign(&claims).unwrap(),
+            fn_touch: Some(
+                |_: &TokenRepositoryMock, key: &str, expire: u64| -> Result<()> {
+                    assert!(key.starts_with("Session::"));
+                    assert_eq!(expire, 60);
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_token_application(Some(token_repo));
+        app.ttl_policy.session_idle = Some(Duration::from_secs(60));
+
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_idle_session_should_not_extend_beyond_absolute_lifetime() {
+        let claims = Token::new(
+            "test",
+            "999",
+            Duration::from_secs(30),
+            TokenKind::Session,
+            None,
+        );
+
+        let token_repo = TokenRepositoryMock {
+            token: KEYRING.sign(&claims).unwrap(),
+            fn_touch: Some(
+                |_: &TokenRepositoryMock, _: &str, expire: u64| -> Result<()> {
+                    assert!(expire <= 30);
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_token_application(Some(token_repo));
+        app.ttl_policy.session_idle = Some(Duration::from_secs(60));
+
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_idle_session_already_expired_should_fail() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_touch: Some(|_: &TokenRepositoryMock, _: &str, _: u64| -> Result<()> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_token_application(Some(token_repo));
+        app.ttl_policy.session_idle = Some(Duration::from_secs(60));
+
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn verify_token_with_audience_and_scopes_should_not_fail() {
+        let mut claims = new_token(TokenKind::Session);
+        claims.aud = Some("dummy_app".to_string());
+        claims.scope = Some("read write".to_string());
+
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        let options = VerifyOptions {
+            must_exists: false,
+            audience: Some("dummy_app".to_string()),
+            scopes: vec!["write".to_string()],
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        app.verify(&claims, options).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_token_wrong_audience_should_fail() {
+        let mut claims = new_token(TokenKind::Session);
+        claims.aud = Some("another_app".to_string());
+
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        for claims in [claims, new_token(TokenKind::Session)] {
+            let options = VerifyOptions {
+                must_exists: false,
+                audience: Some("dummy_app".to_string()),
+                ..VerifyOptions::new(TokenKind::Session)
+            };
+
+            app.verify(&claims, options)
+                .await
+                .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+                .unwrap_err();
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_token_missing_scope_should_fail() {
+        let mut claims = new_token(TokenKind::Session);
+        claims.scope = Some("read".to_string());
+
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        let options = VerifyOptions {
+            must_exists: false,
+            scopes: vec!["read".to_string(), "write".to_string()],
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        app.verify(&claims, options)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    fn new_bound_token(kind: TokenKind) -> Token {
+        let mut claims = new_token(kind);
+        claims.cnf = Some(Confirmation {
+            jkt: dpop_thumbprint(),
+        });
+
+        claims
+    }
+
+    fn new_dpop_request(token: &str) -> DpopRequest {
+        let proof = new_dpop_proof("GET", "https://rauth.eu/session", Some(token));
+        DpopRequest::new(&proof, "GET", "/session").with_access_token(token)
+    }
+
+    #[tokio::test]
+    async fn verify_bound_token_should_not_fail() {
+        let token = KEYRING.sign(new_bound_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_insert: Some(
+                |_: &TokenRepositoryMock,
+                 key: &str,
+                 _: &str,
+                 expire: Option<u64>|
+                 -> Result<bool> {
+                    assert!(key.starts_with(&format!("Dpop::{}::", dpop_thumbprint())));
+                    assert_eq!(expire, Some(120));
+                    Ok(true)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        let options = VerifyOptions {
+            dpop: Some(new_dpop_request(&token)),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        app.verify(&claims, options).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_bound_token_without_proof_should_fail() {
+        let token = KEYRING.sign(new_bound_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn verify_bound_token_wrong_key_should_fail() {
+        let mut claims = new_token(TokenKind::Session);
+        claims.cnf = Some(Confirmation {
+            jkt: "another_key".to_string(),
+        });
+
+        let token = KEYRING.sign(claims).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        let options = VerifyOptions {
+            dpop: Some(new_dpop_request(&token)),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        app.verify(&claims, options)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn verify_bound_token_replayed_proof_should_fail() {
+        let token = KEYRING.sign(new_bound_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_insert: Some(
+                |_: &TokenRepositoryMock, _: &str, _: &str, _: Option<u64>| -> Result<bool> {
+                    Ok(false)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        let options = VerifyOptions {
+            dpop: Some(new_dpop_request(&token)),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        app.verify(&claims, options)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn introspect_bound_token_should_not_require_proof() {
+        let token = KEYRING.sign(new_bound_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let introspection = app
+            .introspect(&token, VerifyOptions::default())
+            .await
+            .unwrap();
+
+        assert!(introspection.active);
+        assert_eq!(
+            introspection.cnf.map(|cnf| cnf.jkt),
+            Some(dpop_thumbprint())
+        );
+    }
+
+    fn new_peer_token(kind: TokenKind) -> Token {
+        let mut claims = new_token(kind);
+        claims.iss = "peer".to_string();
+        claims
+    }
+
+    #[tokio::test]
+    async fn verify_peer_token_should_not_fail() {
+        let token = PEER_KEYRING
+            .sign(new_peer_token(TokenKind::Session))
+            .unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let claims = app.decode(&token).await.unwrap();
+        assert_eq!(claims.iss, "peer");
+
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_peer_token_without_existence_check_should_not_fail() {
+        let token = PEER_KEYRING
+            .sign(new_peer_token(TokenKind::Session))
+            .unwrap();
+        let token_repo = TokenRepositoryMock {
+            fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
+
+        let trusted_issuers = TRUSTED_ISSUERS.clone().without_existence_check();
+        let mut app = new_token_application(Some(token_repo));
+        app.trusted_issuers = &trusted_issuers;
+
+        let claims = app.decode(&token).await.unwrap();
+        app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_peer_token_not_present_should_fail() {
+        let token = PEER_KEYRING
+            .sign(new_peer_token(TokenKind::Session))
+            .unwrap();
+        let token_repo = TokenRepositoryMock {
+            fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
 
         let app = new_token_application(Some(token_repo));
         let claims = app.decode(&token).await.unwrap();
         app.verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn decode_peer_token_wrong_kind_should_fail() {
+        let token = PEER_KEYRING.sign(new_peer_token(TokenKind::Reset)).unwrap();
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        app.decode(&token)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn decode_peer_token_wrong_key_should_fail() {
+        let app = new_token_application::<TokenRepositoryMock>(None);
+
+        // a trusted issuer cannot be impersonated by signing with any other key
+        let token = KEYRING.sign(new_peer_token(TokenKind::Session)).unwrap();
+        app.decode(&token)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+
+        // nor can an untrusted issuer get its tokens accepted
+        let mut claims = new_token(TokenKind::Session);
+        claims.iss = "stranger".to_string();
+        let token = PEER_KEYRING.sign(claims).unwrap();
+        app.decode(&token)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn introspect_token_should_not_fail() {
+        let claims = new_token(TokenKind::Session);
+        let token = KEYRING.sign(&claims).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let introspection = app
+            .introspect(&token, VerifyOptions::default())
+            .await
+            .unwrap();
+
+        assert!(introspection.active);
+        assert_eq!(introspection.sub.as_deref(), Some(claims.sub.as_str()));
+        assert_eq!(introspection.iss.as_deref(), Some(claims.iss.as_str()));
+        assert_eq!(introspection.knd, Some(TokenKind::Session));
+        assert_eq!(introspection.exp, Some(claims.exp));
+        assert!(introspection.expires_in.unwrap() <= TEST_DEFAULT_TOKEN_TIMEOUT as usize);
+    }
+
+    #[tokio::test]
+    async fn introspect_revoked_token_should_be_inactive() {
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let introspection = app
+            .introspect(&token, VerifyOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(introspection, Introspection::inactive());
+
+        let json = serde_json::to_string(&introspection).unwrap();
+        assert_eq!(json, r#"{"active":false}"#);
+    }
+
+    #[tokio::test]
+    async fn introspect_invalid_token_should_be_inactive() {
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        let introspection = app
+            .introspect("not a token", VerifyOptions::default())
             .await
             .unwrap();
+        assert_eq!(introspection, Introspection::inactive());
     }
 
     #[tokio::test]
@@ -254,7 +1765,7 @@ pub mod tests {
         let mut claim = new_token(TokenKind::Session);
         claim.exp = time::unix_timestamp(SystemTime::now() - Duration::from_secs(61));
 
-        let token = crypto::sign_jwt(&PRIVATE_KEY, claim).unwrap();
+        let token = KEYRING.sign(claim).unwrap();
         let app = new_token_application::<TokenRepositoryMock>(None);
 
         app.decode(&token)
@@ -265,7 +1776,8 @@ pub mod tests {
 
     #[tokio::test]
     async fn decode_token_invalid_should_fail() {
-        let token = crypto::sign_jwt(&PRIVATE_KEY, new_token(TokenKind::Session))
+        let token = KEYRING
+            .sign(new_token(TokenKind::Session))
             .unwrap()
             .replace('A', "a");
         let token_repo = TokenRepositoryMock {
@@ -285,7 +1797,7 @@ pub mod tests {
 
     #[tokio::test]
     async fn verify_token_wrong_kind_should_fail() {
-        let token = crypto::sign_jwt(&PRIVATE_KEY, new_token(TokenKind::Session)).unwrap();
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
         let token_repo = TokenRepositoryMock {
             token: token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -304,7 +1816,7 @@ pub mod tests {
 
     #[tokio::test]
     async fn verify_token_not_present_should_fail() {
-        let token = crypto::sign_jwt(&PRIVATE_KEY, new_token(TokenKind::Session)).unwrap();
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
         let token_repo = TokenRepositoryMock {
             token: token.clone(),
             fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -323,7 +1835,7 @@ pub mod tests {
 
     #[tokio::test]
     async fn verify_token_mismatch_should_fail() {
-        let token = crypto::sign_jwt(&PRIVATE_KEY, new_token(TokenKind::Session)).unwrap();
+        let token = KEYRING.sign(new_token(TokenKind::Session)).unwrap();
         let token_repo = TokenRepositoryMock {
             token: token.clone(),
             fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -339,4 +1851,446 @@ pub mod tests {
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
     }
+
+    pub fn new_family_token(kind: TokenKind) -> Token {
+        let mut token = new_token(kind);
+        token.fam = Some("dummy_family".to_string());
+        token
+    }
+
+    #[tokio::test]
+    async fn generate_token_should_honor_ttl_policy() {
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock, key: &str, _: &str, expire: Option<u64>| -> Result<()> {
+                    assert!(key.starts_with("Reset::"));
+                    assert_eq!(expire, Some(99));
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let before = time::unix_timestamp(SystemTime::now());
+        let token = app
+            .generate(TokenKind::Reset, "999", None, GenerateOptions::default())
+            .await
+            .unwrap();
+
+        let claims = app.decode(token.signature()).await.unwrap();
+        assert!(claims.exp >= before + 99 && claims.exp <= before + 100);
+    }
+
+    #[tokio::test]
+    async fn generate_pair_should_not_fail() {
+        let app = new_token_application::<TokenRepositoryMock>(None);
+        let options = GenerateOptions {
+            audience: Some("dummy_app".to_string()),
+            scopes: vec!["read".to_string(), "write".to_string()],
+            ..Default::default()
+        };
+
+        let pair = app.generate_pair("999", options).await.unwrap();
+
+        let session = app.decode(pair.session().signature()).await.unwrap();
+        let refresh = app.decode(pair.refresh().signature()).await.unwrap();
+
+        assert_eq!(session.knd, TokenKind::Session);
+        assert_eq!(refresh.knd, TokenKind::Refresh);
+        assert!(session.fam.is_some());
+        assert_eq!(session.fam, refresh.fam);
+        assert!(refresh.exp > session.exp);
+        assert_eq!(session.aud.as_deref(), Some("dummy_app"));
+        assert_eq!(session.scope.as_deref(), Some("read write"));
+        assert_eq!(session.aud, refresh.aud);
+        assert_eq!(session.scope, refresh.scope);
+    }
+
+    #[tokio::test]
+    async fn generate_pair_should_index_tokens() {
+        let token_repo = TokenRepositoryMock {
+            fn_index: Some(
+                |_: &TokenRepositoryMock,
+                 sub: &str,
+                 key: &str,
+                 expire: Option<u64>|
+                 -> Result<()> {
+                    assert_eq!(sub, "999");
+                    match key.split("::").next() {
+                        Some("Session") => assert_eq!(expire, Some(999)),
+                        Some("Refresh") => assert_eq!(expire, Some(9999)),
+                        _ => panic!("unexpected key {key} being indexed"),
+                    }
+
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.generate_pair("999", GenerateOptions::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoke_subject_should_not_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_find_by_subject: Some(|_: &TokenRepositoryMock, _: &str| -> Result<Vec<String>> {
+                let session = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+                let refresh = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+                Ok(vec![session, refresh, "expired or malformed".to_string()])
+            }),
+            fn_find: Some(|_: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if key != "Family::dummy_family" {
+                    return Ok(KEYRING.sign(new_family_token(TokenKind::Session)).unwrap());
+                }
+
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: "Refresh::dummy".to_string(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            fn_delete: Some(|_: &TokenRepositoryMock, key: &str| -> Result<()> {
+                assert!(["Session::dummy", "Refresh::dummy", "Family::dummy_family"].contains(&key));
+                Ok(())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.revoke_subject("999").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoke_subject_empty_family_should_not_fail() {
+        static SUBJECT_REVOKED: AtomicBool = AtomicBool::new(false);
+        let token_repo = TokenRepositoryMock {
+            fn_find_by_subject: Some(|_: &TokenRepositoryMock, _: &str| -> Result<Vec<String>> {
+                let session = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+                Ok(vec![session])
+            }),
+            fn_find: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Ok(String::new()) // the family has already expired or been revoked
+            }),
+            fn_delete_by_subject: Some(|_: &TokenRepositoryMock, _: &str| -> Result<()> {
+                SUBJECT_REVOKED.store(true, Ordering::Relaxed);
+                Ok(())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.revoke_subject("999").await.unwrap();
+        assert!(SUBJECT_REVOKED.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn revoke_token_should_announce_revocation() {
+        let token = new_token(TokenKind::Session);
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock,
+                 key: &str,
+                 revocation: &str,
+                 expire: Option<u64>|
+                 -> Result<()> {
+                    assert!(key.starts_with("Revoked::"));
+                    let revocation: Revocation = serde_json::from_str(revocation).unwrap();
+                    assert_eq!(key, format!("Revoked::{}", revocation.jti));
+                    assert!(expire.is_some_and(|expire| expire > 0));
+                    Ok(())
+                },
+            ),
+            fn_index: Some(
+                |_: &TokenRepositoryMock, sub: &str, key: &str, _: Option<u64>| -> Result<()> {
+                    assert_eq!(sub, "::revocations");
+                    assert!(key.starts_with("Revoked::"));
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        static EMITTED: AtomicBool = AtomicBool::new(false);
+        let mut app = new_token_application(Some(token_repo));
+        app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, token: &Token| -> Result<()> {
+                assert_eq!(token.sub, "999");
+                EMITTED.store(true, Ordering::Relaxed);
+                Ok(())
+            }),
+        });
+
+        app.revoke(&token).await.unwrap();
+        assert!(EMITTED.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn revoke_token_with_bus_unavailable_should_not_fail() {
+        let mut app = new_token_application::<TokenRepositoryMock>(None);
+        app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, _: &Token| -> Result<()> {
+                Err(Error::NotAvailable)
+            }),
+        });
+
+        app.revoke(&new_token(TokenKind::Session)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn revoke_expired_token_should_not_announce_revocation() {
+        let mut token = new_token(TokenKind::Session);
+        token.exp = 0;
+
+        let token_repo = TokenRepositoryMock {
+            fn_save: Some(
+                |_: &TokenRepositoryMock, _: &str, _: &str, _: Option<u64>| -> Result<()> {
+                    Err(Error::Unknown)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_token_application(Some(token_repo));
+        app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, _: &Token| -> Result<()> {
+                Err(Error::Unknown)
+            }),
+        });
+
+        app.revoke(&token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn revocations_should_not_fail() {
+        let token_repo = TokenRepositoryMock {
+            fn_find_by_subject: Some(
+                |_: &TokenRepositoryMock, sub: &str| -> Result<Vec<String>> {
+                    assert_eq!(sub, "::revocations");
+                    Ok(vec![
+                        r#"{"jti":"later","exp":200}"#.to_string(),
+                        r#"{"jti":"sooner","exp":100}"#.to_string(),
+                    ])
+                },
+            ),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let revocations = app.revocations().await.unwrap();
+        assert_eq!(
+            revocations,
+            vec![
+                Revocation {
+                    jti: "sooner".to_string(),
+                    exp: 100
+                },
+                Revocation {
+                    jti: "later".to_string(),
+                    exp: 200
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_token_should_not_fail() {
+        let mut claims = new_family_token(TokenKind::Refresh);
+        claims.aud = Some("dummy_app".to_string());
+        claims.scope = Some("read".to_string());
+
+        let token = KEYRING.sign(claims).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                let claims: Token = KEYRING.decode(&this.token)?;
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: claims.get_id(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        let pair = app.refresh(&token, None).await.unwrap();
+
+        let session = app.decode(pair.session().signature()).await.unwrap();
+        let refresh = app.decode(pair.refresh().signature()).await.unwrap();
+
+        assert_eq!(session.knd, TokenKind::Session);
+        assert_eq!(refresh.knd, TokenKind::Refresh);
+        assert_eq!(session.fam.as_deref(), Some("dummy_family"));
+        assert_eq!(refresh.fam.as_deref(), Some("dummy_family"));
+        assert_eq!(session.aud.as_deref(), Some("dummy_app"));
+        assert_eq!(session.scope.as_deref(), Some("read"));
+    }
+
+    #[tokio::test]
+    async fn refresh_bound_token_should_keep_key() {
+        let mut claims = new_bound_token(TokenKind::Refresh);
+        claims.fam = Some("dummy_family".to_string());
+
+        let token = KEYRING.sign(claims).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                let claims: Token = KEYRING.decode(&this.token)?;
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: claims.get_id(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.refresh(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+
+        let proof = new_dpop_proof("POST", "/session/refresh", None);
+        let dpop = DpopRequest::new(&proof, "POST", "/session/refresh");
+        let pair = app.refresh(&token, Some(&dpop)).await.unwrap();
+
+        let session = app.decode(pair.session().signature()).await.unwrap();
+        let refresh = app.decode(pair.refresh().signature()).await.unwrap();
+        assert_eq!(session.cnf.map(|cnf| cnf.jkt), Some(dpop_thumbprint()));
+        assert_eq!(refresh.cnf.map(|cnf| cnf.jkt), Some(dpop_thumbprint()));
+    }
+
+    #[tokio::test]
+    async fn refresh_token_reuse_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: "Refresh::another".to_string(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.refresh(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn refresh_token_already_consumed_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if !key.starts_with("Family::") {
+                    return Ok(this.token.clone());
+                }
+
+                let claims: Token = KEYRING.decode(&this.token)?;
+                let family = TokenFamily {
+                    session: "Session::dummy".to_string(),
+                    refresh: claims.get_id(),
+                };
+
+                Ok(serde_json::to_string(&family).unwrap())
+            }),
+            fn_consume: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Err(Error::NotFound) // a concurrent refresh has already consumed the token
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.refresh(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn refresh_token_revoked_family_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if key.starts_with("Family::") {
+                    return Err(Error::NotFound);
+                }
+
+                Ok(this.token.clone())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.refresh(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn refresh_token_empty_family_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Refresh)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            fn_find: Some(|this: &TokenRepositoryMock, key: &str| -> Result<String> {
+                if key.starts_with("Family::") {
+                    return Ok(String::new());
+                }
+
+                Ok(this.token.clone())
+            }),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.refresh(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn refresh_session_token_kind_should_fail() {
+        let token = KEYRING.sign(new_family_token(TokenKind::Session)).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: token.clone(),
+            ..Default::default()
+        };
+
+        let app = new_token_application(Some(token_repo));
+        app.refresh(&token, None)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
 }
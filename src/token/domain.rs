@@ -1,15 +1,28 @@
-use crate::time;
-use rand::Rng;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use crate::result::{Error, Result};
+use crate::{crypto, time};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
 use std::time::{Duration, SystemTime};
 
+/// The `typ` header of any token, as recommended by RFC 7519.
+pub const TOKEN_TYPE_GENERIC: &str = "JWT";
+const TOKEN_ID_LEN: usize = 32;
+const SEALED_SECRET_PREFIX: &str = "sealed.";
+
+/// Splits a space-delimited list of scopes, as found in the `scope` claim, into the scopes it holds.
+pub fn split_scope(scope: &str) -> Vec<String> {
+    scope.split_whitespace().map(ToString::to_string).collect()
+}
+
 pub trait TokenDefinition {
     fn get_id(&self) -> String;
     fn get_secret(&self) -> Option<&str>;
     fn get_kind(&self) -> &TokenKind;
+    fn get_family(&self) -> Option<&str>;
 }
 
+#[derive(Debug)]
 pub struct SignedToken {
     pub(super) id: String,
     pub(super) signature: String,
@@ -25,25 +38,250 @@ impl SignedToken {
     }
 }
 
+/// The set of tokens a "not valid before" epoch applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevocationScope {
+    /// Any token, no matter who it was issued to or by.
+    Global,
+    /// The tokens issued to the given subject.
+    Subject(String),
+    /// The tokens issued by the given issuer.
+    Issuer(String),
+}
+
+impl RevocationScope {
+    /// Returns all the scopes the given token falls into.
+    pub fn of(token: &Token) -> [RevocationScope; 3] {
+        [
+            RevocationScope::Global,
+            RevocationScope::Subject(token.sub.clone()),
+            RevocationScope::Issuer(token.iss.clone()),
+        ]
+    }
+}
+
+impl fmt::Display for RevocationScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevocationScope::Global => write!(f, "Global"),
+            RevocationScope::Subject(sub) => write!(f, "Subject::{}", sub),
+            RevocationScope::Issuer(iss) => write!(f, "Issuer::{}", iss),
+        }
+    }
+}
+
+/// The key a token is bound to, as described by RFC 7800, so only its holder can make use of the token.
+#[derive(Serialize, Deserialize, Hash, Debug, Clone, PartialEq, Eq)]
+pub struct Confirmation {
+    pub jkt: String, // thumbprint of the DPoP public key, as described by RFC 9449
+}
+
+/// Determines what is handed out to clients as the token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenFormat {
+    /// The signed JWT itself, whose claims can be read by anyone holding it.
+    #[default]
+    Jwt,
+    /// A random handle that can only be resolved into the actual JWT by the server.
+    Opaque,
+}
+
+/// A short-lived session token along with the long-lived refresh token that allows to renew it.
+#[derive(Debug)]
+pub struct TokenPair {
+    pub(super) session: SignedToken,
+    pub(super) refresh: SignedToken,
+}
+
+impl TokenPair {
+    pub fn session(&self) -> &SignedToken {
+        &self.session
+    }
+
+    pub fn refresh(&self) -> &SignedToken {
+        &self.refresh
+    }
+}
+
+/// Keeps track of the latest tokens issued for a given family, this is, all those tokens that descend
+/// from the same login.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenFamily {
+    pub session: String, // id of the latest session token
+    pub refresh: String, // id of the latest refresh token
+}
+
+/// An entry of the revocation list, telling a token is no longer valid even though it has not expired yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Revocation {
+    pub jti: String,
+    pub exp: usize,
+}
+
+impl From<&Token> for Revocation {
+    fn from(token: &Token) -> Self {
+        Revocation {
+            jti: token.jti.clone(),
+            exp: token.exp,
+        }
+    }
+}
+
+/// The state of a token as described by RFC 7662. All fields but `active` are omitted for those tokens
+/// that are not active.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub knd: Option<TokenKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<usize>, // remaining lifetime in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+impl Introspection {
+    pub fn inactive() -> Self {
+        Default::default()
+    }
+}
+
+impl From<&Token> for Introspection {
+    fn from(token: &Token) -> Self {
+        let now = time::unix_timestamp(SystemTime::now());
+        Introspection {
+            active: true,
+            sub: Some(token.sub.clone()),
+            exp: Some(token.exp),
+            iat: Some(token.iat),
+            knd: Some(token.knd.clone()),
+            iss: Some(token.iss.clone()),
+            aud: token.aud.clone(),
+            scope: token.scope.clone(),
+            expires_in: Some(token.exp.saturating_sub(now)),
+            cnf: token.cnf.clone(),
+        }
+    }
+}
+
+/// The time to live of each kind of token, as well as of anything stored along with them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TtlPolicy {
+    pub session: Duration,
+    pub verification: Duration,
+    pub reset: Duration,
+    pub refresh: Duration,
+    /// If set, session tokens expire once idle for this long, while `session` becomes their absolute
+    /// maximum lifetime.
+    pub session_idle: Option<Duration>,
+}
+
+impl TtlPolicy {
+    /// Returns the time to live of the given kind of token.
+    pub fn ttl(&self, kind: &TokenKind) -> Duration {
+        match kind {
+            TokenKind::Session => self.session,
+            TokenKind::Verification => self.verification,
+            TokenKind::Reset => self.reset,
+            TokenKind::Refresh => self.refresh,
+        }
+    }
+
+    /// Returns for how long the given kind of token has to be kept in the repository right after being
+    /// issued, which for idle-timed sessions is less than its actual time to live.
+    pub fn storage_ttl(&self, kind: &TokenKind) -> Duration {
+        match (kind, self.session_idle) {
+            (TokenKind::Session, Some(idle)) => idle.min(self.session),
+            _ => self.ttl(kind),
+        }
+    }
+
+    /// Returns the longest time to live any token may have.
+    pub fn max(&self) -> Duration {
+        self.session
+            .max(self.verification)
+            .max(self.reset)
+            .max(self.refresh)
+    }
+}
+
 #[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Debug, Clone, strum_macros::Display)]
 pub enum TokenKind {
     Session = 0,
     Verification = 1,
     Reset = 2,
+    Refresh = 3,
+}
+
+impl TokenKind {
+    /// Returns the `typ` header tokens of this kind are stamped with, so they cannot be mistaken for one
+    /// another. Session tokens keep the generic type, since they are the ones other services consume.
+    pub fn typ(&self) -> &'static str {
+        match self {
+            TokenKind::Session => TOKEN_TYPE_GENERIC,
+            TokenKind::Verification => "verification+jwt",
+            TokenKind::Reset => "reset+jwt",
+            TokenKind::Refresh => "refresh+jwt",
+        }
+    }
+}
+
+/// Deserializes a NumericDate as described by RFC 7519, as well as the `SystemTime` structure tokens
+/// issued by older versions were serialized with.
+fn deserialize_numeric_date<'de, D>(deserializer: D) -> std::result::Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumericDate {
+        Seconds(usize),
+        Legacy { secs_since_epoch: usize },
+    }
+
+    Ok(match NumericDate::deserialize(deserializer)? {
+        NumericDate::Seconds(secs) => secs,
+        NumericDate::Legacy { secs_since_epoch } => secs_since_epoch,
+    })
 }
 
 #[derive(Serialize, Deserialize, Hash, Debug, Clone, PartialEq)]
 pub struct Token {
-    pub jti: String,     // JWT ID
-    pub exp: usize,      // expiration time (as UTC timestamp) - required
-    pub nbf: usize,      // not before time (as UTC timestamp) - non required
-    pub iat: SystemTime, // issued at: creation time
-    pub iss: String,     // issuer
-    pub sub: String,     // subject
-    pub knd: TokenKind,  // kind - required
+    pub jti: String, // JWT ID: random and unique
+    pub exp: usize,  // expiration time (as UTC timestamp) - required
+    pub nbf: usize,  // not before time (as UTC timestamp) - non required
+    #[serde(deserialize_with = "deserialize_numeric_date")]
+    pub iat: usize, // issued at: creation time (as UTC timestamp)
+    pub iss: String, // issuer
+    pub sub: String, // subject
+    pub knd: TokenKind, // kind - required
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "Token::default_secret_value")]
     pub scr: Option<String>, // secret data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "Token::default_family_value")]
+    pub fam: Option<String>, // family the token belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "Token::default_audience_value")]
+    pub aud: Option<String>, // audience: the only recipient the token is intended for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "Token::default_scope_value")]
+    pub scope: Option<String>, // space-delimited list of scopes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "Token::default_confirmation_value")]
+    pub cnf: Option<Confirmation>, // confirmation: the key the token is bound to
 }
 
 impl Token {
@@ -51,6 +289,22 @@ impl Token {
         None
     }
 
+    fn default_family_value() -> Option<String> {
+        None
+    }
+
+    fn default_audience_value() -> Option<String> {
+        None
+    }
+
+    fn default_scope_value() -> Option<String> {
+        None
+    }
+
+    fn default_confirmation_value() -> Option<Confirmation> {
+        None
+    }
+
     pub fn new(
         iss: &str,
         sub: &str,
@@ -58,22 +312,77 @@ impl Token {
         kind: TokenKind,
         secret: Option<&str>,
     ) -> Self {
-        let mut token = Token {
-            jti: rand::thread_rng().gen::<u64>().to_string(), // noise
-            exp: time::unix_timestamp(SystemTime::now() + timeout),
-            nbf: time::unix_timestamp(SystemTime::now()),
-            iat: SystemTime::now(),
+        let now = SystemTime::now();
+        Token {
+            jti: crypto::get_random_string(TOKEN_ID_LEN),
+            exp: time::unix_timestamp(now + timeout),
+            nbf: time::unix_timestamp(now),
+            iat: time::unix_timestamp(now),
             iss: iss.to_string(),
             sub: sub.to_string(),
             knd: kind,
             scr: secret.map(ToString::to_string),
+            fam: None,
+            aud: None,
+            scope: None,
+            cnf: None,
+        }
+    }
+
+    /// Encrypts the secret data of the token, if any, so it cannot be read by anyone holding the token. The
+    /// secret is bound to the token's id, so it cannot be moved into any other token.
+    pub fn seal_secret(&mut self, key: &[u8]) -> Result<()> {
+        let Some(secret) = &self.scr else {
+            return Ok(());
+        };
+
+        let sealed = crypto::encrypt_aes_gcm(key, self.jti.as_bytes(), secret.as_bytes())?;
+        self.scr = Some(format!(
+            "{}{}",
+            SEALED_SECRET_PREFIX,
+            general_purpose::URL_SAFE_NO_PAD.encode(sealed)
+        ));
+        Ok(())
+    }
+
+    /// Decrypts the secret data of the token, if any, as encrypted by [`Token::seal_secret`]. Tokens issued
+    /// before secrets got sealed keep them in plain text, as signed, so these are left as they are.
+    pub fn unseal_secret(&mut self, key: &[u8]) -> Result<()> {
+        let Some(sealed) = self
+            .scr
+            .as_deref()
+            .and_then(|secret| secret.strip_prefix(SEALED_SECRET_PREFIX))
+        else {
+            return Ok(());
         };
 
-        let mut hasher = DefaultHasher::new();
-        token.hash(&mut hasher);
-        token.jti = hasher.finish().to_string();
+        let sealed = general_purpose::URL_SAFE_NO_PAD
+            .decode(sealed)
+            .map_err(|err| {
+                warn!(error = err.to_string(), "decoding token secret from base64");
+                Error::InvalidToken
+            })?;
 
-        token
+        let secret = crypto::decrypt_aes_gcm(key, self.jti.as_bytes(), &sealed)?;
+        let secret = String::from_utf8(secret).map_err(|err| {
+            warn!(error = err.to_string(), "parsing token secret to string");
+            Error::InvalidToken
+        })?;
+
+        self.scr = Some(secret);
+        Ok(())
+    }
+
+    /// Returns all the scopes the token has been granted.
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.iter().flat_map(|scope| scope.split_whitespace())
+    }
+
+    /// Returns true if, and only if, the token has been granted all the given scopes.
+    pub fn has_scopes<S: AsRef<str>>(&self, scopes: &[S]) -> bool {
+        scopes
+            .iter()
+            .all(|required| self.scopes().any(|scope| scope == required.as_ref()))
     }
 }
 
@@ -89,14 +398,20 @@ impl TokenDefinition for Token {
     fn get_secret(&self) -> Option<&str> {
         self.scr.as_deref()
     }
+
+    fn get_family(&self) -> Option<&str> {
+        self.fam.as_deref()
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use super::{Token, TokenKind};
+    use super::{Token, TokenKind, TtlPolicy};
+    use crate::result::Error;
     use crate::time::unix_timestamp;
     use crate::{crypto, time};
     use base64::{engine::general_purpose, Engine as _};
+    use jsonwebtoken::Algorithm;
     use std::time::{Duration, SystemTime};
 
     pub const TEST_DEFAULT_TOKEN_TIMEOUT: u64 = 60;
@@ -114,7 +429,8 @@ pub mod tests {
         let claim = Token::new(ISS, &SUB.to_string(), timeout, TokenKind::Session, None);
         let after = SystemTime::now();
 
-        assert!(claim.iat >= before && claim.iat <= after);
+        assert!(claim.iat >= unix_timestamp(before) && claim.iat <= unix_timestamp(after));
+        assert_eq!(claim.jti.len(), 32);
         assert!(claim.exp >= unix_timestamp(before + timeout));
         assert!(claim.exp <= unix_timestamp(after + timeout));
         assert_eq!(claim.knd, TokenKind::Session);
@@ -134,18 +450,160 @@ pub mod tests {
         let after = SystemTime::now();
 
         let secret = general_purpose::STANDARD.decode(JWT_SECRET).unwrap();
-        let token = crypto::sign_jwt(&secret, claim).unwrap();
+        let token = crypto::sign_jwt(&secret, Algorithm::ES256, None, claim).unwrap();
 
         let public = general_purpose::STANDARD.decode(JWT_PUBLIC).unwrap();
-        let claim = crypto::decode_jwt::<Token>(&public, &token).unwrap();
+        let claim = crypto::decode_jwt::<Token>(&public, Algorithm::ES256, &token).unwrap();
 
-        assert!(claim.iat >= before && claim.iat <= after);
+        assert!(claim.iat >= unix_timestamp(before) && claim.iat <= unix_timestamp(after));
         assert!(claim.exp >= unix_timestamp(before + timeout));
         assert!(claim.exp <= unix_timestamp(after + timeout));
         assert_eq!(ISS, claim.iss);
         assert_eq!(SUB.to_string(), claim.sub);
     }
 
+    #[test]
+    fn token_legacy_claims_should_not_fail() {
+        let claim: Token = serde_json::from_str(
+            r#"{
+                "jti": "7283648234987234",
+                "exp": 1687258800,
+                "nbf": 1687255200,
+                "iat": {"secs_since_epoch": 1687255200, "nanos_since_epoch": 123456789},
+                "iss": "test",
+                "sub": "999",
+                "knd": "Session"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(claim.iat, 1687255200);
+        assert_eq!(claim.jti, "7283648234987234");
+        assert_eq!(claim.knd, TokenKind::Session);
+    }
+
+    #[test]
+    fn token_claims_should_be_numeric_dates() {
+        let timeout = Duration::from_secs(TEST_DEFAULT_TOKEN_TIMEOUT);
+        let claim = Token::new("test", "999", timeout, TokenKind::Session, None);
+        let json = serde_json::to_value(&claim).unwrap();
+
+        assert!(json["iat"].is_u64());
+        assert!(json["exp"].is_u64());
+        assert!(json["nbf"].is_u64());
+        assert_eq!(serde_json::from_value::<Token>(json).unwrap(), claim);
+    }
+
+    #[test]
+    fn token_new_should_be_unique() {
+        let timeout = Duration::from_secs(TEST_DEFAULT_TOKEN_TIMEOUT);
+        let claim = Token::new("test", "999", timeout, TokenKind::Session, None);
+        let other = Token::new("test", "999", timeout, TokenKind::Session, None);
+        assert_ne!(claim.jti, other.jti);
+    }
+
+    #[test]
+    fn ttl_policy_should_not_fail() {
+        let policy = TtlPolicy {
+            session: Duration::from_secs(60),
+            verification: Duration::from_secs(60),
+            reset: Duration::from_secs(15),
+            refresh: Duration::from_secs(3600),
+            session_idle: None,
+        };
+
+        assert_eq!(policy.ttl(&TokenKind::Session), Duration::from_secs(60));
+        assert_eq!(
+            policy.ttl(&TokenKind::Verification),
+            Duration::from_secs(60)
+        );
+        assert_eq!(policy.ttl(&TokenKind::Reset), Duration::from_secs(15));
+        assert_eq!(policy.ttl(&TokenKind::Refresh), Duration::from_secs(3600));
+        assert_eq!(policy.max(), Duration::from_secs(3600));
+        assert_eq!(
+            policy.storage_ttl(&TokenKind::Session),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn ttl_policy_with_idle_timeout_should_not_fail() {
+        let policy = TtlPolicy {
+            session: Duration::from_secs(60),
+            verification: Duration::from_secs(60),
+            reset: Duration::from_secs(60),
+            refresh: Duration::from_secs(3600),
+            session_idle: Some(Duration::from_secs(10)),
+        };
+
+        assert_eq!(policy.ttl(&TokenKind::Session), Duration::from_secs(60));
+        assert_eq!(
+            policy.storage_ttl(&TokenKind::Session),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            policy.storage_ttl(&TokenKind::Reset),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn token_has_scopes_should_not_fail() {
+        let timeout = Duration::from_secs(TEST_DEFAULT_TOKEN_TIMEOUT);
+        let mut claim = Token::new("test", "999", timeout, TokenKind::Session, None);
+        assert!(claim.has_scopes::<&str>(&[]));
+        assert!(!claim.has_scopes(&["read"]));
+
+        claim.scope = Some("read write".to_string());
+        assert!(claim.has_scopes(&["write", "read"]));
+        assert!(!claim.has_scopes(&["read", "admin"]));
+    }
+
+    #[test]
+    fn token_seal_secret_should_not_fail() {
+        const KEY: &[u8] = &[7; 32];
+
+        let timeout = Duration::from_secs(TEST_DEFAULT_TOKEN_TIMEOUT);
+        let original = Token::new("test", "999", timeout, TokenKind::Reset, Some("secret"));
+
+        let mut claim = original.clone();
+        claim.seal_secret(KEY).unwrap();
+        assert!(claim.scr.is_some());
+        assert_ne!(claim.scr, original.scr);
+
+        claim.unseal_secret(KEY).unwrap();
+        assert_eq!(claim, original);
+    }
+
+    #[test]
+    fn token_unseal_plain_secret_should_not_fail() {
+        const KEY: &[u8] = &[7; 32];
+
+        let timeout = Duration::from_secs(TEST_DEFAULT_TOKEN_TIMEOUT);
+        let original = Token::new("test", "999", timeout, TokenKind::Reset, Some("secret"));
+
+        let mut claim = original.clone();
+        claim.unseal_secret(KEY).unwrap();
+        assert_eq!(claim, original);
+    }
+
+    #[test]
+    fn token_unseal_moved_secret_should_fail() {
+        const KEY: &[u8] = &[7; 32];
+
+        let timeout = Duration::from_secs(TEST_DEFAULT_TOKEN_TIMEOUT);
+        let mut claim = Token::new("test", "999", timeout, TokenKind::Reset, Some("secret"));
+        claim.seal_secret(KEY).unwrap();
+
+        let mut other = Token::new("test", "999", timeout, TokenKind::Reset, None);
+        other.scr = claim.scr;
+
+        other
+            .unseal_secret(KEY)
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
     #[test]
     fn expired_token_verification_should_fail() {
         use crate::crypto;
@@ -158,9 +616,9 @@ pub mod tests {
         claim.exp = time::unix_timestamp(SystemTime::now() - Duration::from_secs(61));
 
         let secret = general_purpose::STANDARD.decode(JWT_SECRET).unwrap();
-        let token = crypto::sign_jwt(&secret, claim).unwrap();
+        let token = crypto::sign_jwt(&secret, Algorithm::ES256, None, claim).unwrap();
         let public = general_purpose::STANDARD.decode(JWT_PUBLIC).unwrap();
 
-        assert!(crypto::decode_jwt::<Token>(&public, &token).is_err());
+        assert!(crypto::decode_jwt::<Token>(&public, Algorithm::ES256, &token).is_err());
     }
 }
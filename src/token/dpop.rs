@@ -0,0 +1,354 @@
+//! Proof-of-possession as described by RFC 9449 (DPoP), so a token bound to a key pair is useless to
+//! anyone not holding the private part of that key.
+
+use crate::crypto;
+use crate::result::{Error, Result};
+use crate::time;
+use base64::{engine::general_purpose, Engine as _};
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+/// The `typ` header any DPoP proof must have.
+pub const DPOP_TYPE: &str = "dpop+jwt";
+/// For how long a DPoP proof is accepted since it was issued.
+pub const DPOP_PROOF_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// The claims of a DPoP proof.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DpopClaims {
+    pub jti: String, // unique id of the proof
+    pub htm: String, // method of the request the proof is intended for
+    pub htu: String, // uri of the request the proof is intended for
+    pub iat: usize,  // issued at (as UTC timestamp)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub ath: Option<String>, // hash of the access token presented along with the proof
+}
+
+/// A DPoP proof as presented in a request, along with the details of that very same request.
+#[derive(Debug, Clone)]
+pub struct DpopRequest {
+    pub proof: String,
+    pub htm: String,
+    pub htu: String, // the path of the request, any origin is accepted
+    pub access_token: Option<String>,
+}
+
+impl DpopRequest {
+    pub fn new(proof: &str, htm: &str, htu: &str) -> Self {
+        DpopRequest {
+            proof: proof.to_string(),
+            htm: htm.to_string(),
+            htu: htu.to_string(),
+            access_token: None,
+        }
+    }
+
+    /// Returns the same request, but requiring the proof to be bound to the given access token.
+    pub fn with_access_token(mut self, access_token: &str) -> Self {
+        self.access_token = Some(access_token.to_string());
+        self
+    }
+}
+
+/// A DPoP proof whose signature has been checked against the key it carries.
+#[derive(Debug, Clone)]
+pub struct DpopProof {
+    pub jkt: String, // thumbprint of the key the proof has been signed with
+    pub claims: DpopClaims,
+}
+
+impl DpopProof {
+    /// Returns the proof in the given request if, and only if, it has been issued for that very request
+    /// no longer than [`DPOP_PROOF_MAX_AGE`] ago. Replays are not checked at all.
+    pub fn from_request(request: &DpopRequest) -> Result<Self> {
+        let proof = decode(&request.proof)?;
+
+        if !proof.claims.htm.eq_ignore_ascii_case(&request.htm) {
+            warn!(htm = proof.claims.htm, "checking dpop proof's method");
+            return Err(Error::InvalidToken);
+        }
+
+        if uri_path(&proof.claims.htu) != uri_path(&request.htu) {
+            warn!(htu = proof.claims.htu, "checking dpop proof's uri");
+            return Err(Error::InvalidToken);
+        }
+
+        let now = time::unix_timestamp(SystemTime::now());
+        if now.abs_diff(proof.claims.iat) as u64 > DPOP_PROOF_MAX_AGE.as_secs() {
+            warn!(iat = proof.claims.iat, "checking dpop proof's issuing time");
+            return Err(Error::InvalidToken);
+        }
+
+        if let Some(access_token) = &request.access_token {
+            if proof.claims.ath.as_deref() != Some(&access_token_hash(access_token)) {
+                warn!("checking dpop proof's access token hash");
+                return Err(Error::InvalidToken);
+            }
+        }
+
+        Ok(proof)
+    }
+}
+
+/// Returns the proof if, and only if, it is of the DPoP type and has been signed, with an asymmetric
+/// algorithm, by the public key in its header.
+pub fn decode(proof: &str) -> Result<DpopProof> {
+    let header = jsonwebtoken::decode_header(proof).map_err(|err| {
+        warn!(error = err.to_string(), "decoding dpop proof's header");
+        Error::InvalidToken
+    })?;
+
+    if header.typ.as_deref() != Some(DPOP_TYPE) {
+        warn!(typ = header.typ, "checking dpop proof's type");
+        return Err(Error::InvalidToken);
+    }
+
+    if crypto::is_symmetric(header.alg) {
+        warn!(
+            alg = format!("{:?}", header.alg),
+            "checking dpop proof's algorithm"
+        );
+        return Err(Error::InvalidToken);
+    }
+
+    let jwk = header.jwk.ok_or_else(|| {
+        warn!("finding dpop proof's public key");
+        Error::InvalidToken
+    })?;
+
+    let key = DecodingKey::from_jwk(&jwk).map_err(|err| {
+        warn!(error = err.to_string(), "building decoding key from jwk");
+        Error::InvalidToken
+    })?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.required_spec_claims = HashSet::new();
+    validation.validate_exp = false;
+
+    let claims = jsonwebtoken::decode::<DpopClaims>(proof, &key, &validation)
+        .map_err(|err| {
+            warn!(error = err.to_string(), "checking dpop proof's signature");
+            Error::InvalidToken
+        })?
+        .claims;
+
+    Ok(DpopProof {
+        jkt: thumbprint(&jwk)?,
+        claims,
+    })
+}
+
+/// Returns the thumbprint of the given public key as described by RFC 7638, this is, the base64 url
+/// encoded sha256 digest of its required members in lexicographic order.
+pub fn thumbprint(jwk: &Jwk) -> Result<String> {
+    let members = match &jwk.algorithm {
+        AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":{},"kty":"EC","x":{},"y":{}}}"#,
+            json_string(&params.curve)?,
+            json_string(&params.x)?,
+            json_string(&params.y)?,
+        ),
+        AlgorithmParameters::RSA(params) => format!(
+            r#"{{"e":{},"kty":"RSA","n":{}}}"#,
+            json_string(&params.e)?,
+            json_string(&params.n)?,
+        ),
+        AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":{},"kty":"OKP","x":{}}}"#,
+            json_string(&params.curve)?,
+            json_string(&params.x)?,
+        ),
+        AlgorithmParameters::OctetKey(_) => {
+            warn!("computing thumbprint of a symmetric key");
+            return Err(Error::InvalidToken);
+        }
+    };
+
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(openssl::sha::sha256(members.as_bytes())))
+}
+
+/// Returns the value of the `ath` claim for the given access token.
+pub fn access_token_hash(access_token: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(openssl::sha::sha256(access_token.as_bytes()))
+}
+
+fn json_string<S: Serialize>(value: &S) -> Result<String> {
+    serde_json::to_string(value).map_err(|err| {
+        error!(error = err.to_string(), "serializing jwk member to json");
+        Error::Unknown
+    })
+}
+
+/// Returns the path of the given uri, without any query or fragment.
+fn uri_path(uri: &str) -> &str {
+    let path = match uri.find("://") {
+        Some(scheme_end) => {
+            let authority = &uri[scheme_end + 3..];
+            authority
+                .find('/')
+                .map(|path_start| &authority[path_start..])
+                .unwrap_or("/")
+        }
+        None => uri,
+    };
+
+    path.split(['?', '#']).next().unwrap_or_default()
+}
+
+/// Signs a DPoP proof with the given private key in PEM format, whose public part is the given JWK.
+pub fn sign(private: &[u8], alg: Algorithm, jwk: Jwk, claims: &DpopClaims) -> Result<String> {
+    let mut header = jsonwebtoken::Header::new(alg);
+    header.typ = Some(DPOP_TYPE.to_string());
+    header.jwk = Some(jwk);
+    crypto::sign_jwt_with_header(private, header, claims)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        access_token_hash, sign, thumbprint, uri_path, DpopClaims, DpopProof, DpopRequest,
+    };
+    use crate::base64::B64_CUSTOM_ENGINE;
+    use crate::result::Error;
+    use crate::{crypto, time};
+    use base64::Engine;
+    use jsonwebtoken::jwk::Jwk;
+    use jsonwebtoken::Algorithm;
+    use lazy_static::lazy_static;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use std::time::SystemTime;
+
+    lazy_static! {
+        pub static ref DPOP_KEY_PAIR: (Vec<u8>, Jwk) = {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let pkey = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            let private = pkey.private_key_to_pem_pkcs8().unwrap();
+            let (x, y) = crypto::ec_coordinates(&pkey.public_key_to_pem().unwrap()).unwrap();
+
+            let jwk = serde_json::from_value(serde_json::json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "x": B64_CUSTOM_ENGINE.encode(x),
+                "y": B64_CUSTOM_ENGINE.encode(y),
+            }))
+            .unwrap();
+
+            (private, jwk)
+        };
+    }
+
+    /// Returns a brand new DPoP proof for the given request, signed with the test key pair.
+    pub fn new_dpop_proof(htm: &str, htu: &str, access_token: Option<&str>) -> String {
+        let claims = DpopClaims {
+            jti: crypto::get_random_string(16),
+            htm: htm.to_string(),
+            htu: htu.to_string(),
+            iat: time::unix_timestamp(SystemTime::now()),
+            ath: access_token.map(access_token_hash),
+        };
+
+        sign(
+            &DPOP_KEY_PAIR.0,
+            Algorithm::ES256,
+            DPOP_KEY_PAIR.1.clone(),
+            &claims,
+        )
+        .unwrap()
+    }
+
+    pub fn dpop_thumbprint() -> String {
+        thumbprint(&DPOP_KEY_PAIR.1).unwrap()
+    }
+
+    #[test]
+    fn thumbprint_should_not_fail() {
+        // example from RFC 7638, section 3.1
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            thumbprint(&jwk).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn uri_path_should_not_fail() {
+        assert_eq!(uri_path("https://rauth.eu/session?id=1#top"), "/session");
+        assert_eq!(uri_path("https://rauth.eu"), "/");
+        assert_eq!(uri_path("/session.Session/Login"), "/session.Session/Login");
+    }
+
+    #[test]
+    fn dpop_proof_should_not_fail() {
+        let proof = new_dpop_proof("POST", "https://rauth.eu/session", Some("token"));
+        let request = DpopRequest::new(&proof, "post", "/session").with_access_token("token");
+
+        let proof = DpopProof::from_request(&request).unwrap();
+        assert_eq!(proof.jkt, dpop_thumbprint());
+    }
+
+    #[test]
+    fn dpop_proof_wrong_request_should_fail() {
+        let proof = new_dpop_proof("POST", "https://rauth.eu/session", None);
+        for request in [
+            DpopRequest::new(&proof, "GET", "/session"),
+            DpopRequest::new(&proof, "POST", "/sessions"),
+            DpopRequest::new(&proof, "POST", "/session").with_access_token("token"),
+        ] {
+            DpopProof::from_request(&request)
+                .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+                .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn dpop_proof_expired_should_fail() {
+        let claims = DpopClaims {
+            jti: "dummy".to_string(),
+            htm: "POST".to_string(),
+            htu: "/session".to_string(),
+            iat: time::unix_timestamp(SystemTime::now()) - 61,
+            ath: None,
+        };
+
+        let proof = sign(
+            &DPOP_KEY_PAIR.0,
+            Algorithm::ES256,
+            DPOP_KEY_PAIR.1.clone(),
+            &claims,
+        )
+        .unwrap();
+        DpopProof::from_request(&DpopRequest::new(&proof, "POST", "/session"))
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn dpop_proof_wrong_type_should_fail() {
+        let claims = DpopClaims {
+            jti: "dummy".to_string(),
+            htm: "POST".to_string(),
+            htu: "/session".to_string(),
+            iat: time::unix_timestamp(SystemTime::now()),
+            ath: None,
+        };
+
+        let proof = crypto::sign_jwt(&DPOP_KEY_PAIR.0, Algorithm::ES256, None, &claims).unwrap();
+        DpopProof::from_request(&DpopRequest::new(&proof, "POST", "/session"))
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+}
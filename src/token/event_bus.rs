@@ -0,0 +1,86 @@
+use super::{application::TokenEventBus, domain::Token};
+use crate::{
+    rabbitmq::EventKind,
+    result::{Error, Result},
+};
+use async_trait::async_trait;
+use deadpool_lapin::Pool;
+use lapin::{options::*, BasicProperties};
+use serde_json;
+
+#[derive(Serialize, Deserialize)]
+struct TokenEventPayload<'a> {
+    pub(super) token_id: &'a str,
+    pub(super) token_subject: &'a str,
+    pub(super) token_expiration: usize,
+    pub(super) event_issuer: &'a str,
+    pub(super) event_kind: EventKind,
+}
+
+pub struct RabbitMqTokenBus<'a> {
+    pub pool: &'a Pool,
+    pub exchange: &'a str,
+    pub issuer: &'a str,
+}
+
+#[async_trait]
+impl<'a> TokenEventBus for RabbitMqTokenBus<'a> {
+    #[instrument(skip(self))]
+    async fn emit_token_revoked(&self, token: &Token) -> Result<()> {
+        let event = TokenEventPayload {
+            token_id: &token.jti,
+            token_subject: &token.sub,
+            token_expiration: token.exp,
+            event_issuer: self.issuer,
+            event_kind: EventKind::Revoked,
+        };
+
+        let payload = serde_json::to_string(&event)
+            .map(|str| str.into_bytes())
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "serializing \"token revoked\" event data to json",
+                );
+                Error::Unknown
+            })?;
+
+        let connection = self.pool.get().await.map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "pulling connection from rabbitmq pool",
+            );
+            Error::Unknown
+        })?;
+
+        connection
+            .create_channel()
+            .await
+            .map_err(|err| {
+                error!(error = err.to_string(), "creating rabbitmq channel",);
+                Error::Unknown
+            })?
+            .basic_publish(
+                self.exchange,
+                "",
+                BasicPublishOptions::default(),
+                &payload,
+                BasicProperties::default(),
+            )
+            .await
+            .map_err(|err| {
+                error!(error = err.to_string(), "emitting \"token revoked\" event",);
+                Error::Unknown
+            })?
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "confirming \"token revoked\" event reception",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(())
+    }
+}
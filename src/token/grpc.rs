@@ -0,0 +1,108 @@
+use super::application::{TokenApplication, TokenEventBus, TokenRepository};
+use super::domain::RevocationScope;
+use super::keyring::{Jwk, KeyRing};
+use crate::result::Error;
+use crate::{crypto, time};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tonic::{Request, Response, Status};
+
+// Import the generated rust code into module
+mod proto {
+    tonic::include_proto!("token");
+}
+
+// Proto generated server traits
+use proto::token_admin_server::TokenAdmin;
+pub use proto::token_admin_server::TokenAdminServer;
+use proto::token_server::Token;
+pub use proto::token_server::TokenServer;
+
+// Proto message structs
+use proto::{Empty, JwksResponse, RevokeBeforeRequest};
+
+pub struct TokenGrpcService {
+    pub keyring: &'static KeyRing,
+    pub jwks_max_age: Duration,
+}
+
+/// Operations intended for administrators only, which require the admin secret in the admin header.
+pub struct TokenAdminGrpcService<T: TokenRepository + Sync + Send, B: TokenEventBus + Sync + Send> {
+    pub token_app: Arc<TokenApplication<'static, T, B>>,
+    pub admin_header: &'static str,
+    pub admin_secret: &'static str,
+}
+
+impl<T: TokenRepository + Sync + Send, B: TokenEventBus + Sync + Send> TokenAdminGrpcService<T, B> {
+    fn authorize<R>(&self, request: &Request<R>) -> Result<(), Error> {
+        let secret = request
+            .metadata()
+            .get(self.admin_header)
+            .map(|secret| secret.as_bytes())
+            .unwrap_or_default();
+
+        if !crypto::secure_eq(secret, self.admin_secret.as_bytes()) {
+            warn!("checking admin secret");
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Jwk> for proto::Jwk {
+    fn from(value: Jwk) -> Self {
+        proto::Jwk {
+            kty: value.kty,
+            kid: value.kid,
+            r#use: value.usage,
+            alg: value.alg,
+            crv: value.crv.unwrap_or_default(),
+            x: value.x.unwrap_or_default(),
+            y: value.y.unwrap_or_default(),
+            n: value.n.unwrap_or_default(),
+            e: value.e.unwrap_or_default(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Token for TokenGrpcService {
+    #[instrument(skip(self))]
+    async fn jwks(&self, _: Request<Empty>) -> Result<Response<JwksResponse>, Status> {
+        let jwks = self.keyring.jwks()?;
+        Ok(Response::new(JwksResponse {
+            keys: jwks.keys.into_iter().map(Into::into).collect(),
+            max_age: self.keyring.max_age(self.jwks_max_age).as_secs(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl<T: 'static + TokenRepository + Sync + Send, B: 'static + TokenEventBus + Sync + Send>
+    TokenAdmin for TokenAdminGrpcService<T, B>
+{
+    #[instrument(skip(self, request))]
+    async fn revoke_before(
+        &self,
+        request: Request<RevokeBeforeRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        self.authorize(&request)?;
+
+        let msg_ref = request.into_inner();
+        let scope = match (msg_ref.sub.is_empty(), msg_ref.iss.is_empty()) {
+            (true, true) => RevocationScope::Global,
+            (false, true) => RevocationScope::Subject(msg_ref.sub),
+            (true, false) => RevocationScope::Issuer(msg_ref.iss),
+            (false, false) => return Err(Error::InvalidFormat.into()),
+        };
+
+        let before = match msg_ref.before {
+            0 => time::unix_timestamp(SystemTime::now()),
+            before => before as usize,
+        };
+
+        self.token_app.revoke_before(&scope, before).await?;
+        Ok(Response::new(Empty {}))
+    }
+}
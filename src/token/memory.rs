@@ -0,0 +1,341 @@
+use super::application::{TokenEventBus, TokenRepository};
+use super::domain::Token;
+use crate::result::{Error, Result};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+type Entry<T> = (T, Option<Instant>); // value, expiration time
+
+fn is_alive<T>(entry: &Entry<T>) -> bool {
+    entry
+        .1
+        .map(|deadline| deadline > Instant::now())
+        .unwrap_or(true)
+}
+
+fn deadline(expire: Option<u64>) -> Option<Instant> {
+    expire.map(|expire| Instant::now() + Duration::from_secs(expire))
+}
+
+/// A [`TokenRepository`] holding all the tokens in the process' memory, so they get lost on restart.
+/// Expired entries are never returned, and get purged whenever a new one is saved.
+#[derive(Default)]
+pub struct InMemoryTokenRepository {
+    tokens: RwLock<HashMap<String, Entry<String>>>,
+    subjects: RwLock<HashMap<String, Entry<HashSet<String>>>>,
+}
+
+impl InMemoryTokenRepository {
+    fn lock_tokens_error<E: ToString>(err: E) -> Error {
+        error!(error = err.to_string(), "locking tokens");
+        Error::Unknown
+    }
+}
+
+#[async_trait]
+impl TokenRepository for InMemoryTokenRepository {
+    #[instrument(skip(self))]
+    async fn find(&self, key: &str) -> Result<String> {
+        let tokens = self.tokens.read().map_err(Self::lock_tokens_error)?;
+        tokens
+            .get(key)
+            .filter(|entry| is_alive(entry))
+            .map(|entry| entry.0.clone())
+            .ok_or(Error::NotFound)
+    }
+
+    #[instrument(skip(self))]
+    async fn find_many(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let tokens = self.tokens.read().map_err(Self::lock_tokens_error)?;
+        Ok(keys
+            .iter()
+            .map(|key| {
+                tokens
+                    .get(key)
+                    .filter(|entry| is_alive(entry))
+                    .map(|entry| entry.0.clone())
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn save(&self, key: &str, token: &str, expire: Option<u64>) -> Result<()> {
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        tokens.retain(|_, entry| is_alive(entry));
+        tokens.insert(key.to_string(), (token.to_string(), deadline(expire)));
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn insert(&self, key: &str, token: &str, expire: Option<u64>) -> Result<bool> {
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        tokens.retain(|_, entry| is_alive(entry));
+        if tokens.contains_key(key) {
+            return Ok(false);
+        }
+
+        tokens.insert(key.to_string(), (token.to_string(), deadline(expire)));
+        Ok(true)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, key: &str) -> Result<()> {
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        tokens.remove(key);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn increment(&self, key: &str, expire: u64) -> Result<u64> {
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        tokens.retain(|_, entry| is_alive(entry));
+
+        let count = match tokens.get(key) {
+            Some((count, _)) => count.parse::<u64>().map_err(|err| {
+                error!(error = err.to_string(), "parsing counter to integer");
+                Error::Unknown
+            })?,
+            None => 0,
+        } + 1;
+
+        tokens.insert(key.to_string(), (count.to_string(), deadline(Some(expire))));
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    async fn touch(&self, key: &str, expire: u64) -> Result<()> {
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        let entry = tokens
+            .get_mut(key)
+            .filter(|entry| is_alive(entry))
+            .ok_or(Error::NotFound)?;
+
+        entry.1 = deadline(Some(expire));
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn consume(&self, key: &str) -> Result<String> {
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        tokens
+            .remove(key)
+            .filter(is_alive)
+            .map(|entry| entry.0)
+            .ok_or(Error::NotFound)
+    }
+
+    #[instrument(skip(self))]
+    async fn index(&self, sub: &str, key: &str, expire: Option<u64>) -> Result<()> {
+        let mut subjects = self.subjects.write().map_err(Self::lock_tokens_error)?;
+        subjects.retain(|_, entry| is_alive(entry));
+
+        let entry = subjects
+            .entry(sub.to_string())
+            .or_insert_with(|| (HashSet::new(), Some(Instant::now())));
+
+        entry.0.insert(key.to_string());
+        // the index must live as long as the longest-lived token it holds
+        entry.1 = match (entry.1, deadline(expire)) {
+            (Some(current), Some(deadline)) => Some(current.max(deadline)),
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_subject(&self, sub: &str) -> Result<Vec<String>> {
+        let keys: Vec<String> = {
+            // block is required because of lock release
+            let subjects = self.subjects.read().map_err(Self::lock_tokens_error)?;
+            subjects
+                .get(sub)
+                .filter(|entry| is_alive(entry))
+                .map(|entry| entry.0.iter().cloned().collect())
+                .unwrap_or_default()
+        };
+
+        let tokens = self.tokens.read().map_err(Self::lock_tokens_error)?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| tokens.get(key))
+            .filter(|entry| is_alive(entry))
+            .map(|entry| entry.0.clone())
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_by_subject(&self, sub: &str) -> Result<()> {
+        let Some((keys, _)) = self
+            .subjects
+            .write()
+            .map_err(Self::lock_tokens_error)?
+            .remove(sub)
+        else {
+            return Ok(());
+        };
+
+        let mut tokens = self.tokens.write().map_err(Self::lock_tokens_error)?;
+        keys.iter().for_each(|key| {
+            tokens.remove(key);
+        });
+
+        Ok(())
+    }
+}
+
+/// A [`TokenEventBus`] that, instead of publishing the events, keeps track of them in the process' memory.
+#[derive(Default)]
+pub struct InMemoryTokenBus {
+    revoked: RwLock<Vec<String>>,
+}
+
+impl InMemoryTokenBus {
+    /// Returns the id of all those tokens whose revocation has been emitted, in order.
+    pub fn revoked_tokens(&self) -> Vec<String> {
+        self.revoked
+            .read()
+            .map(|revoked| revoked.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl TokenEventBus for InMemoryTokenBus {
+    #[instrument(skip(self))]
+    async fn emit_token_revoked(&self, token: &Token) -> Result<()> {
+        let mut revoked = self.revoked.write().map_err(|err| {
+            error!(error = err.to_string(), "locking token events for writing");
+            Error::Unknown
+        })?;
+
+        revoked.push(token.jti.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{InMemoryTokenBus, InMemoryTokenRepository};
+    use crate::result::Error;
+    use crate::token::application::{TokenEventBus, TokenRepository};
+    use crate::token::domain::{Token, TokenKind};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn in_memory_token_lifecycle_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Session::1", "token", Some(60)).await.unwrap();
+        assert_eq!(repo.find("Session::1").await.unwrap(), "token");
+
+        repo.delete("Session::1").await.unwrap();
+        repo.find("Session::1")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_consume_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Reset::1", "token", Some(60)).await.unwrap();
+        assert_eq!(repo.consume("Reset::1").await.unwrap(), "token");
+
+        repo.consume("Reset::1")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_insert_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Dpop::1", "expired", Some(0)).await.unwrap();
+        assert!(repo.insert("Dpop::1", "first", Some(60)).await.unwrap());
+        assert!(!repo.insert("Dpop::1", "second", Some(60)).await.unwrap());
+        assert_eq!(repo.find("Dpop::1").await.unwrap(), "first");
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_find_many_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Session::1", "token", None).await.unwrap();
+        repo.save("Session::2", "expired", Some(0)).await.unwrap();
+
+        let keys = ["Session::2", "Session::1", "Session::3"].map(String::from);
+        let tokens = repo.find_many(&keys).await.unwrap();
+        assert_eq!(tokens, vec![None, Some("token".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_touch_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Session::1", "token", Some(0)).await.unwrap();
+        repo.touch("Session::1", 60)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+
+        repo.save("Session::1", "token", Some(1)).await.unwrap();
+        repo.touch("Session::1", 60).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(repo.find("Session::1").await.unwrap(), "token");
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_expired_should_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Session::1", "token", Some(0)).await.unwrap();
+        repo.find("Session::1")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_subject_index_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        repo.save("Session::1", "first", Some(60)).await.unwrap();
+        repo.save("Session::2", "second", Some(60)).await.unwrap();
+        repo.save("Session::3", "another", Some(60)).await.unwrap();
+        repo.index("1", "Session::1", Some(60)).await.unwrap();
+        repo.index("1", "Session::2", Some(60)).await.unwrap();
+        repo.index("2", "Session::3", Some(60)).await.unwrap();
+
+        repo.delete("Session::2").await.unwrap();
+        assert_eq!(repo.find_by_subject("1").await.unwrap(), vec!["first"]);
+
+        repo.delete_by_subject("1").await.unwrap();
+        assert!(repo.find_by_subject("1").await.unwrap().is_empty());
+        assert!(repo.find("Session::1").await.is_err());
+        assert_eq!(repo.find("Session::3").await.unwrap(), "another");
+    }
+
+    #[tokio::test]
+    async fn in_memory_increment_should_not_fail() {
+        let repo = InMemoryTokenRepository::default();
+        assert_eq!(repo.increment("Attempts::1", 60).await.unwrap(), 1);
+        assert_eq!(repo.increment("Attempts::1", 60).await.unwrap(), 2);
+        assert_eq!(repo.find("Attempts::1").await.unwrap(), "2");
+
+        repo.delete("Attempts::1").await.unwrap();
+        assert_eq!(repo.increment("Attempts::1", 60).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn in_memory_token_bus_should_not_fail() {
+        let bus = InMemoryTokenBus::default();
+        let token = Token::new(
+            "test",
+            "999",
+            Duration::from_secs(60),
+            TokenKind::Session,
+            None,
+        );
+
+        bus.emit_token_revoked(&token).await.unwrap();
+        assert_eq!(bus.revoked_tokens(), vec![token.jti]);
+    }
+}
@@ -1,4 +1,16 @@
 pub mod application;
 pub mod domain;
+pub mod dpop;
+#[cfg(feature = "rabbitmq")]
+pub mod event_bus;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod keyring;
+#[cfg(feature = "in-memory")]
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
 #[cfg(feature = "redis-cache")]
 pub mod repository;
+#[cfg(feature = "rest")]
+pub mod rest;
@@ -0,0 +1,327 @@
+use super::application::TokenRepository;
+use crate::result::{Error, Result};
+use async_trait::async_trait;
+use chrono::{naive::NaiveDateTime, Duration as ChronoDuration, Utc};
+use sqlx::error::Error as SqlError;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::num::ParseIntError;
+use std::time::Duration;
+
+const QUERY_FIND_TOKEN: &str =
+    "SELECT token FROM tokens WHERE key = $1 AND (expires_at IS NULL OR expires_at > $2)";
+const QUERY_FIND_TOKENS: &str =
+    "SELECT key, token FROM tokens WHERE key = ANY($1) AND (expires_at IS NULL OR expires_at > $2)";
+const QUERY_UPSERT_TOKEN: &str =
+    "INSERT INTO tokens (key, token, expires_at) VALUES ($1, $2, $3) ON CONFLICT (key) DO UPDATE SET token = $2, expires_at = $3";
+const QUERY_INSERT_TOKEN: &str =
+    "INSERT INTO tokens (key, token, expires_at) VALUES ($1, $2, $3) ON CONFLICT (key) DO UPDATE SET token = $2, expires_at = $3 WHERE tokens.expires_at <= $4";
+const QUERY_DELETE_TOKEN: &str = "DELETE FROM tokens WHERE key = $1";
+const QUERY_INCREMENT_TOKEN: &str =
+    "INSERT INTO tokens (key, token, expires_at) VALUES ($1, '1', $2) ON CONFLICT (key) DO UPDATE SET token = CASE WHEN tokens.expires_at <= $3 THEN '1' ELSE (tokens.token::BIGINT + 1)::TEXT END, expires_at = $2 RETURNING token";
+const QUERY_TOUCH_TOKEN: &str =
+    "UPDATE tokens SET expires_at = $2 WHERE key = $1 AND (expires_at IS NULL OR expires_at > $3)";
+const QUERY_CONSUME_TOKEN: &str =
+    "DELETE FROM tokens WHERE key = $1 AND (expires_at IS NULL OR expires_at > $2) RETURNING token";
+const QUERY_UPSERT_SUBJECT: &str =
+    "INSERT INTO tokensubjects (sub, key, expires_at) VALUES ($1, $2, $3) ON CONFLICT (sub, key) DO UPDATE SET expires_at = $3";
+const QUERY_FIND_TOKENS_BY_SUBJECT: &str =
+    "SELECT tokens.token FROM tokens INNER JOIN tokensubjects ON tokensubjects.key = tokens.key WHERE tokensubjects.sub = $1 AND (tokens.expires_at IS NULL OR tokens.expires_at > $2)";
+const QUERY_DELETE_TOKENS_BY_SUBJECT: &str =
+    "DELETE FROM tokens WHERE key IN (SELECT key FROM tokensubjects WHERE sub = $1)";
+const QUERY_DELETE_SUBJECT: &str = "DELETE FROM tokensubjects WHERE sub = $1";
+const QUERY_SWEEP_TOKENS: &str = "DELETE FROM tokens WHERE expires_at <= $1";
+const QUERY_SWEEP_SUBJECTS: &str =
+    "DELETE FROM tokensubjects WHERE expires_at <= $1 OR key NOT IN (SELECT key FROM tokens)";
+
+pub struct PostgresTokenRepository<'a> {
+    pub pool: &'a PgPool,
+}
+
+impl<'a> PostgresTokenRepository<'a> {
+    fn expires_at(expire: Option<u64>) -> Result<Option<NaiveDateTime>> {
+        let Some(expire) = expire else {
+            return Ok(None);
+        };
+
+        let expire = ChronoDuration::from_std(Duration::from_secs(expire)).map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "parsing expiration time to duration",
+            );
+            Error::Unknown
+        })?;
+
+        Ok(Some(Utc::now().naive_utc() + expire))
+    }
+
+    /// Removes all those tokens, and their subject's index entries, that have already expired. Since
+    /// expired rows are never returned, this only prevents the tables from growing indefinitely.
+    #[instrument(skip(self))]
+    pub async fn sweep(&self) -> Result<()> {
+        let now = Utc::now().naive_utc();
+
+        let swept = sqlx::query(QUERY_SWEEP_TOKENS)
+            .bind(now)
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing sweep tokens query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        sqlx::query(QUERY_SWEEP_SUBJECTS)
+            .bind(now)
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing sweep subjects query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        info!(tokens = swept.rows_affected(), "expired tokens swept");
+        Ok(())
+    }
+
+    /// Sweeps the expired tokens once every period, forever.
+    pub async fn run_sweeper(&self, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.sweep().await {
+                warn!(error = err.to_string(), "sweeping expired tokens");
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> TokenRepository for PostgresTokenRepository<'a> {
+    #[instrument(skip(self))]
+    async fn find(&self, key: &str) -> Result<String> {
+        let row: (String,) = sqlx::query_as(QUERY_FIND_TOKEN)
+            .bind(key)
+            .bind(Utc::now().naive_utc())
+            .fetch_one(self.pool)
+            .await
+            .map_err(|err| {
+                if matches!(err, SqlError::RowNotFound) {
+                    return Error::NotFound;
+                }
+
+                error!(
+                    error = err.to_string(),
+                    "performing select by key query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(row.0)
+    }
+
+    #[instrument(skip(self))]
+    async fn find_many(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(QUERY_FIND_TOKENS)
+            .bind(keys)
+            .bind(Utc::now().naive_utc())
+            .fetch_all(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing select many query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        let mut tokens: HashMap<String, String> = rows.into_iter().collect();
+        Ok(keys.iter().map(|key| tokens.remove(key)).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn save(&self, key: &str, token: &str, expire: Option<u64>) -> Result<()> {
+        sqlx::query(QUERY_UPSERT_TOKEN)
+            .bind(key)
+            .bind(token)
+            .bind(Self::expires_at(expire)?)
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing upsert query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn insert(&self, key: &str, token: &str, expire: Option<u64>) -> Result<bool> {
+        // an expired token that has not been swept yet is as good as no token at all
+        let inserted = sqlx::query(QUERY_INSERT_TOKEN)
+            .bind(key)
+            .bind(token)
+            .bind(Self::expires_at(expire)?)
+            .bind(Utc::now().naive_utc())
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing insert query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(inserted.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, key: &str) -> Result<()> {
+        sqlx::query(QUERY_DELETE_TOKEN)
+            .bind(key)
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing delete query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn increment(&self, key: &str, expire: u64) -> Result<u64> {
+        // an expired counter that has not been swept yet starts over
+        let row: (String,) = sqlx::query_as(QUERY_INCREMENT_TOKEN)
+            .bind(key)
+            .bind(Self::expires_at(Some(expire))?)
+            .bind(Utc::now().naive_utc())
+            .fetch_one(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing increment query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        row.0.parse().map_err(|err: ParseIntError| {
+            error!(error = err.to_string(), "parsing counter to integer");
+            Error::Unknown
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn touch(&self, key: &str, expire: u64) -> Result<()> {
+        let updated = sqlx::query(QUERY_TOUCH_TOKEN)
+            .bind(key)
+            .bind(Self::expires_at(Some(expire))?)
+            .bind(Utc::now().naive_utc())
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing update expiration query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        if updated.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn consume(&self, key: &str) -> Result<String> {
+        let row: (String,) = sqlx::query_as(QUERY_CONSUME_TOKEN)
+            .bind(key)
+            .bind(Utc::now().naive_utc())
+            .fetch_one(self.pool)
+            .await
+            .map_err(|err| {
+                if matches!(err, SqlError::RowNotFound) {
+                    return Error::NotFound;
+                }
+
+                error!(
+                    error = err.to_string(),
+                    "performing delete returning query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(row.0)
+    }
+
+    #[instrument(skip(self))]
+    async fn index(&self, sub: &str, key: &str, expire: Option<u64>) -> Result<()> {
+        sqlx::query(QUERY_UPSERT_SUBJECT)
+            .bind(sub)
+            .bind(key)
+            .bind(Self::expires_at(expire)?)
+            .execute(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing upsert subject query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_subject(&self, sub: &str) -> Result<Vec<String>> {
+        let rows: Vec<(String,)> = sqlx::query_as(QUERY_FIND_TOKENS_BY_SUBJECT)
+            .bind(sub)
+            .bind(Utc::now().naive_utc())
+            .fetch_all(self.pool)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing select by subject query on postgres",
+                );
+                Error::Unknown
+            })?;
+
+        Ok(rows.into_iter().map(|row| row.0).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_by_subject(&self, sub: &str) -> Result<()> {
+        for query in [QUERY_DELETE_TOKENS_BY_SUBJECT, QUERY_DELETE_SUBJECT] {
+            sqlx::query(query)
+                .bind(sub)
+                .execute(self.pool)
+                .await
+                .map_err(|err| {
+                    error!(
+                        error = err.to_string(),
+                        "performing delete by subject query on postgres",
+                    );
+                    Error::Unknown
+                })?;
+        }
+
+        Ok(())
+    }
+}
@@ -6,10 +6,18 @@ use async_trait::async_trait;
 use reool::AsyncCommands;
 use reool::*;
 
+const SUBJECT_INDEX_PREFIX: &str = "Subject";
+
 pub struct RedisTokenRepository<'a> {
     pub pool: &'a RedisPool,
 }
 
+impl<'a> RedisTokenRepository<'a> {
+    fn subject_key(sub: &str) -> String {
+        format!("{}::{}", SUBJECT_INDEX_PREFIX, sub)
+    }
+}
+
 #[async_trait]
 impl<'a> TokenRepository for RedisTokenRepository<'a> {
     #[instrument(skip(self))]
@@ -31,6 +39,31 @@ impl<'a> TokenRepository for RedisTokenRepository<'a> {
         Ok(token)
     }
 
+    #[instrument(skip(self))]
+    async fn find_many(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let mut tokens = Vec::with_capacity(keys.len());
+        for key in keys {
+            let token: Option<Vec<u8>> = conn.get(key).await.map_err(|err| {
+                error!(error = err.to_string(), "performing GET command on redis",);
+                Error::Unknown
+            })?;
+
+            let token = token.map(String::from_utf8).transpose().map_err(|err| {
+                error!(error = err.to_string(), "parsing token to string",);
+                Error::Unknown
+            })?;
+
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
+
     #[instrument(skip(self))]
     async fn save(&self, key: &str, token: &str, expire: Option<u64>) -> Result<()> {
         let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
@@ -60,6 +93,31 @@ impl<'a> TokenRepository for RedisTokenRepository<'a> {
         Ok(())
     }
 
+    #[instrument(skip(self))]
+    async fn insert(&self, key: &str, token: &str, expire: Option<u64>) -> Result<bool> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let mut command = reool::cmd("SET");
+        command.arg(key).arg(token).arg("NX");
+        if let Some(expire) = expire {
+            // the expiration time must be set along with the token, or it could be left with no expiration at all
+            command.arg("EX").arg(expire);
+        }
+
+        let inserted: Option<String> = command.query_async(&mut conn).await.map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "performing SET NX command on redis",
+            );
+            Error::Unknown
+        })?;
+
+        Ok(inserted.is_some())
+    }
+
     #[instrument(skip(self))]
     async fn delete(&self, key: &str) -> Result<()> {
         let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
@@ -77,4 +135,202 @@ impl<'a> TokenRepository for RedisTokenRepository<'a> {
 
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    async fn increment(&self, key: &str, expire: u64) -> Result<u64> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let count: u64 = conn.incr(key, 1).await.map_err(|err| {
+            error!(error = err.to_string(), "performing INCR command on redis",);
+            Error::Unknown
+        })?;
+
+        let expire: usize = expire.try_into().map_err(|err: TryFromIntError| {
+            error!(error = err.to_string(), "parsing expiration time to usize",);
+            Error::Unknown
+        })?;
+
+        conn.expire(key, expire).await.map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "performing EXPIRE command on redis",
+            );
+            Error::Unknown
+        })?;
+
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    async fn touch(&self, key: &str, expire: u64) -> Result<()> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let expire = expire.try_into().map_err(|err: TryFromIntError| {
+            error!(error = err.to_string(), "parsing expiration time to usize",);
+            Error::Unknown
+        })?;
+
+        let updated: bool = conn.expire(key, expire).await.map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "performing EXPIRE command on redis",
+            );
+            Error::Unknown
+        })?;
+
+        if !updated {
+            return Err(Error::NotFound);
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn consume(&self, key: &str) -> Result<String> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        // GET and DEL within a single transaction, since GETDEL is not available before Redis 6.2
+        let (token, _): (Option<Vec<u8>>, u64) = reool::redis::pipe()
+            .atomic()
+            .get(key)
+            .del(key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing GET and DEL transaction on redis",
+                );
+                Error::Unknown
+            })?;
+
+        let token = token.ok_or(Error::NotFound)?;
+        let token: String = String::from_utf8(token).map_err(|err| {
+            error!(error = err.to_string(), "parsing token to string",);
+            Error::Unknown
+        })?;
+
+        Ok(token)
+    }
+
+    #[instrument(skip(self))]
+    async fn index(&self, sub: &str, key: &str, expire: Option<u64>) -> Result<()> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let index = Self::subject_key(sub);
+        conn.sadd(&index, key).await.map_err(|err| {
+            error!(error = err.to_string(), "performing SADD command on redis",);
+            Error::Unknown
+        })?;
+
+        if let Some(expire) = expire {
+            let expire: usize = expire.try_into().map_err(|err: TryFromIntError| {
+                error!(error = err.to_string(), "parsing expiration time to usize",);
+                Error::Unknown
+            })?;
+
+            // the index must live as long as the longest-lived token it holds
+            let ttl: isize = conn.ttl(&index).await.map_err(|err| {
+                error!(error = err.to_string(), "performing TTL command on redis",);
+                Error::Unknown
+            })?;
+
+            if ttl < expire as isize {
+                conn.expire(&index, expire).await.map_err(|err| {
+                    error!(
+                        error = err.to_string(),
+                        "performing EXPIRE command on redis",
+                    );
+                    Error::Unknown
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_subject(&self, sub: &str) -> Result<Vec<String>> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let index = Self::subject_key(sub);
+        let keys: Vec<String> = conn.smembers(&index).await.map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "performing SMEMBERS command on redis",
+            );
+            Error::Unknown
+        })?;
+
+        let mut tokens = Vec::with_capacity(keys.len());
+        for key in keys {
+            let token: Option<Vec<u8>> = conn.get(&key).await.map_err(|err| {
+                error!(error = err.to_string(), "performing GET command on redis",);
+                Error::Unknown
+            })?;
+
+            let Some(token) = token else {
+                // the token has expired or has been revoked, so it no longer belongs to the index
+                conn.srem(&index, &key).await.map_err(|err| {
+                    error!(error = err.to_string(), "performing SREM command on redis",);
+                    Error::Unknown
+                })?;
+
+                continue;
+            };
+
+            let token = String::from_utf8(token).map_err(|err| {
+                error!(error = err.to_string(), "parsing token to string",);
+                Error::Unknown
+            })?;
+
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_by_subject(&self, sub: &str) -> Result<()> {
+        let mut conn = self.pool.check_out(PoolDefault).await.map_err(|err| {
+            error!(error = err.to_string(), "pulling connection for redis",);
+            Error::Unknown
+        })?;
+
+        let index = Self::subject_key(sub);
+        let keys: Vec<String> = conn.smembers(&index).await.map_err(|err| {
+            error!(
+                error = err.to_string(),
+                "performing SMEMBERS command on redis",
+            );
+            Error::Unknown
+        })?;
+
+        for key in keys.iter().chain([&index]) {
+            conn.del(key).await.map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "performing DELETE command on redis",
+                );
+                Error::Unknown
+            })?;
+        }
+
+        Ok(())
+    }
 }
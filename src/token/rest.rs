@@ -0,0 +1,36 @@
+use super::keyring::KeyRing;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse, Responder};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct TokenRestService {
+    pub keyring: &'static KeyRing,
+    pub jwks_max_age: Duration,
+}
+
+impl TokenRestService {
+    pub fn router(&self) -> impl Fn(&mut web::ServiceConfig) {
+        |cfg: &mut web::ServiceConfig| {
+            cfg.service(
+                web::resource("/.well-known/jwks.json").route(web::get().to(Self::get_jwks)),
+            );
+        }
+    }
+
+    #[instrument(skip(app_data))]
+    async fn get_jwks(app_data: web::Data<Arc<TokenRestService>>) -> impl Responder {
+        match app_data.keyring.jwks() {
+            Ok(jwks) => {
+                let max_age = app_data.keyring.max_age(app_data.jwks_max_age);
+                HttpResponse::Ok()
+                    .insert_header(CacheControl(vec![
+                        CacheDirective::Public,
+                        CacheDirective::MaxAge(max_age.as_secs().try_into().unwrap_or(u32::MAX)),
+                    ]))
+                    .json(jwks)
+            }
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+}
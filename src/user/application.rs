@@ -1,13 +1,14 @@
-use super::domain::User;
-use crate::crypto;
+use super::domain::{Authenticator, TotpEnabling, TotpPolicy, TotpProvisioning, User};
 use crate::result::{Error, Result};
 use crate::secret::{application::SecretRepository, domain::Secret};
 use crate::token::application::{GenerateOptions, VerifyOptions};
 use crate::token::domain::TokenDefinition;
 use crate::token::{
-    application::{TokenApplication, TokenRepository},
+    application::{TokenApplication, TokenEventBus, TokenRepository},
     domain::{Token, TokenKind},
+    dpop::DpopRequest,
 };
+use crate::{crypto, regex};
 use async_trait::async_trait;
 use chrono::Utc;
 use std::num::ParseIntError;
@@ -40,19 +41,170 @@ pub struct UserApplication<
     T: TokenRepository,
     B: EventBus,
     M: Mailer,
+    N: TokenEventBus,
 > {
     pub user_repo: Arc<U>,
     pub secret_repo: Arc<E>,
-    pub token_app: Arc<TokenApplication<'a, T>>,
+    pub token_app: Arc<TokenApplication<'a, T, N>>,
     pub mailer: Arc<M>,
     pub event_bus: Arc<B>,
     pub totp_secret_len: usize,
     pub totp_secret_name: &'a str,
+    pub totp_issuer: &'a str,
+    pub totp_policy: TotpPolicy,
+    pub recovery_code_count: usize,
+    pub recovery_code_len: usize,
+    pub recovery_code_name: &'a str,
     pub pwd_sufix: &'a str,
 }
 
-impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus, M: Mailer>
-    UserApplication<'a, U, E, T, B, M>
+const TOTP_COUNTER_PREFIX: &str = "Totp";
+const DEFAULT_AUTHENTICATOR: &str = "default";
+
+/// Checks the second factor of the users on behalf of any application requiring it.
+pub(crate) struct TotpVerifier<'b, E: SecretRepository, T: TokenRepository> {
+    pub secret_repo: &'b E,
+    pub token_repo: &'b T,
+    pub totp_secret_name: &'b str,
+    pub totp_policy: &'b TotpPolicy,
+    pub recovery_code_name: &'b str,
+    pub pwd_sufix: &'b str,
+}
+
+impl<'b, E: SecretRepository, T: TokenRepository> TotpVerifier<'b, E, T> {
+    /// Returns all the authenticators of the user, either active or waiting to be confirmed. The default one
+    /// keeps the bare totp secret name, so the secrets enrolled before authenticators got named remain valid,
+    /// while any other is stored by the totp secret name followed by its own.
+    pub async fn find_authenticators(&self, user_id: i32) -> Result<Vec<Secret>> {
+        let mut authenticators = match self
+            .secret_repo
+            .find_by_user_and_name(user_id, self.totp_secret_name)
+            .await
+        {
+            Ok(secret) => vec![secret],
+            Err(_) => Vec::new(),
+        };
+
+        authenticators.extend(
+            self.secret_repo
+                .find_by_user(user_id)
+                .await?
+                .into_iter()
+                .filter(|secret| is_named_authenticator(secret, self.totp_secret_name)),
+        );
+
+        Ok(authenticators)
+    }
+
+    /// Returns true if, and only if, the totp is valid for any of the active authenticators or, otherwise, it
+    /// is any of the unused recovery codes of the user, which gets consumed so it cannot be used again.
+    pub async fn verify(
+        &self,
+        user_id: i32,
+        authenticators: &[Secret],
+        totp: &str,
+    ) -> Result<bool> {
+        for secret in authenticators.iter().filter(|secret| !secret.is_deleted()) {
+            if self.verify_totp(secret, totp).await? {
+                return Ok(true);
+            }
+        }
+
+        if totp.is_empty() {
+            return Ok(false);
+        }
+
+        let digest = crypto::obfuscate(totp, self.pwd_sufix);
+        let Some(recovery_code) = self
+            .secret_repo
+            .find_by_user(user_id)
+            .await?
+            .into_iter()
+            .filter(|secret| is_recovery_code(secret, self.recovery_code_name))
+            .find(|secret| crypto::secure_eq(secret.get_data(), digest.as_bytes()))
+        else {
+            return Ok(false);
+        };
+
+        // a concurrent request may have consumed the very same code since it was found
+        self.secret_repo.delete(&recovery_code).await
+    }
+
+    /// Returns true if, and only if, the totp is valid for the given secret and no other totp has been
+    /// accepted for the same period, nor any later one, so a captured totp cannot be replayed.
+    pub async fn verify_totp(&self, secret: &Secret, totp: &str) -> Result<bool> {
+        let Some(counter) = crypto::verify_totp(secret.get_data(), self.totp_policy, totp)? else {
+            return Ok(false);
+        };
+
+        let key = format!("{}::{}", TOTP_COUNTER_PREFIX, secret.get_id());
+        let last_counter = match self
+            .token_repo
+            .find_many(std::slice::from_ref(&key))
+            .await?
+            .pop()
+        {
+            Some(Some(last_counter)) => Some(last_counter.parse::<u64>().map_err(|err| {
+                error!(
+                    error = err.to_string(),
+                    "parsing last accepted totp counter"
+                );
+                Error::Unknown
+            })?),
+            _ => None,
+        };
+
+        // the insertion settles any race between requests presenting the very same totp at once
+        let expire = self.totp_policy.replay_window().as_secs();
+        if last_counter.is_some_and(|last_counter| counter <= last_counter)
+            || !self
+                .token_repo
+                .insert(&format!("{key}::{counter}"), "", Some(expire))
+                .await?
+        {
+            warn!(
+                secret = secret.get_id(),
+                counter, "replaying an already accepted totp"
+            );
+            return Ok(false);
+        }
+
+        self.token_repo
+            .save(&key, &counter.to_string(), Some(expire))
+            .await?;
+
+        Ok(true)
+    }
+}
+
+/// Returns true if, and only if, any of the given authenticators is active.
+pub(crate) fn is_totp_enabled(authenticators: &[Secret]) -> bool {
+    authenticators.iter().any(|secret| !secret.is_deleted())
+}
+
+fn is_named_authenticator(secret: &Secret, totp_secret_name: &str) -> bool {
+    secret
+        .get_name()
+        .strip_prefix(totp_secret_name)
+        .is_some_and(|name| name.starts_with("::"))
+}
+
+fn is_recovery_code(secret: &Secret, recovery_code_name: &str) -> bool {
+    secret
+        .get_name()
+        .strip_prefix(recovery_code_name)
+        .is_some_and(|index| index.starts_with("::"))
+}
+
+impl<
+        'a,
+        U: UserRepository,
+        E: SecretRepository,
+        T: TokenRepository,
+        B: EventBus,
+        M: Mailer,
+        N: TokenEventBus,
+    > UserApplication<'a, U, E, T, B, M, N>
 {
     #[instrument(skip(self))]
     pub async fn verify_signup_email(&self, email: &str, pwd: &str) -> Result<()> {
@@ -79,7 +231,10 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
                 TokenKind::Verification,
                 token_to_keep.id(),
                 None,
-                GenerateOptions { store: false },
+                GenerateOptions {
+                    store: false,
+                    ..Default::default()
+                },
             )
             .await?;
 
@@ -97,21 +252,28 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
                 &claims,
                 VerifyOptions {
                     must_exists: false,
-                    kind: Some(TokenKind::Verification),
+                    ..VerifyOptions::new(TokenKind::Verification)
                 },
             )
             .await?;
 
         let claims: Token = self.token_app.retrieve(&claims.sub).await?;
-        self.token_app
-            .verify(&claims, VerifyOptions::new(TokenKind::Verification))
+        let signed = self
+            .token_app
+            .consume(&claims, VerifyOptions::new(TokenKind::Verification))
             .await?;
 
         let password = &claims.get_secret().ok_or(Error::InvalidToken)?;
-        let token = self.signup(&claims.sub, password).await?;
-        self.token_app.revoke(&claims).await?;
-
-        Ok(token)
+        match self.signup(&claims.sub, password).await {
+            Ok(token) => {
+                self.announce_consumed(&claims).await;
+                Ok(token)
+            }
+            Err(err) => {
+                self.token_app.restore(&claims, &signed).await?;
+                Err(err)
+            }
+        }
     }
 
     #[instrument(skip(self))]
@@ -119,23 +281,29 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
         let mut user = User::new(email, pwd)?;
         self.user_repo.create(&mut user).await?;
         self.event_bus.emit_user_created(&user).await?;
+
+        // the session is issued the same way a login does, so it is listed and revoked along with any other
         self.token_app
-            .generate(
-                TokenKind::Session,
-                &user.get_id().to_string(),
-                None,
-                GenerateOptions::default(),
-            )
+            .generate_pair(&user.get_id().to_string(), GenerateOptions::default())
             .await
-            .map(|token| token.signature().to_string())
+            .map(|pair| pair.session().signature().to_string())
     }
 
     #[instrument(skip(self))]
-    pub async fn delete_with_token(&self, token: &str, pwd: &str, totp: &str) -> Result<()> {
+    pub async fn delete_with_token(
+        &self,
+        token: &str,
+        pwd: &str,
+        totp: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<()> {
         let claims: Token = self.token_app.decode(token).await?;
-        self.token_app
-            .verify(&claims, VerifyOptions::new(TokenKind::Session))
-            .await?;
+        let options = VerifyOptions {
+            dpop: dpop.cloned(),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        self.token_app.verify(&claims, options).await?;
 
         let user_id = claims.sub.parse().map_err(|err: ParseIntError| {
             warn!(error = err.to_string(), "parsing str to i32");
@@ -159,22 +327,18 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
         }
 
         // if, and only if, the user has activated the totp
-        let secret_lookup = self
-            .secret_repo
-            .find_by_user_and_name(user.id, self.totp_secret_name)
-            .await
-            .ok();
+        let authenticators = self.totp_verifier().find_authenticators(user.id).await?;
+        if is_totp_enabled(&authenticators)
+            && !self.verify_totp(&user, &authenticators, totp).await?
+        {
+            return Err(Error::Unauthorized);
+        }
 
-        if let Some(secret) = secret_lookup {
-            if !secret.is_deleted() {
-                let data = secret.get_data();
-                if !crypto::verify_totp(data, totp)? {
-                    return Err(Error::Unauthorized);
-                }
-                self.secret_repo.delete(&secret).await?;
-            }
+        for secret in &authenticators {
+            self.secret_repo.delete(secret).await?;
         }
 
+        self.delete_recovery_codes(&user).await?;
         self.user_repo.delete(&user).await?;
         Ok(())
     }
@@ -185,22 +349,37 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
         token: &str,
         pwd: &str,
         totp: &str,
-    ) -> Result<Option<String>> {
+        name: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<TotpEnabling> {
         let claims: Token = self.token_app.decode(token).await?;
-        self.token_app
-            .verify(&claims, VerifyOptions::new(TokenKind::Session))
-            .await?;
+        let options = VerifyOptions {
+            dpop: dpop.cloned(),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        self.token_app.verify(&claims, options).await?;
 
         let user_id = claims.sub.parse().map_err(|err: ParseIntError| {
             warn!(error = err.to_string(), "parsing str to i32");
             Error::InvalidToken
         })?;
 
-        self.enable_totp(user_id, pwd, totp).await
+        self.enable_totp(user_id, pwd, totp, name).await
     }
 
+    /// Generates the TOTP secret of the given authenticator, returning everything an authenticator app requires
+    /// to get provisioned with it, if the user has none by that name yet. Otherwise, enables it as long as the
+    /// given totp is valid for it, returning the recovery codes issued for the user if it is its first
+    /// authenticator, since these codes are shared by all of them.
     #[instrument(skip(self))]
-    pub async fn enable_totp(&self, user_id: i32, pwd: &str, totp: &str) -> Result<Option<String>> {
+    pub async fn enable_totp(
+        &self,
+        user_id: i32,
+        pwd: &str,
+        totp: &str,
+        name: &str,
+    ) -> Result<TotpEnabling> {
         let user = self
             .user_repo
             .find(user_id)
@@ -212,53 +391,264 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
             return Err(Error::WrongCredentials);
         }
 
-        // if, and only if, the user has activated the totp
+        // if, and only if, the user has enrolled the given authenticator
+        let secret_name = self.authenticator_secret_name(name)?;
         let mut secret_lookup = self
             .secret_repo
-            .find_by_user_and_name(user.id, self.totp_secret_name)
+            .find_by_user_and_name(user.id, &secret_name)
             .await
             .ok();
 
+        let verifier = self.totp_verifier();
         if let Some(secret) = &mut secret_lookup {
             if !secret.is_deleted() {
-                // the totp is already enabled
+                // the authenticator is already enabled
                 return Err(Error::NotAvailable);
             }
 
-            let data = secret.get_data();
-            if !crypto::verify_totp(data, totp)? {
+            // recovery codes may be issued right after, so only a totp is accepted here
+            if !verifier.verify_totp(secret, totp).await? {
                 return Err(Error::Unauthorized);
             }
 
+            let authenticators = verifier.find_authenticators(user.id).await?;
             secret.set_deleted_at(None);
             self.secret_repo.save(secret).await?;
-            return Ok(None);
+
+            if is_totp_enabled(&authenticators) {
+                return Ok(TotpEnabling::Enabled(Vec::new()));
+            }
+
+            let recovery_codes = self.issue_recovery_codes(&user).await?;
+            return Ok(TotpEnabling::Enabled(recovery_codes));
+        }
+
+        // enrolling any further authenticator requires a totp from any of the active ones
+        let authenticators = verifier.find_authenticators(user.id).await?;
+        if is_totp_enabled(&authenticators)
+            && !self.verify_totp(&user, &authenticators, totp).await?
+        {
+            return Err(Error::Unauthorized);
         }
 
         let token = crypto::get_random_string(self.totp_secret_len);
-        let mut secret = Secret::new(&user, self.totp_secret_name, token.as_bytes());
+        let mut secret = Secret::new(&user, &secret_name, token.as_bytes());
         secret.set_deleted_at(Some(Utc::now().naive_utc())); // unavailable till confirmed
         self.secret_repo.create(&mut secret).await?;
-        Ok(Some(token))
+
+        let provisioning = TotpProvisioning::new(
+            token.as_bytes(),
+            &self.totp_policy,
+            self.totp_issuer,
+            user.get_email(),
+        )?;
+        Ok(TotpEnabling::Provisioned(provisioning))
     }
 
     #[instrument(skip(self))]
-    pub async fn disable_totp_with_token(&self, token: &str, pwd: &str, totp: &str) -> Result<()> {
+    pub async fn regenerate_recovery_codes_with_token(
+        &self,
+        token: &str,
+        pwd: &str,
+        totp: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<Vec<String>> {
         let claims: Token = self.token_app.decode(token).await?;
-        self.token_app
-            .verify(&claims, VerifyOptions::new(TokenKind::Session))
-            .await?;
+        let options = VerifyOptions {
+            dpop: dpop.cloned(),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        self.token_app.verify(&claims, options).await?;
+
+        let user_id = claims.sub.parse().map_err(|err: ParseIntError| {
+            warn!(error = err.to_string(), "parsing str to i32");
+            Error::InvalidToken
+        })?;
+
+        self.regenerate_recovery_codes(user_id, pwd, totp).await
+    }
+
+    /// Replaces all the recovery codes of the user, used or not, with a brand new set of them.
+    #[instrument(skip(self))]
+    pub async fn regenerate_recovery_codes(
+        &self,
+        user_id: i32,
+        pwd: &str,
+        totp: &str,
+    ) -> Result<Vec<String>> {
+        let user = self
+            .user_repo
+            .find(user_id)
+            .await
+            .map_err(|_| Error::WrongCredentials)?;
+
+        let pwd = crypto::obfuscate(pwd, self.pwd_sufix);
+        if !user.match_password(&pwd) {
+            return Err(Error::WrongCredentials);
+        }
+
+        // recovery codes make no sense unless the user has activated the totp
+        let authenticators = self.totp_verifier().find_authenticators(user.id).await?;
+        if !is_totp_enabled(&authenticators) {
+            return Err(Error::NotAvailable);
+        }
+
+        if !self.verify_totp(&user, &authenticators, totp).await? {
+            return Err(Error::Unauthorized);
+        }
+
+        self.issue_recovery_codes(&user).await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn list_authenticators_with_token(
+        &self,
+        token: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<Vec<Authenticator>> {
+        let claims: Token = self.token_app.decode(token).await?;
+        let options = VerifyOptions {
+            dpop: dpop.cloned(),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        self.token_app.verify(&claims, options).await?;
+
+        let user_id = claims.sub.parse().map_err(|err: ParseIntError| {
+            warn!(error = err.to_string(), "parsing str to i32");
+            Error::InvalidToken
+        })?;
+
+        self.list_authenticators(user_id).await
+    }
+
+    /// Returns all the authenticators the user has enrolled, either active or waiting to be confirmed.
+    #[instrument(skip(self))]
+    pub async fn list_authenticators(&self, user_id: i32) -> Result<Vec<Authenticator>> {
+        let authenticators = self
+            .totp_verifier()
+            .find_authenticators(user_id)
+            .await?
+            .iter()
+            .map(|secret| Authenticator {
+                name: self.authenticator_name(secret).to_string(),
+                active: !secret.is_deleted(),
+            })
+            .collect();
+
+        Ok(authenticators)
+    }
+
+    fn totp_verifier(&self) -> TotpVerifier<'_, E, T> {
+        TotpVerifier {
+            secret_repo: &*self.secret_repo,
+            token_repo: &*self.token_app.token_repo,
+            totp_secret_name: self.totp_secret_name,
+            totp_policy: &self.totp_policy,
+            recovery_code_name: self.recovery_code_name,
+            pwd_sufix: self.pwd_sufix,
+        }
+    }
+
+    async fn verify_totp(
+        &self,
+        user: &User,
+        authenticators: &[Secret],
+        totp: &str,
+    ) -> Result<bool> {
+        self.totp_verifier()
+            .verify(user.get_id(), authenticators, totp)
+            .await
+    }
+
+    /// Returns the name by which the secret of the given authenticator is stored, being the default one if
+    /// no name is given.
+    fn authenticator_secret_name(&self, name: &str) -> Result<String> {
+        if name.is_empty() || name == DEFAULT_AUTHENTICATOR {
+            return Ok(self.totp_secret_name.to_string());
+        }
+
+        regex::match_regex(regex::AUTHENTICATOR_NAME, name).map_err(|err| {
+            warn!(error = err.to_string(), "validating authenticator's name");
+            Error::InvalidFormat
+        })?;
+
+        Ok(format!("{}::{}", self.totp_secret_name, name))
+    }
+
+    fn authenticator_name<'s>(&self, secret: &'s Secret) -> &'s str {
+        secret
+            .get_name()
+            .strip_prefix(self.totp_secret_name)
+            .and_then(|name| name.strip_prefix("::"))
+            .unwrap_or(DEFAULT_AUTHENTICATOR)
+    }
+
+    /// Stores the digest of a brand new set of recovery codes for the user, replacing any previous one, and
+    /// returns them in plain text since there is no way to recover them afterwards.
+    async fn issue_recovery_codes(&self, user: &User) -> Result<Vec<String>> {
+        self.delete_recovery_codes(user).await?;
+
+        let mut recovery_codes = Vec::with_capacity(self.recovery_code_count);
+        for index in 0..self.recovery_code_count {
+            let recovery_code = crypto::get_random_string(self.recovery_code_len);
+            let digest = crypto::obfuscate(&recovery_code, self.pwd_sufix);
+            let name = format!("{}::{}", self.recovery_code_name, index);
+            let mut secret = Secret::new(user, &name, digest.as_bytes());
+            self.secret_repo.create(&mut secret).await?;
+            recovery_codes.push(recovery_code);
+        }
+
+        Ok(recovery_codes)
+    }
+
+    async fn delete_recovery_codes(&self, user: &User) -> Result<()> {
+        for secret in self.secret_repo.find_by_user(user.get_id()).await? {
+            if is_recovery_code(&secret, self.recovery_code_name) {
+                self.secret_repo.delete(&secret).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn disable_totp_with_token(
+        &self,
+        token: &str,
+        pwd: &str,
+        totp: &str,
+        name: &str,
+        dpop: Option<&DpopRequest>,
+    ) -> Result<()> {
+        let claims: Token = self.token_app.decode(token).await?;
+        let options = VerifyOptions {
+            dpop: dpop.cloned(),
+            ..VerifyOptions::new(TokenKind::Session)
+        };
+
+        self.token_app.verify(&claims, options).await?;
 
         let user_id = claims.sub.parse().map_err(|err: ParseIntError| {
             warn!(error = err.to_string(), "parsing str to i32",);
             Error::InvalidToken
         })?;
 
-        self.disable_totp(user_id, pwd, totp).await
+        self.disable_totp(user_id, pwd, totp, name).await
     }
 
+    /// Removes the given authenticator of the user, as long as the totp is valid for any of its active
+    /// authenticators, so a lost device can be removed by using another one. The recovery codes are removed
+    /// as well once no active authenticator is left.
     #[instrument(skip(self))]
-    pub async fn disable_totp(&self, user_id: i32, pwd: &str, totp: &str) -> Result<()> {
+    pub async fn disable_totp(
+        &self,
+        user_id: i32,
+        pwd: &str,
+        totp: &str,
+        name: &str,
+    ) -> Result<()> {
         let user = self
             .user_repo
             .find(user_id)
@@ -270,29 +660,34 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
             return Err(Error::WrongCredentials);
         }
 
-        // if, and only if, the user has activated the totp
-        let mut secret_lookup = self
+        // if, and only if, the user has activated the given authenticator
+        let secret_name = self.authenticator_secret_name(name)?;
+        let secret = match self
             .secret_repo
-            .find_by_user_and_name(user.id, self.totp_secret_name)
+            .find_by_user_and_name(user.id, &secret_name)
             .await
-            .ok();
+        {
+            Ok(secret) if !secret.is_deleted() => secret,
+            _ => return Err(Error::NotAvailable),
+        };
 
-        if let Some(secret) = &mut secret_lookup {
-            if secret.is_deleted() {
-                // the totp is not enabled yet
-                return Err(Error::NotAvailable);
-            }
+        let authenticators = self.totp_verifier().find_authenticators(user.id).await?;
+        if !self.verify_totp(&user, &authenticators, totp).await? {
+            return Err(Error::Unauthorized);
+        }
 
-            let data = secret.get_data();
-            if !crypto::verify_totp(data, totp)? {
-                return Err(Error::Unauthorized);
-            }
+        self.secret_repo.delete(&secret).await?;
 
-            self.secret_repo.delete(secret).await?;
-            return Ok(());
+        let remaining: Vec<Secret> = authenticators
+            .into_iter()
+            .filter(|authenticator| authenticator.get_id() != secret.get_id())
+            .collect();
+
+        if !is_totp_enabled(&remaining) {
+            self.delete_recovery_codes(&user).await?;
         }
 
-        Err(Error::NotAvailable)
+        Ok(())
     }
 
     #[instrument(skip(self))]
@@ -320,20 +715,38 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
     #[instrument(skip(self))]
     pub async fn reset_with_token(&self, token: &str, new_pwd: &str, totp: &str) -> Result<()> {
         let claims: Token = self.token_app.decode(token).await?;
-        self.token_app
-            .verify(&claims, VerifyOptions::new(TokenKind::Reset))
-            .await?;
-
         let user_id = claims.sub.parse().map_err(|err: ParseIntError| {
             warn!(error = err.to_string(), "parsing str to i32",);
             Error::InvalidToken
         })?;
 
-        self.reset(user_id, new_pwd, totp).await?;
-        self.token_app.revoke(&claims).await?;
+        let signed = self
+            .token_app
+            .consume(&claims, VerifyOptions::new(TokenKind::Reset))
+            .await?;
+
+        if let Err(err) = self.reset(user_id, new_pwd, totp).await {
+            // the token is given back so, for instance, the totp can be provided in a later attempt
+            self.token_app.restore(&claims, &signed).await?;
+            return Err(err);
+        }
+
+        self.announce_consumed(&claims).await;
         Ok(())
     }
 
+    /// Announces the revocation of a token consumed by an action that has succeeded, which cannot be undone
+    /// anyway, so failing to announce it must not fail the action as well.
+    async fn announce_consumed(&self, token: &Token) {
+        if let Err(err) = self.token_app.announce_revocation(token).await {
+            error!(
+                error = err.to_string(),
+                token_id = token.get_id(),
+                "announcing consumed token revocation",
+            );
+        }
+    }
+
     #[instrument(skip(self))]
     pub async fn reset(&self, user_id: i32, new_pwd: &str, totp: &str) -> Result<()> {
         let mut user = self
@@ -348,17 +761,15 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
         }
 
         // if, and only if, the user has activated the totp
-        if let Ok(secret) = self
-            .secret_repo
-            .find_by_user_and_name(user.get_id(), self.totp_secret_name)
-            .await
+        let authenticators = self
+            .totp_verifier()
+            .find_authenticators(user.get_id())
+            .await?;
+
+        if is_totp_enabled(&authenticators)
+            && !self.verify_totp(&user, &authenticators, totp).await?
         {
-            if !secret.is_deleted() {
-                let data = secret.get_data();
-                if !crypto::verify_totp(data, totp)? {
-                    return Err(Error::Unauthorized);
-                }
-            }
+            return Err(Error::Unauthorized);
         }
 
         user.set_password(&new_pwd)?;
@@ -369,7 +780,10 @@ impl<'a, U: UserRepository, E: SecretRepository, T: TokenRepository, B: EventBus
 #[cfg(test)]
 pub mod tests {
     use super::super::domain::tests::{TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD};
-    use super::super::domain::{tests::new_user_custom, User};
+    use super::super::domain::{
+        tests::{new_user, new_user_custom},
+        TotpEnabling, TotpPolicy, User,
+    };
     use super::{EventBus, UserApplication, UserRepository};
     use crate::secret::{
         application::tests::SecretRepositoryMock,
@@ -379,9 +793,11 @@ pub mod tests {
         },
     };
     use crate::smtp::tests::MailerMock;
-    use crate::token::application::tests::{new_token_application, PRIVATE_KEY, PUBLIC_KEY};
+    use crate::token::application::tests::{
+        new_token_application, TokenEventBusMock, KEYRING, SECRET_KEY,
+    };
     use crate::token::{
-        application::tests::TokenRepositoryMock,
+        application::{tests::TokenRepositoryMock, VerifyOptions},
         domain::{Token, TokenDefinition, TokenKind},
     };
     use crate::user::domain::tests::TEST_DEFAULT_PWD_SUFIX;
@@ -391,12 +807,29 @@ pub mod tests {
     };
     use async_trait::async_trait;
     use chrono::Utc;
-    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
     pub const TEST_CREATE_ID: i32 = 999;
     pub const TEST_FIND_BY_EMAIL_ID: i32 = 888;
     pub const TEST_FIND_BY_NAME_ID: i32 = 777;
+    pub const TEST_RECOVERY_CODE: &str = "dummyrecoverycode";
+    pub const TEST_AUTHENTICATOR_NAME: &str = "tablet";
+    pub const TEST_AUTHENTICATOR_DATA: &str = "dummybackupsecret";
+
+    /// Returns the stored recovery code matching [`TEST_RECOVERY_CODE`].
+    pub fn new_recovery_code() -> Secret {
+        let digest = crypto::obfuscate(TEST_RECOVERY_CODE, TEST_DEFAULT_PWD_SUFIX);
+        Secret::new(&new_user(), ".dummy_recovery_code::0", digest.as_bytes())
+    }
+
+    /// Returns the stored secret of the [`TEST_AUTHENTICATOR_NAME`] authenticator, whose TOTPs are generated out
+    /// of [`TEST_AUTHENTICATOR_DATA`].
+    pub fn new_authenticator() -> Secret {
+        let name = format!(".dummy_totp_secret::{}", TEST_AUTHENTICATOR_NAME);
+        Secret::new(&new_user(), &name, TEST_AUTHENTICATOR_DATA.as_bytes())
+    }
 
     type MockFnFind = Option<fn(this: &UserRepositoryMock, id: i32) -> Result<User>>;
     type MockFnFindByEmail = Option<fn(this: &UserRepositoryMock, email: &str) -> Result<User>>;
@@ -494,6 +927,7 @@ pub mod tests {
         TokenRepositoryMock,
         EventBusMock,
         MailerMock,
+        TokenEventBusMock,
     > {
         let user_repo = UserRepositoryMock::default();
         let secret_repo = SecretRepositoryMock::default();
@@ -509,6 +943,11 @@ pub mod tests {
             event_bus: Arc::new(event_bus),
             totp_secret_len: 32_usize,
             totp_secret_name: ".dummy_totp_secret",
+            totp_issuer: "rauth",
+            totp_policy: TotpPolicy::default(),
+            recovery_code_count: 3,
+            recovery_code_len: 10,
+            recovery_code_name: ".dummy_recovery_code",
             pwd_sufix: TEST_DEFAULT_PWD_SUFIX,
         }
     }
@@ -565,13 +1004,14 @@ pub mod tests {
             ..Default::default()
         };
 
-        let token_to_keep = Token::new(
+        let mut token_to_keep = Token::new(
             "test",
             TEST_DEFAULT_USER_EMAIL,
             Duration::from_secs(60),
             TokenKind::Verification,
             Some(TEST_DEFAULT_USER_PASSWORD),
         );
+        token_to_keep.seal_secret(SECRET_KEY).unwrap();
 
         let token_to_send = Token::new(
             "test",
@@ -581,13 +1021,13 @@ pub mod tests {
             None,
         );
 
-        let token_to_keep = crypto::sign_jwt(&PRIVATE_KEY, token_to_keep).unwrap();
-        let token_to_send = crypto::sign_jwt(&PRIVATE_KEY, token_to_send).unwrap();
+        let token_to_keep = KEYRING.sign(token_to_keep).unwrap();
+        let token_to_send = KEYRING.sign(token_to_send).unwrap();
 
         let token_repo = TokenRepositoryMock {
             token: token_to_keep.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, tid: &str| -> Result<String> {
-                let claims: Token = crypto::decode_jwt(&PUBLIC_KEY, &this.token)?;
+                let claims: Token = KEYRING.decode(&this.token)?;
                 assert_eq!(claims.get_id(), tid);
 
                 Ok(this.token.clone())
@@ -598,10 +1038,63 @@ pub mod tests {
         app.user_repo = Arc::new(user_repo);
 
         let token = app.signup_with_token(&token_to_send).await.unwrap();
-        let claims: Token = crypto::decode_jwt(&PUBLIC_KEY, &token).unwrap();
+        let claims: Token = KEYRING.decode(&token).unwrap();
         assert_eq!(claims.sub, TEST_CREATE_ID.to_string());
     }
 
+    #[tokio::test]
+    async fn user_secure_signup_should_announce_revocation() {
+        static ANNOUNCED: AtomicBool = AtomicBool::new(false);
+        let user_repo = UserRepositoryMock {
+            fn_find_by_email: Some(|_: &UserRepositoryMock, _: &str| -> Result<User> {
+                Err(Error::NotFound)
+            }),
+            ..Default::default()
+        };
+
+        let mut token_to_keep = Token::new(
+            "test",
+            TEST_DEFAULT_USER_EMAIL,
+            Duration::from_secs(60),
+            TokenKind::Verification,
+            Some(TEST_DEFAULT_USER_PASSWORD),
+        );
+        token_to_keep.seal_secret(SECRET_KEY).unwrap();
+
+        let token_to_send = Token::new(
+            "test",
+            &token_to_keep.get_id(),
+            Duration::from_secs(60),
+            TokenKind::Verification,
+            None,
+        );
+
+        let token_to_keep = KEYRING.sign(token_to_keep).unwrap();
+        let token_to_send = KEYRING.sign(token_to_send).unwrap();
+
+        let token_repo = TokenRepositoryMock {
+            token: token_to_keep.clone(),
+            ..Default::default()
+        };
+
+        let mut token_app = new_token_application(Some(token_repo));
+        token_app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, token: &Token| -> Result<()> {
+                assert_eq!(token.knd, TokenKind::Verification);
+                assert_eq!(token.sub, TEST_DEFAULT_USER_EMAIL);
+                ANNOUNCED.store(true, Ordering::Relaxed);
+                Ok(())
+            }),
+        });
+
+        let mut app = new_user_application(None);
+        app.user_repo = Arc::new(user_repo);
+        app.token_app = Arc::new(token_app);
+
+        app.signup_with_token(&token_to_send).await.unwrap();
+        assert!(ANNOUNCED.load(Ordering::Relaxed));
+    }
+
     #[tokio::test]
     async fn user_secure_signup_verification_token_kind_should_fail() {
         let user_repo = UserRepositoryMock {
@@ -619,7 +1112,7 @@ pub mod tests {
             None,
         );
 
-        let token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let token = KEYRING.sign(token).unwrap();
         let mut app = new_user_application(None);
         app.user_repo = Arc::new(user_repo);
 
@@ -646,7 +1139,7 @@ pub mod tests {
             None,
         );
 
-        let token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let token = KEYRING.sign(token).unwrap();
         let mut app = new_user_application(None);
         app.user_repo = Arc::new(user_repo);
 
@@ -656,24 +1149,110 @@ pub mod tests {
             .unwrap_err();
     }
 
-    #[tokio::test]
-    async fn user_signup_should_not_fail() {
-        let user_repo = UserRepositoryMock {
+    #[tokio::test]
+    async fn user_signup_should_not_fail() {
+        let user_repo = UserRepositoryMock {
+            fn_find_by_email: Some(|_: &UserRepositoryMock, _: &str| -> Result<User> {
+                Err(Error::Unknown)
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.user_repo = Arc::new(user_repo);
+
+        let token = app
+            .signup(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD)
+            .await
+            .unwrap();
+        let claims: Token = KEYRING.decode(&token).unwrap();
+        assert_eq!(claims.sub, TEST_CREATE_ID.to_string());
+    }
+
+    #[tokio::test]
+    async fn user_signup_session_should_be_listed_and_revoked() {
+        static STORED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+        static INDEXED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+        let token_repo = TokenRepositoryMock {
+            fn_find: Some(|_: &TokenRepositoryMock, key: &str| -> Result<String> {
+                STORED
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(stored, _)| stored == key)
+                    .map(|(_, value)| value.clone())
+                    .ok_or(Error::NotFound)
+            }),
+            fn_save: Some(
+                |_: &TokenRepositoryMock, key: &str, value: &str, _: Option<u64>| -> Result<()> {
+                    let mut stored = STORED.lock().unwrap();
+                    stored.retain(|(stored, _)| stored != key);
+                    stored.push((key.to_string(), value.to_string()));
+                    Ok(())
+                },
+            ),
+            fn_delete: Some(|_: &TokenRepositoryMock, key: &str| -> Result<()> {
+                STORED.lock().unwrap().retain(|(stored, _)| stored != key);
+                Ok(())
+            }),
+            fn_index: Some(
+                |_: &TokenRepositoryMock, sub: &str, key: &str, _: Option<u64>| -> Result<()> {
+                    INDEXED
+                        .lock()
+                        .unwrap()
+                        .push((sub.to_string(), key.to_string()));
+                    Ok(())
+                },
+            ),
+            fn_find_by_subject: Some(
+                |_: &TokenRepositoryMock, sub: &str| -> Result<Vec<String>> {
+                    let stored = STORED.lock().unwrap();
+                    Ok(INDEXED
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|(indexed, _)| indexed == sub)
+                        .filter_map(|(_, key)| stored.iter().find(|(stored, _)| stored == key))
+                        .map(|(_, value)| value.clone())
+                        .collect())
+                },
+            ),
+            fn_delete_by_subject: Some(|_: &TokenRepositoryMock, sub: &str| -> Result<()> {
+                INDEXED
+                    .lock()
+                    .unwrap()
+                    .retain(|(indexed, _)| indexed != sub);
+                Ok(())
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(Some(&token_repo));
+        app.user_repo = Arc::new(UserRepositoryMock {
             fn_find_by_email: Some(|_: &UserRepositoryMock, _: &str| -> Result<User> {
                 Err(Error::Unknown)
             }),
             ..Default::default()
-        };
-
-        let mut app = new_user_application(None);
-        app.user_repo = Arc::new(user_repo);
+        });
 
         let token = app
             .signup(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD)
             .await
             .unwrap();
-        let claims: Token = crypto::decode_jwt(&PUBLIC_KEY, &token).unwrap();
-        assert_eq!(claims.sub, TEST_CREATE_ID.to_string());
+        let claims: Token = KEYRING.decode(&token).unwrap();
+        assert!(claims.get_family().is_some());
+
+        let sub = TEST_CREATE_ID.to_string();
+        let sessions = app.token_app.find_by_subject(&sub).await.unwrap();
+        assert!(sessions.iter().any(|session| session == &claims));
+
+        app.token_app.revoke_subject(&sub).await.unwrap();
+        app.token_app
+            .verify(&claims, VerifyOptions::new(TokenKind::Session))
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
     }
 
     #[tokio::test]
@@ -721,7 +1300,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -733,7 +1312,7 @@ pub mod tests {
         let mut app = new_user_application(Some(&token_repo));
         app.secret_repo = Arc::new(secret_repo);
 
-        app.delete_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "")
+        app.delete_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "", None)
             .await
             .unwrap();
     }
@@ -757,7 +1336,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -769,7 +1348,7 @@ pub mod tests {
         let mut app = new_user_application(Some(&token_repo));
         app.secret_repo = Arc::new(secret_repo);
 
-        app.delete_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "")
+        app.delete_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "", None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -788,7 +1367,7 @@ pub mod tests {
 
         let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -800,7 +1379,7 @@ pub mod tests {
         let mut app = new_user_application(Some(&token_repo));
         app.secret_repo = Arc::new(secret_repo);
 
-        app.delete_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "")
+        app.delete_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "", None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -826,9 +1405,10 @@ pub mod tests {
     #[tokio::test]
     async fn user_delete_totp_should_not_fail() {
         let app = new_user_application(None);
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
         app.delete(0, TEST_DEFAULT_USER_PASSWORD, &code)
             .await
             .unwrap();
@@ -910,7 +1490,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -923,11 +1503,24 @@ pub mod tests {
         app.secret_repo = Arc::new(secret_repo);
 
         let totp = app
-            .enable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "")
+            .enable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "", "", None)
             .await
             .unwrap();
-        assert!(totp.is_some());
-        assert_eq!(totp.unwrap().len(), app.totp_secret_len);
+        let TotpEnabling::Provisioned(provisioning) = totp else {
+            panic!("totp should have been provisioned");
+        };
+        let secret = base32::decode(
+            base32::Alphabet::RFC4648 { padding: false },
+            &provisioning.secret,
+        )
+        .unwrap();
+
+        assert_eq!(secret.len(), app.totp_secret_len);
+        assert!(provisioning.uri.starts_with("otpauth://totp/"));
+        assert!(provisioning
+            .uri
+            .contains(&format!("secret={}", provisioning.secret)));
+        assert!(provisioning.qr_code.contains("<svg"));
     }
 
     #[tokio::test]
@@ -949,7 +1542,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -961,7 +1554,7 @@ pub mod tests {
         let mut app = new_user_application(Some(&token_repo));
         app.secret_repo = Arc::new(secret_repo);
 
-        app.enable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "")
+        app.enable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "", "", None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -979,7 +1572,7 @@ pub mod tests {
         };
 
         let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -991,7 +1584,7 @@ pub mod tests {
         let mut app = new_user_application(Some(&token_repo));
         app.secret_repo = Arc::new(secret_repo);
 
-        app.enable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "")
+        app.enable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, "", "", None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -1020,11 +1613,24 @@ pub mod tests {
         app.secret_repo = Arc::new(secret_repo);
 
         let totp = app
-            .enable_totp(0, TEST_DEFAULT_USER_PASSWORD, "")
+            .enable_totp(0, TEST_DEFAULT_USER_PASSWORD, "", "")
             .await
             .unwrap();
-        assert!(totp.is_some());
-        assert_eq!(totp.unwrap().len(), app.totp_secret_len);
+        let TotpEnabling::Provisioned(provisioning) = totp else {
+            panic!("totp should have been provisioned");
+        };
+        let secret = base32::decode(
+            base32::Alphabet::RFC4648 { padding: false },
+            &provisioning.secret,
+        )
+        .unwrap();
+
+        assert_eq!(secret.len(), app.totp_secret_len);
+        assert!(provisioning.uri.starts_with("otpauth://totp/"));
+        assert!(provisioning
+            .uri
+            .contains(&format!("secret={}", provisioning.secret)));
+        assert!(provisioning.qr_code.contains("<svg"));
     }
 
     #[tokio::test]
@@ -1050,14 +1656,23 @@ pub mod tests {
         let mut app = new_user_application(None);
         app.secret_repo = Arc::new(secret_repo);
 
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
         let totp = app
-            .enable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code)
+            .enable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code, "")
             .await
             .unwrap();
-        assert_eq!(totp, None);
+
+        let TotpEnabling::Enabled(recovery_codes) = totp else {
+            panic!("totp should have been enabled");
+        };
+
+        assert_eq!(recovery_codes.len(), app.recovery_code_count);
+        assert!(recovery_codes
+            .iter()
+            .all(|code| code.len() == app.recovery_code_len));
     }
 
     #[tokio::test]
@@ -1076,10 +1691,11 @@ pub mod tests {
         let mut app = new_user_application(None);
         app.secret_repo = Arc::new(secret_repo);
 
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.enable_totp(0, "bad password", &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.enable_totp(0, "bad password", &code, "")
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::WrongCredentials.to_string()))
             .unwrap_err();
@@ -1088,15 +1704,140 @@ pub mod tests {
     #[tokio::test]
     async fn user_enable_totp_already_enabled_should_fail() {
         let app = new_user_application(None);
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.enable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.enable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code, "")
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::NotAvailable.to_string()))
             .unwrap_err();
     }
 
+    #[tokio::test]
+    async fn user_enable_totp_further_authenticator_should_not_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, name: &str| -> Result<Secret> {
+                    if name == new_authenticator().get_name() {
+                        return Err(Error::NotFound);
+                    }
+
+                    Ok(new_secret())
+                },
+            ),
+            fn_create: Some(
+                |_: &SecretRepositoryMock, secret: &mut Secret| -> Result<()> {
+                    assert_eq!(secret.get_name(), new_authenticator().get_name());
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        let totp = app
+            .enable_totp(
+                0,
+                TEST_DEFAULT_USER_PASSWORD,
+                &code,
+                TEST_AUTHENTICATOR_NAME,
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(totp, TotpEnabling::Provisioned(_)));
+    }
+
+    #[tokio::test]
+    async fn user_enable_totp_further_authenticator_wrong_totp_should_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, name: &str| -> Result<Secret> {
+                    if name == new_authenticator().get_name() {
+                        return Err(Error::NotFound);
+                    }
+
+                    Ok(new_secret())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        app.enable_totp(
+            0,
+            TEST_DEFAULT_USER_PASSWORD,
+            "bad totp",
+            TEST_AUTHENTICATOR_NAME,
+        )
+        .await
+        .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn user_enable_totp_verify_further_authenticator_should_not_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, name: &str| -> Result<Secret> {
+                    if name == new_authenticator().get_name() {
+                        let mut secret = new_authenticator();
+                        secret.set_deleted_at(Some(Utc::now().naive_utc()));
+                        return Ok(secret);
+                    }
+
+                    Ok(new_secret())
+                },
+            ),
+            fn_create: Some(|_: &SecretRepositoryMock, _: &mut Secret| -> Result<()> {
+                // recovery codes are shared by all the authenticators of the user
+                Err(Error::Unknown)
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        let code =
+            crypto::generate_totp(TEST_AUTHENTICATOR_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        let totp = app
+            .enable_totp(
+                0,
+                TEST_DEFAULT_USER_PASSWORD,
+                &code,
+                TEST_AUTHENTICATOR_NAME,
+            )
+            .await
+            .unwrap();
+
+        let TotpEnabling::Enabled(recovery_codes) = totp else {
+            panic!("totp should have been enabled");
+        };
+
+        assert!(recovery_codes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn user_enable_totp_wrong_authenticator_name_should_fail() {
+        let app = new_user_application(None);
+        app.enable_totp(0, TEST_DEFAULT_USER_PASSWORD, "", "bad::name")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidFormat.to_string()))
+            .unwrap_err();
+    }
+
     #[tokio::test]
     async fn user_secure_disable_totp_should_not_fail() {
         let token = Token::new(
@@ -1107,7 +1848,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -1117,10 +1858,11 @@ pub mod tests {
         };
 
         let app = new_user_application(Some(&token_repo));
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, &code, "", None)
             .await
             .unwrap();
     }
@@ -1135,7 +1877,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -1145,10 +1887,11 @@ pub mod tests {
         };
 
         let app = new_user_application(Some(&token_repo));
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, &code, "", None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -1157,7 +1900,7 @@ pub mod tests {
     #[tokio::test]
     async fn user_secure_disable_totp_reset_token_kind_should_fail() {
         let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -1167,10 +1910,11 @@ pub mod tests {
         };
 
         let app = new_user_application(Some(&token_repo));
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp_with_token(&secure_token, TEST_DEFAULT_USER_PASSWORD, &code, "", None)
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
             .unwrap_err();
@@ -1179,10 +1923,11 @@ pub mod tests {
     #[tokio::test]
     async fn user_disable_totp_should_not_fail() {
         let app = new_user_application(None);
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code, "")
             .await
             .unwrap();
     }
@@ -1190,10 +1935,11 @@ pub mod tests {
     #[tokio::test]
     async fn user_disable_totp_wrong_password_should_fail() {
         let app = new_user_application(None);
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp(0, "bad password", &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp(0, "bad password", &code, "")
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::WrongCredentials.to_string()))
             .unwrap_err();
@@ -1202,7 +1948,7 @@ pub mod tests {
     #[tokio::test]
     async fn user_disable_totp_wrong_totp_should_fail() {
         let app = new_user_application(None);
-        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, "bad totp")
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, "bad totp", "")
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
             .unwrap_err();
@@ -1222,10 +1968,11 @@ pub mod tests {
         let mut app = new_user_application(None);
         app.secret_repo = Arc::new(secret_repo);
 
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code, "")
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::NotAvailable.to_string()))
             .unwrap_err();
@@ -1247,10 +1994,186 @@ pub mod tests {
         let mut app = new_user_application(None);
         app.secret_repo = Arc::new(secret_repo);
 
-        let code = crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes())
-            .unwrap()
-            .generate();
-        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code)
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, &code, "")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotAvailable.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn user_disable_totp_recovery_code_should_not_fail() {
+        static CONSUMED: AtomicBool = AtomicBool::new(false);
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                Ok(vec![new_secret(), new_recovery_code()])
+            }),
+            fn_delete: Some(
+                |_: &SecretRepositoryMock, secret: &Secret| -> Result<bool> {
+                    if secret.get_name() == new_recovery_code().get_name() {
+                        CONSUMED.store(true, Ordering::Relaxed);
+                    }
+
+                    Ok(true)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, TEST_RECOVERY_CODE, "")
+            .await
+            .unwrap();
+        assert!(CONSUMED.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn user_disable_totp_consumed_recovery_code_should_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                Ok(vec![new_secret(), new_recovery_code()])
+            }),
+            fn_delete: Some(|_: &SecretRepositoryMock, _: &Secret| -> Result<bool> {
+                // the recovery code has been consumed by a concurrent request
+                Ok(false)
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, TEST_RECOVERY_CODE, "")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn user_disable_totp_wrong_recovery_code_should_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                Ok(vec![new_secret(), new_recovery_code()])
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        app.disable_totp(0, TEST_DEFAULT_USER_PASSWORD, "badrecoverycode", "")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn user_disable_totp_with_another_authenticator_should_not_fail() {
+        static REMOVED: AtomicBool = AtomicBool::new(false);
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, name: &str| -> Result<Secret> {
+                    if name == new_authenticator().get_name() {
+                        return Ok(new_authenticator());
+                    }
+
+                    Ok(new_secret())
+                },
+            ),
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                Ok(vec![new_authenticator(), new_recovery_code()])
+            }),
+            fn_delete: Some(
+                |_: &SecretRepositoryMock, secret: &Secret| -> Result<bool> {
+                    // the default authenticator is still active, so the recovery codes must be kept
+                    assert_eq!(secret.get_name(), new_authenticator().get_name());
+                    REMOVED.store(true, Ordering::Relaxed);
+                    Ok(true)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        // the totp comes from the default authenticator, as if the removed one had been lost
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+        app.disable_totp(
+            0,
+            TEST_DEFAULT_USER_PASSWORD,
+            &code,
+            TEST_AUTHENTICATOR_NAME,
+        )
+        .await
+        .unwrap();
+        assert!(REMOVED.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn user_list_authenticators_should_not_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user: Some(|_: &SecretRepositoryMock, _: i32| -> Result<Vec<Secret>> {
+                let mut authenticator = new_authenticator();
+                authenticator.set_deleted_at(Some(Utc::now().naive_utc()));
+                Ok(vec![authenticator, new_recovery_code()])
+            }),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        let authenticators = app.list_authenticators(0).await.unwrap();
+        assert_eq!(authenticators.len(), 2);
+        assert_eq!(authenticators[0].name, "default");
+        assert!(authenticators[0].active);
+        assert_eq!(authenticators[1].name, TEST_AUTHENTICATOR_NAME);
+        assert!(!authenticators[1].active);
+    }
+
+    #[tokio::test]
+    async fn user_regenerate_recovery_codes_should_not_fail() {
+        let app = new_user_application(None);
+        let code =
+            crypto::generate_totp(TEST_DEFAULT_SECRET_DATA.as_bytes(), &TotpPolicy::default())
+                .unwrap()
+                .generate();
+
+        let recovery_codes = app
+            .regenerate_recovery_codes(0, TEST_DEFAULT_USER_PASSWORD, &code)
+            .await
+            .unwrap();
+
+        assert_eq!(recovery_codes.len(), app.recovery_code_count);
+        assert_ne!(recovery_codes[0], recovery_codes[1]);
+    }
+
+    #[tokio::test]
+    async fn user_regenerate_recovery_codes_totp_not_enabled_should_fail() {
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, _: &str| -> Result<Secret> {
+                    let mut secret = new_secret();
+                    secret.set_deleted_at(Some(Utc::now().naive_utc()));
+                    Ok(secret)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+
+        app.regenerate_recovery_codes(0, TEST_DEFAULT_USER_PASSWORD, "")
             .await
             .map_err(|err| assert_eq!(err.to_string(), Error::NotAvailable.to_string()))
             .unwrap_err();
@@ -1268,7 +2191,7 @@ pub mod tests {
         };
 
         let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -1285,6 +2208,110 @@ pub mod tests {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn user_secure_reset_should_announce_revocation() {
+        static ANNOUNCED: AtomicBool = AtomicBool::new(false);
+        let secret_repo = SecretRepositoryMock {
+            fn_find_by_user_and_name: Some(
+                |_: &SecretRepositoryMock, _: i32, _: &str| -> Result<Secret> {
+                    Err(Error::NotFound)
+                },
+            ),
+            ..Default::default()
+        };
+
+        let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
+        let secure_token = KEYRING.sign(token).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: secure_token.clone(),
+            ..Default::default()
+        };
+
+        let mut token_app = new_token_application(Some(token_repo));
+        token_app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, token: &Token| -> Result<()> {
+                assert_eq!(token.knd, TokenKind::Reset);
+                assert_eq!(token.sub, "0");
+                ANNOUNCED.store(true, Ordering::Relaxed);
+                Ok(())
+            }),
+        });
+
+        let mut app = new_user_application(None);
+        app.secret_repo = Arc::new(secret_repo);
+        app.token_app = Arc::new(token_app);
+
+        app.reset_with_token(&secure_token, "ABCDEF1234567891", "")
+            .await
+            .unwrap();
+        assert!(ANNOUNCED.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn user_secure_reset_consumed_token_should_fail() {
+        let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
+        let secure_token = KEYRING.sign(token).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: secure_token.clone(),
+            fn_consume: Some(|_: &TokenRepositoryMock, _: &str| -> Result<String> {
+                Err(Error::NotFound) // another call has already consumed the token
+            }),
+            ..Default::default()
+        };
+
+        let app = new_user_application(Some(&token_repo));
+        app.reset_with_token(&secure_token, "ABCDEF1234567891", "")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::InvalidToken.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn user_secure_reset_failure_should_restore_token() {
+        static RESTORED: AtomicBool = AtomicBool::new(false);
+        static ANNOUNCED: AtomicBool = AtomicBool::new(false);
+
+        let token = Token::new("test", "0", Duration::from_secs(60), TokenKind::Reset, None);
+        let secure_token = KEYRING.sign(token).unwrap();
+        let token_repo = TokenRepositoryMock {
+            token: secure_token.clone(),
+            fn_save: Some(
+                |this: &TokenRepositoryMock,
+                 key: &str,
+                 token: &str,
+                 expire: Option<u64>|
+                 -> Result<()> {
+                    assert!(key.starts_with("Reset::"));
+                    assert_eq!(token, this.token);
+                    assert!(expire.unwrap() <= 60);
+                    RESTORED.store(true, Ordering::SeqCst);
+                    Ok(())
+                },
+            ),
+            ..Default::default()
+        };
+
+        let mut token_app = new_token_application(Some(token_repo));
+        token_app.event_bus = Arc::new(TokenEventBusMock {
+            fn_emit_token_revoked: Some(|_: &TokenEventBusMock, _: &Token| -> Result<()> {
+                ANNOUNCED.store(true, Ordering::SeqCst);
+                Ok(())
+            }),
+        });
+
+        // the default secret repository requires a totp to be provided
+        let mut app = new_user_application(None);
+        app.token_app = Arc::new(token_app);
+        app.reset_with_token(&secure_token, "ABCDEF1234567891", "")
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unauthorized.to_string()))
+            .unwrap_err();
+
+        // the token is still valid, so no verifier must be told otherwise
+        assert!(RESTORED.load(Ordering::SeqCst));
+        assert!(!ANNOUNCED.load(Ordering::SeqCst));
+    }
+
     #[tokio::test]
     async fn user_secure_reset_verification_token_kind_should_fail() {
         let secret_repo = SecretRepositoryMock {
@@ -1304,7 +2331,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -1341,7 +2368,7 @@ pub mod tests {
             None,
         );
 
-        let secure_token = crypto::sign_jwt(&PRIVATE_KEY, token).unwrap();
+        let secure_token = KEYRING.sign(token).unwrap();
         let token_repo = TokenRepositoryMock {
             token: secure_token.clone(),
             fn_find: Some(|this: &TokenRepositoryMock, _: &str| -> Result<String> {
@@ -1,9 +1,11 @@
 use crate::metadata::domain::Metadata;
 use crate::{
-    email, regex,
+    crypto, email, qr, regex,
     result::{Error, Result},
 };
 
+pub use crate::crypto::TotpPolicy;
+
 /// Represents a user and all its personal data
 #[derive(Debug)]
 pub struct User {
@@ -66,6 +68,45 @@ impl User {
     }
 }
 
+/// Everything an authenticator app requires to get provisioned with the TOTP of a user
+#[derive(Debug, Serialize)]
+pub struct TotpProvisioning {
+    /// The secret of the TOTP, encoded in base32
+    pub secret: String,
+    /// The otpauth uri carrying the secret along with the issuer, account, algorithm, digits and period
+    pub uri: String,
+    /// The SVG image of the QR code encoding the uri
+    pub qr_code: String,
+}
+
+impl TotpProvisioning {
+    pub fn new(secret: &[u8], policy: &TotpPolicy, issuer: &str, account: &str) -> Result<Self> {
+        let uri = crypto::totp_uri(secret, policy, issuer, account)?;
+        Ok(TotpProvisioning {
+            secret: crypto::encode_base32(secret),
+            qr_code: qr::svg(&uri)?,
+            uri,
+        })
+    }
+}
+
+/// The outcome of each of the two steps enabling the TOTP of a user takes
+#[derive(Debug)]
+pub enum TotpEnabling {
+    /// The TOTP has been generated and is waiting to be confirmed
+    Provisioned(TotpProvisioning),
+    /// The TOTP has been confirmed, and these are the single use recovery codes issued for it
+    Enabled(Vec<String>),
+}
+
+/// Each of the devices a user has enrolled to generate its TOTPs
+#[derive(Debug, Serialize)]
+pub struct Authenticator {
+    pub name: String,
+    /// Whether the authenticator has been confirmed, so its TOTPs are accepted
+    pub active: bool,
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::User;
@@ -1,8 +1,9 @@
 use super::application::Mailer;
 use crate::base64::B64_CUSTOM_ENGINE;
 use crate::secret::application::SecretRepository;
-use crate::token::application::TokenRepository;
+use crate::token::application::{TokenEventBus, TokenRepository};
 use crate::user::application::{EventBus, UserApplication, UserRepository};
+use crate::user::domain::TotpEnabling;
 use crate::{grpc, result::Error};
 use base64::Engine;
 use tonic::metadata::errors::InvalidMetadataValue;
@@ -11,6 +12,12 @@ use tonic::{Request, Response, Status};
 const TOTP_ACTION_ENABLE: i32 = 0;
 const TOTP_ACTION_DISABLE: i32 = 1;
 
+// The path DPoP proofs are issued for when calling the service's methods
+const DELETE_PATH: &str = "/user.User/Delete";
+const TOTP_PATH: &str = "/user.User/Totp";
+const RECOVERY_CODES_PATH: &str = "/user.User/RecoveryCodes";
+const AUTHENTICATORS_PATH: &str = "/user.User/Authenticators";
+
 // Import the generated rust code into module
 mod proto {
     tonic::include_proto!("user");
@@ -21,7 +28,10 @@ use proto::user_server::User;
 pub use proto::user_server::UserServer;
 
 // Proto message structs
-use proto::{DeleteRequest, Empty, ResetRequest, SignupRequest, TotpRequest};
+use proto::{
+    Authenticator, AuthenticatorsResponse, DeleteRequest, Empty, RecoveryCodesRequest,
+    RecoveryCodesResponse, ResetRequest, SignupRequest, TotpRequest, TotpResponse,
+};
 
 pub struct UserGrpcService<
     U: UserRepository + Sync + Send,
@@ -29,10 +39,12 @@ pub struct UserGrpcService<
     S: TokenRepository + Sync + Send,
     B: EventBus + Sync + Send,
     M: Mailer,
+    N: TokenEventBus + Sync + Send,
 > {
-    pub user_app: UserApplication<'static, U, E, S, B, M>,
+    pub user_app: UserApplication<'static, U, E, S, B, M, N>,
     pub jwt_header: &'static str,
     pub totp_header: &'static str,
+    pub dpop_header: &'static str,
 }
 
 #[tonic::async_trait]
@@ -42,7 +54,8 @@ impl<
         S: 'static + TokenRepository + Sync + Send,
         B: 'static + EventBus + Sync + Send,
         M: 'static + Mailer + Sync + Send,
-    > User for UserGrpcService<U, E, S, B, M>
+        N: 'static + TokenEventBus + Sync + Send,
+    > User for UserGrpcService<U, E, S, B, M, N>
 {
     #[instrument(skip(self))]
     async fn signup(&self, request: Request<SignupRequest>) -> Result<Response<Empty>, Status> {
@@ -98,46 +111,132 @@ impl<
     #[instrument(skip(self))]
     async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<Empty>, Status> {
         let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = grpc::get_dpop_request(&request, self.dpop_header, DELETE_PATH, Some(&token))?;
         let msg_ref = request.into_inner();
         self.user_app
-            .delete_with_token(&token, &msg_ref.pwd, &msg_ref.totp)
+            .delete_with_token(&token, &msg_ref.pwd, &msg_ref.totp, dpop.as_ref())
             .await
             .map(|_| Response::new(Empty {}))
             .map_err(|err| Status::aborted(err.to_string()))
     }
 
     #[instrument(skip(self))]
-    async fn totp(&self, request: Request<TotpRequest>) -> Result<Response<Empty>, Status> {
+    async fn totp(&self, request: Request<TotpRequest>) -> Result<Response<TotpResponse>, Status> {
         let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = grpc::get_dpop_request(&request, self.dpop_header, TOTP_PATH, Some(&token))?;
         let msg_ref = request.into_inner();
 
         if msg_ref.action == TOTP_ACTION_DISABLE {
             return self
                 .user_app
-                .disable_totp_with_token(&token, &msg_ref.pwd, &msg_ref.totp)
+                .disable_totp_with_token(
+                    &token,
+                    &msg_ref.pwd,
+                    &msg_ref.totp,
+                    &msg_ref.name,
+                    dpop.as_ref(),
+                )
                 .await
-                .map(|_| Response::new(Empty {}))
+                .map(|_| Response::new(TotpResponse::default()))
                 .map_err(|err| Status::unknown(err.to_string()));
         }
 
         if msg_ref.action == TOTP_ACTION_ENABLE {
-            let token = self
+            let enabling = self
                 .user_app
-                .enable_totp_with_token(&token, &msg_ref.pwd, &msg_ref.totp)
+                .enable_totp_with_token(
+                    &token,
+                    &msg_ref.pwd,
+                    &msg_ref.totp,
+                    &msg_ref.name,
+                    dpop.as_ref(),
+                )
                 .await
                 .map_err(|err| Status::aborted(err.to_string()))?;
 
-            let mut response = Response::new(Empty {});
-            if let Some(token) = token {
-                let token = token.parse().map_err(|err: InvalidMetadataValue| {
+            let provisioning = match enabling {
+                TotpEnabling::Provisioned(provisioning) => provisioning,
+                TotpEnabling::Enabled(recovery_codes) => {
+                    return Ok(Response::new(TotpResponse {
+                        recovery_codes,
+                        ..Default::default()
+                    }))
+                }
+            };
+
+            let secret = provisioning
+                .secret
+                .parse()
+                .map_err(|err: InvalidMetadataValue| {
                     error!(error = err.to_string(), "parsing str to metadata",);
                     Status::aborted(Error::Unknown.to_string())
                 })?;
 
-                response.metadata_mut().insert(self.totp_header, token);
-            }
+            let mut response = Response::new(TotpResponse {
+                secret: provisioning.secret,
+                uri: provisioning.uri,
+                qr_code: provisioning.qr_code,
+                ..Default::default()
+            });
+
+            response.metadata_mut().insert(self.totp_header, secret);
+            return Ok(response);
         }
 
         Err(Error::NotAvailable.into())
     }
+
+    #[instrument(skip(self))]
+    async fn recovery_codes(
+        &self,
+        request: Request<RecoveryCodesRequest>,
+    ) -> Result<Response<RecoveryCodesResponse>, Status> {
+        let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = grpc::get_dpop_request(
+            &request,
+            self.dpop_header,
+            RECOVERY_CODES_PATH,
+            Some(&token),
+        )?;
+
+        let msg_ref = request.into_inner();
+        self.user_app
+            .regenerate_recovery_codes_with_token(
+                &token,
+                &msg_ref.pwd,
+                &msg_ref.totp,
+                dpop.as_ref(),
+            )
+            .await
+            .map(|recovery_codes| Response::new(RecoveryCodesResponse { recovery_codes }))
+            .map_err(|err| Status::aborted(err.to_string()))
+    }
+
+    #[instrument(skip(self))]
+    async fn authenticators(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<AuthenticatorsResponse>, Status> {
+        let token = grpc::get_encoded_header(&request, self.jwt_header)?;
+        let dpop = grpc::get_dpop_request(
+            &request,
+            self.dpop_header,
+            AUTHENTICATORS_PATH,
+            Some(&token),
+        )?;
+
+        let authenticators = self
+            .user_app
+            .list_authenticators_with_token(&token, dpop.as_ref())
+            .await
+            .map_err(|err| Status::aborted(err.to_string()))?
+            .into_iter()
+            .map(|authenticator| Authenticator {
+                name: authenticator.name,
+                active: authenticator.active,
+            })
+            .collect();
+
+        Ok(Response::new(AuthenticatorsResponse { authenticators }))
+    }
 }
@@ -0,0 +1,243 @@
+use super::application::{EventBus, UserRepository};
+use super::domain::User;
+use crate::metadata::application::MetadataRepository;
+use crate::result::{Error, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+type InMemoryUserRow = (i32, String, String, String, String, i32); // id, name, email, actual_email, password, meta_id
+
+/// A [`UserRepository`] holding all the users in the process' memory, so they get lost on restart.
+pub struct InMemoryUserRepository<M: MetadataRepository> {
+    pub metadata_repo: Arc<M>,
+    rows: RwLock<HashMap<i32, InMemoryUserRow>>,
+    last_id: RwLock<i32>,
+}
+
+impl<M: MetadataRepository> InMemoryUserRepository<M> {
+    pub fn new(metadata_repo: Arc<M>) -> Self {
+        InMemoryUserRepository {
+            metadata_repo,
+            rows: Default::default(),
+            last_id: Default::default(),
+        }
+    }
+
+    async fn build(&self, row: InMemoryUserRow) -> Result<User> {
+        let meta = self.metadata_repo.find(row.5).await?;
+
+        Ok(User {
+            id: row.0,
+            name: row.1,
+            email: row.2,
+            actual_email: row.3,
+            password: row.4,
+            meta,
+        })
+    }
+
+    fn find_row<P: Fn(&InMemoryUserRow) -> bool>(&self, predicate: P) -> Result<InMemoryUserRow> {
+        let rows = self.rows.read().map_err(|err| {
+            error!(error = err.to_string(), "locking users for reading");
+            Error::Unknown
+        })?;
+
+        rows.values()
+            .find(|row| predicate(row))
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    /// Writes the given row as long as it does not violate any unique constraint.
+    fn write_row(&self, row: InMemoryUserRow) -> Result<()> {
+        let mut rows = self.rows.write().map_err(|err| {
+            error!(error = err.to_string(), "locking users for writing");
+            Error::Unknown
+        })?;
+
+        let conflict = rows.values().any(|other| {
+            other.0 != row.0
+                && (other.1 == row.1 || other.2 == row.2 || other.3 == row.3 || other.5 == row.5)
+        });
+
+        if conflict {
+            error!(id = row.0, "violating users unique constraint");
+            return Err(Error::Unknown);
+        }
+
+        rows.insert(row.0, row);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<M: MetadataRepository + Sync + Send> UserRepository for InMemoryUserRepository<M> {
+    #[instrument(skip(self))]
+    async fn find(&self, target: i32) -> Result<User> {
+        let row = self.find_row(|row| row.0 == target)?;
+        self.build(row).await
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_email(&self, target: &str) -> Result<User> {
+        let row = self.find_row(|row| row.2 == target || row.3 == target)?;
+        self.build(row).await
+    }
+
+    #[instrument(skip(self))]
+    async fn find_by_name(&self, target: &str) -> Result<User> {
+        let row = self.find_row(|row| row.1 == target)?;
+        self.build(row).await
+    }
+
+    #[instrument(skip(self))]
+    async fn create(&self, user: &mut User) -> Result<()> {
+        self.metadata_repo.create(&mut user.meta).await?;
+
+        let id = {
+            // block is required because of lock release
+            let mut last_id = self.last_id.write().map_err(|err| {
+                error!(error = err.to_string(), "locking users sequence");
+                Error::Unknown
+            })?;
+
+            *last_id += 1;
+            *last_id
+        };
+
+        self.write_row((
+            id,
+            user.name.clone(),
+            user.email.clone(),
+            user.actual_email.clone(),
+            user.password.clone(),
+            user.meta.get_id(),
+        ))?;
+
+        user.id = id;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn save(&self, user: &User) -> Result<()> {
+        if self.find_row(|row| row.0 == user.id).is_err() {
+            return Ok(()); // as an update, does nothing if the user does not exist
+        }
+
+        self.write_row((
+            user.id,
+            user.name.clone(),
+            user.email.clone(),
+            user.actual_email.clone(),
+            user.password.clone(),
+            user.meta.get_id(),
+        ))
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, user: &User) -> Result<()> {
+        {
+            // block is required because of lock release
+            let mut rows = self.rows.write().map_err(|err| {
+                error!(error = err.to_string(), "locking users for writing");
+                Error::Unknown
+            })?;
+
+            rows.remove(&user.id);
+        }
+
+        self.metadata_repo.delete(&user.meta).await
+    }
+}
+
+/// An [`EventBus`] that, instead of publishing the events, keeps track of them in the process' memory.
+#[derive(Default)]
+pub struct InMemoryEventBus {
+    created: RwLock<Vec<i32>>,
+}
+
+impl InMemoryEventBus {
+    /// Returns the id of all those users whose creation has been emitted, in order.
+    pub fn created_users(&self) -> Vec<i32> {
+        self.created
+            .read()
+            .map(|created| created.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl EventBus for InMemoryEventBus {
+    #[instrument(skip(self))]
+    async fn emit_user_created(&self, user: &User) -> Result<()> {
+        let mut created = self.created.write().map_err(|err| {
+            error!(error = err.to_string(), "locking user events for writing");
+            Error::Unknown
+        })?;
+
+        created.push(user.get_id());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{InMemoryEventBus, InMemoryUserRepository};
+    use crate::metadata::memory::InMemoryMetadataRepository;
+    use crate::result::Error;
+    use crate::user::application::{EventBus, UserRepository};
+    use crate::user::domain::tests::{TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD};
+    use crate::user::domain::User;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn in_memory_user_lifecycle_should_not_fail() {
+        let repo = InMemoryUserRepository::new(Arc::new(InMemoryMetadataRepository::default()));
+        let mut user = User::new(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD).unwrap();
+        repo.create(&mut user).await.unwrap();
+        assert_eq!(user.get_id(), 1);
+
+        let found = repo.find_by_email(TEST_DEFAULT_USER_EMAIL).await.unwrap();
+        assert_eq!(found.get_id(), user.get_id());
+        let found = repo.find_by_name(user.get_name()).await.unwrap();
+        assert_eq!(found.get_id(), user.get_id());
+
+        user.set_password("0123456789ABCDEF").unwrap();
+        repo.save(&user).await.unwrap();
+        assert!(repo
+            .find(user.get_id())
+            .await
+            .unwrap()
+            .match_password("0123456789ABCDEF"));
+
+        repo.delete(&user).await.unwrap();
+        repo.find(user.get_id())
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::NotFound.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_user_duplicated_email_should_fail() {
+        let repo = InMemoryUserRepository::new(Arc::new(InMemoryMetadataRepository::default()));
+        let mut user = User::new(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD).unwrap();
+        repo.create(&mut user).await.unwrap();
+
+        let mut user = User::new(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD).unwrap();
+        repo.create(&mut user)
+            .await
+            .map_err(|err| assert_eq!(err.to_string(), Error::Unknown.to_string()))
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn in_memory_event_bus_should_not_fail() {
+        let bus = InMemoryEventBus::default();
+        let mut user = User::new(TEST_DEFAULT_USER_EMAIL, TEST_DEFAULT_USER_PASSWORD).unwrap();
+        user.id = 7;
+
+        bus.emit_user_created(&user).await.unwrap();
+        assert_eq!(bus.created_users(), vec![7]);
+    }
+}
@@ -4,5 +4,9 @@ pub mod domain;
 pub mod event_bus;
 #[cfg(feature = "grpc")]
 pub mod grpc;
+#[cfg(feature = "in-memory")]
+pub mod memory;
 #[cfg(feature = "postgres")]
 pub mod repository;
+#[cfg(feature = "rest")]
+pub mod rest;
@@ -0,0 +1,271 @@
+use super::application::{EventBus, Mailer, UserApplication, UserRepository};
+use super::domain::{Authenticator, TotpEnabling};
+use crate::base64::B64_CUSTOM_ENGINE;
+use crate::http;
+use crate::secret::application::SecretRepository;
+use crate::token::application::{TokenEventBus, TokenRepository};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct SignupBody {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    pwd: String,
+}
+
+#[derive(Deserialize)]
+struct ResetBody {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    pwd: String,
+    #[serde(default)]
+    totp: String,
+}
+
+/// The credentials a logged in user must provide, on top of its session token, to perform any sensitive
+/// operation.
+#[derive(Deserialize)]
+struct CredentialsBody {
+    pwd: String,
+    #[serde(default)]
+    totp: String,
+}
+
+/// The credentials required to enable or disable the totp, along with the name of the authenticator to act
+/// on, being the default one if none is given.
+#[derive(Deserialize)]
+struct TotpBody {
+    pwd: String,
+    #[serde(default)]
+    totp: String,
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct AuthenticatorsResponse {
+    authenticators: Vec<Authenticator>,
+}
+
+pub struct UserRestService<
+    U: UserRepository + Sync + Send,
+    E: SecretRepository + Sync + Send,
+    S: TokenRepository + Sync + Send,
+    B: EventBus + Sync + Send,
+    M: Mailer,
+    N: TokenEventBus + Sync + Send,
+> {
+    pub user_app: UserApplication<'static, U, E, S, B, M, N>,
+    pub jwt_header: &'static str,
+    pub totp_header: &'static str,
+    pub dpop_header: &'static str,
+}
+
+impl<
+        U: 'static + UserRepository + Sync + Send,
+        E: 'static + SecretRepository + Sync + Send,
+        S: 'static + TokenRepository + Sync + Send,
+        B: 'static + EventBus + Sync + Send,
+        M: 'static + Mailer + Sync + Send,
+        N: 'static + TokenEventBus + Sync + Send,
+    > UserRestService<U, E, S, B, M, N>
+{
+    pub fn router(&self) -> impl Fn(&mut web::ServiceConfig) {
+        |cfg: &mut web::ServiceConfig| {
+            cfg.service(web::resource("/user").route(web::post().to(Self::signup)));
+            cfg.service(web::resource("/user").route(web::delete().to(Self::delete)));
+            cfg.service(web::resource("/user/reset").route(web::post().to(Self::reset)));
+            cfg.service(
+                web::resource("/user/totp").route(web::get().to(Self::list_authenticators)),
+            );
+            cfg.service(web::resource("/user/totp").route(web::post().to(Self::enable_totp)));
+            cfg.service(web::resource("/user/totp").route(web::delete().to(Self::disable_totp)));
+            cfg.service(
+                web::resource("/user/totp/recovery-codes")
+                    .route(web::post().to(Self::regenerate_recovery_codes)),
+            );
+        }
+    }
+
+    /// Sends the verification email if no token is provided, otherwise completes the signup.
+    #[instrument(skip(app_data, body))]
+    async fn signup(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+        body: web::Json<SignupBody>,
+    ) -> impl Responder {
+        if req.headers().contains_key(app_data.jwt_header) {
+            return match async {
+                let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+                app_data.user_app.signup_with_token(&token).await
+            }
+            .await
+            {
+                Ok(token) => HttpResponse::Created()
+                    .insert_header((app_data.jwt_header, B64_CUSTOM_ENGINE.encode(token)))
+                    .finish(),
+                Err(err) => HttpResponse::from(err),
+            };
+        }
+
+        match app_data
+            .user_app
+            .verify_signup_email(&body.email, &body.pwd)
+            .await
+        {
+            Ok(_) => HttpResponse::Accepted().finish(),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    /// Sends the reset email if no token is provided, otherwise sets the new password.
+    #[instrument(skip(app_data, body))]
+    async fn reset(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+        body: web::Json<ResetBody>,
+    ) -> impl Responder {
+        if req.headers().contains_key(app_data.jwt_header) {
+            return match async {
+                let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+                app_data
+                    .user_app
+                    .reset_with_token(&token, &body.pwd, &body.totp)
+                    .await
+            }
+            .await
+            {
+                Ok(_) => HttpResponse::Ok().finish(),
+                Err(err) => HttpResponse::from(err),
+            };
+        }
+
+        match app_data.user_app.verify_reset_email(&body.email).await {
+            Ok(_) => HttpResponse::Accepted().finish(),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data, body))]
+    async fn delete(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+        body: web::Json<CredentialsBody>,
+    ) -> impl Responder {
+        match async {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .user_app
+                .delete_with_token(&token, &body.pwd, &body.totp, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(_) => HttpResponse::Ok().finish(),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data))]
+    async fn list_authenticators(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        match async {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .user_app
+                .list_authenticators_with_token(&token, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(authenticators) => {
+                HttpResponse::Ok().json(AuthenticatorsResponse { authenticators })
+            }
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    /// Generates the totp secret of the given authenticator, sent back in the totp header along with its
+    /// provisioning uri and QR code in the body, if the user has none by that name yet. Otherwise, enables it
+    /// as long as the given totp is valid and sends back the recovery codes issued for it, if any.
+    #[instrument(skip(app_data, body))]
+    async fn enable_totp(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+        body: web::Json<TotpBody>,
+    ) -> impl Responder {
+        match async {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .user_app
+                .enable_totp_with_token(&token, &body.pwd, &body.totp, &body.name, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(TotpEnabling::Provisioned(provisioning)) => HttpResponse::Accepted()
+                .insert_header((app_data.totp_header, provisioning.secret.clone()))
+                .json(provisioning),
+            Ok(TotpEnabling::Enabled(recovery_codes)) => {
+                HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes })
+            }
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data, body))]
+    async fn disable_totp(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+        body: web::Json<TotpBody>,
+    ) -> impl Responder {
+        match async {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .user_app
+                .disable_totp_with_token(&token, &body.pwd, &body.totp, &body.name, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(_) => HttpResponse::Ok().finish(),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+
+    #[instrument(skip(app_data, body))]
+    async fn regenerate_recovery_codes(
+        app_data: web::Data<Arc<Self>>,
+        req: HttpRequest,
+        body: web::Json<CredentialsBody>,
+    ) -> impl Responder {
+        match async {
+            let token = http::get_encoded_header(req.clone(), app_data.jwt_header)?;
+            let dpop = http::get_dpop_request(&req, app_data.dpop_header, Some(&token))?;
+            app_data
+                .user_app
+                .regenerate_recovery_codes_with_token(&token, &body.pwd, &body.totp, dpop.as_ref())
+                .await
+        }
+        .await
+        {
+            Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }),
+            Err(err) => HttpResponse::from(err),
+        }
+    }
+}